@@ -0,0 +1,7 @@
+ALTER TABLE orders DROP COLUMN exchange_rate;
+ALTER TABLE orders DROP COLUMN currency;
+
+DROP TABLE product_prices;
+DROP TABLE exchange_rates;
+
+ALTER TABLE products DROP COLUMN currency;
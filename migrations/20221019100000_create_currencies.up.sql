@@ -0,0 +1,22 @@
+ALTER TABLE products ADD COLUMN currency TEXT NOT NULL DEFAULT 'EUR';
+
+CREATE TABLE exchange_rates (
+  currency TEXT PRIMARY KEY,
+  rate DOUBLE PRECISION NOT NULL CHECK (rate > 0),
+  updated_at TIMESTAMP NOT NULL DEFAULT NOW(),
+  created_at TIMESTAMP NOT NULL DEFAULT NOW()
+);
+
+INSERT INTO exchange_rates (currency, rate) VALUES ('EUR', 1.0);
+
+CREATE TABLE product_prices (
+  product_id Integer NOT NULL REFERENCES products (id) ON DELETE CASCADE,
+  currency TEXT NOT NULL,
+  amount bigint NOT NULL,
+  updated_at TIMESTAMP NOT NULL DEFAULT NOW(),
+  created_at TIMESTAMP NOT NULL DEFAULT NOW(),
+  PRIMARY KEY (product_id, currency)
+);
+
+ALTER TABLE orders ADD COLUMN currency TEXT NOT NULL DEFAULT 'EUR';
+ALTER TABLE orders ADD COLUMN exchange_rate DOUBLE PRECISION NOT NULL DEFAULT 1.0;
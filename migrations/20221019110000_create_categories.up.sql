@@ -0,0 +1,20 @@
+CREATE TABLE categories (
+  id SERIAL PRIMARY KEY,
+  parent_id Integer REFERENCES categories (id) ON DELETE CASCADE,
+  name TEXT NOT NULL,
+  slug TEXT NOT NULL UNIQUE,
+  position Integer NOT NULL DEFAULT 0,
+  updated_at TIMESTAMP NOT NULL DEFAULT NOW(),
+  created_at TIMESTAMP NOT NULL DEFAULT NOW(),
+  CHECK (parent_id <> id)
+);
+
+CREATE INDEX categories_parent_id_idx ON categories (parent_id, position);
+
+CREATE TABLE product_categories (
+  product_id Integer NOT NULL REFERENCES products (id) ON DELETE CASCADE,
+  category_id Integer NOT NULL REFERENCES categories (id) ON DELETE CASCADE,
+  PRIMARY KEY (product_id, category_id)
+);
+
+CREATE INDEX product_categories_category_id_idx ON product_categories (category_id);
@@ -0,0 +1,4 @@
+DROP TABLE attribute_definitions;
+
+DROP INDEX products_attributes_idx;
+ALTER TABLE products DROP COLUMN attributes;
@@ -0,0 +1,18 @@
+ALTER TABLE products ADD COLUMN attributes JSONB NOT NULL DEFAULT '{}';
+
+CREATE INDEX products_attributes_idx ON products USING GIN (attributes jsonb_path_ops);
+
+CREATE TABLE attribute_definitions (
+  id SERIAL PRIMARY KEY,
+  category_id Integer NOT NULL REFERENCES categories (id) ON DELETE CASCADE,
+  key TEXT NOT NULL,
+  kind TEXT NOT NULL CHECK (kind IN ('string', 'number', 'enum', 'boolean')),
+  required BOOLEAN NOT NULL DEFAULT FALSE,
+  unit TEXT,
+  options TEXT[] NOT NULL DEFAULT '{}',
+  updated_at TIMESTAMP NOT NULL DEFAULT NOW(),
+  created_at TIMESTAMP NOT NULL DEFAULT NOW(),
+  UNIQUE (category_id, key)
+);
+
+CREATE INDEX attribute_definitions_key_idx ON attribute_definitions (key);
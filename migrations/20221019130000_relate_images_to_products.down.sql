@@ -0,0 +1,15 @@
+-- rebuild the comma separated list, main picture first; image rows are kept
+ALTER TABLE products ADD COLUMN images TEXT NOT NULL DEFAULT '';
+UPDATE products p
+   SET images = COALESCE((SELECT string_agg(i.path, ',' ORDER BY i.is_primary DESC, i.position, i.id)
+                            FROM images i WHERE i.product_id = p.id), '');
+ALTER TABLE products ALTER COLUMN images DROP DEFAULT;
+
+DROP INDEX images_primary_idx;
+DROP INDEX images_product_id_idx;
+
+ALTER TABLE images DROP COLUMN is_primary;
+ALTER TABLE images DROP COLUMN position;
+ALTER TABLE images DROP CONSTRAINT images_product_id_fkey;
+ALTER TABLE images ALTER COLUMN product_id DROP NOT NULL;
+ALTER TABLE images RENAME COLUMN product_id TO productid;
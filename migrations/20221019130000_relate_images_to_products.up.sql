@@ -0,0 +1,19 @@
+DELETE FROM images WHERE productid IS NULL OR productid NOT IN (SELECT id FROM products);
+
+ALTER TABLE images RENAME COLUMN productid TO product_id;
+ALTER TABLE images ALTER COLUMN product_id SET NOT NULL;
+ALTER TABLE images
+  ADD CONSTRAINT images_product_id_fkey FOREIGN KEY (product_id) REFERENCES products (id) ON DELETE CASCADE;
+ALTER TABLE images ADD COLUMN position Integer NOT NULL DEFAULT 0;
+ALTER TABLE images ADD COLUMN is_primary BOOLEAN NOT NULL DEFAULT FALSE;
+
+CREATE INDEX images_product_id_idx ON images (product_id, position);
+CREATE UNIQUE INDEX images_primary_idx ON images (product_id) WHERE is_primary;
+
+-- products.images held a comma separated list of paths, the first one being the main picture
+INSERT INTO images (name, path, product_id, position, is_primary)
+  SELECT regexp_replace(trim(legacy.path), '^.*/', ''), trim(legacy.path), p.id, legacy.ord - 1, legacy.ord = 1
+    FROM products p, unnest(string_to_array(p.images, ',')) WITH ORDINALITY AS legacy (path, ord)
+   WHERE trim(legacy.path) <> '';
+
+ALTER TABLE products DROP COLUMN images;
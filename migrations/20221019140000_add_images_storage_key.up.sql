@@ -0,0 +1,2 @@
+ALTER TABLE images ADD COLUMN storage_key TEXT;
+ALTER TABLE images ADD COLUMN content_type TEXT;
@@ -0,0 +1,2 @@
+DROP VIEW image_listing;
+DROP TABLE image_variants;
@@ -0,0 +1,20 @@
+CREATE TABLE image_variants (
+  id SERIAL PRIMARY KEY,
+  image_id Integer NOT NULL REFERENCES images (id) ON DELETE CASCADE,
+  name TEXT NOT NULL,
+  format TEXT NOT NULL,
+  width Integer NOT NULL,
+  height Integer NOT NULL,
+  path TEXT NOT NULL,
+  storage_key TEXT NOT NULL,
+  content_type TEXT NOT NULL,
+  updated_at TIMESTAMP NOT NULL DEFAULT NOW(),
+  created_at TIMESTAMP NOT NULL DEFAULT NOW(),
+  UNIQUE (image_id, name, format)
+);
+
+-- images together with their derivatives, used to embed image lists as JSON
+CREATE VIEW image_listing AS
+  SELECT i.*,
+         COALESCE((SELECT json_agg(v ORDER BY v.width, v.format) FROM image_variants v WHERE v.image_id = i.id), '[]'::json) AS variants
+    FROM images i;
@@ -0,0 +1,3 @@
+DROP TABLE search_outbox;
+
+ALTER TABLE users DROP COLUMN is_admin;
@@ -0,0 +1,17 @@
+ALTER TABLE users ADD COLUMN is_admin BOOLEAN NOT NULL DEFAULT FALSE;
+
+-- pending changes to the search index, written in the same transaction as the product
+CREATE TABLE search_outbox (
+  id BIGSERIAL NOT NULL PRIMARY KEY,
+  product_id INTEGER NOT NULL,
+  operation TEXT NOT NULL CHECK (operation IN ('upsert', 'delete')),
+  attempts INTEGER NOT NULL DEFAULT 0,
+  last_error TEXT,
+  available_at TIMESTAMP NOT NULL DEFAULT NOW(),
+  created_at TIMESTAMP NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX search_outbox_available_at_idx ON search_outbox (available_at);
+
+-- index everything that existed before the outbox
+INSERT INTO search_outbox (product_id, operation) SELECT id, 'upsert' FROM products;
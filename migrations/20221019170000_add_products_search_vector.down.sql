@@ -0,0 +1,6 @@
+DROP VIEW product_documents;
+
+DROP INDEX products_name_trgm_idx;
+ALTER TABLE products DROP COLUMN search_vector;
+
+-- pg_trgm stays installed, other database objects may use it
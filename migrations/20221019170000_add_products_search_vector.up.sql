@@ -0,0 +1,32 @@
+CREATE EXTENSION IF NOT EXISTS pg_trgm;
+
+-- used by the postgres search backend
+ALTER TABLE products ADD COLUMN search_vector TSVECTOR GENERATED ALWAYS AS (
+  setweight(to_tsvector('simple', name), 'A') ||
+  setweight(to_tsvector('simple', cultivar), 'B') ||
+  setweight(to_tsvector('simple', origin), 'C')
+) STORED;
+
+CREATE INDEX products_search_vector_idx ON products USING GIN (search_vector);
+CREATE INDEX products_name_trgm_idx ON products USING GIN (name gin_trgm_ops);
+
+-- the search document of every product, shared by all search backends
+CREATE VIEW product_documents AS
+  SELECT p.id, p.name, p.price, p.currency, p.origin, p.cultivar,
+         ARRAY(SELECT c.name FROM product_categories pc JOIN categories c ON c.id = pc.category_id
+                WHERE pc.product_id = p.id ORDER BY c.name) AS categories,
+         ARRAY(WITH RECURSIVE tree AS (
+                   SELECT c.id, c.parent_id, c.slug FROM product_categories pc JOIN categories c ON c.id = pc.category_id
+                    WHERE pc.product_id = p.id
+                 UNION
+                   SELECT c.id, c.parent_id, c.slug FROM categories c JOIN tree t ON c.id = t.parent_id
+               )
+               SELECT slug FROM tree) AS category_slugs,
+         p.attributes,
+         (SELECT COALESCE(v.path, i.path) FROM images i
+              LEFT JOIN image_variants v ON v.image_id = i.id AND v.name = 'thumbnail' AND v.format <> 'webp'
+           WHERE i.product_id = p.id
+           ORDER BY i.is_primary DESC, i.position, i.id LIMIT 1) AS image,
+         EXTRACT(EPOCH FROM p.updated_at)::bigint AS updated_at,
+         EXTRACT(EPOCH FROM p.created_at)::bigint AS created_at
+    FROM products p;
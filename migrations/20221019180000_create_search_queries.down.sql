@@ -0,0 +1,2 @@
+DROP INDEX categories_name_trgm_idx;
+DROP TABLE search_queries;
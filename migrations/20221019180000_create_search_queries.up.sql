@@ -0,0 +1,9 @@
+-- how often each search was run, for "popular searches" and suggestions
+CREATE TABLE search_queries (
+  query TEXT NOT NULL PRIMARY KEY,
+  count BIGINT NOT NULL DEFAULT 0,
+  last_searched_at TIMESTAMP NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX search_queries_prefix_idx ON search_queries (query text_pattern_ops);
+CREATE INDEX categories_name_trgm_idx ON categories USING GIN (name gin_trgm_ops);
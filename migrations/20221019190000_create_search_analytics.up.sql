@@ -0,0 +1,28 @@
+-- one row per /search call; session_id is a random per-visitor id, not the user
+CREATE TABLE search_events (
+  id TEXT NOT NULL PRIMARY KEY,
+  query TEXT NOT NULL,
+  filters TEXT[] NOT NULL DEFAULT '{}',
+  sort TEXT,
+  page INTEGER NOT NULL,
+  result_count BIGINT NOT NULL,
+  latency_ms INTEGER NOT NULL,
+  session_id TEXT,
+  -- ok, disabled, timeout or error
+  outcome TEXT NOT NULL DEFAULT 'ok',
+  created_at TIMESTAMP NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX search_events_created_at_idx ON search_events (created_at);
+
+-- search_id isn't a foreign key: events are written in batches and may arrive after their clicks
+CREATE TABLE search_clicks (
+  id BIGSERIAL NOT NULL PRIMARY KEY,
+  search_id TEXT NOT NULL,
+  product_id INTEGER NOT NULL,
+  position INTEGER,
+  session_id TEXT,
+  created_at TIMESTAMP NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX search_clicks_search_id_idx ON search_clicks (search_id);
@@ -0,0 +1,10 @@
+-- every change to synonyms, stop words and merchandising rules adds a version;
+-- the highest version is the active one
+CREATE TABLE search_settings (
+  version SERIAL NOT NULL PRIMARY KEY,
+  synonyms JSONB NOT NULL DEFAULT '[]',
+  stop_words TEXT[] NOT NULL DEFAULT '{}',
+  rules JSONB NOT NULL DEFAULT '[]',
+  created_by INTEGER REFERENCES users (id) ON DELETE SET NULL,
+  created_at TIMESTAMP NOT NULL DEFAULT NOW()
+);
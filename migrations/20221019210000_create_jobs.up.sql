@@ -0,0 +1,29 @@
+-- durable background jobs, claimed by workers with FOR UPDATE SKIP LOCKED
+CREATE TABLE jobs (
+  id BIGSERIAL NOT NULL PRIMARY KEY,
+  kind TEXT NOT NULL,
+  payload JSONB NOT NULL,
+  status TEXT NOT NULL DEFAULT 'pending'
+    CHECK (status IN ('pending', 'running', 'completed', 'dead', 'cancelled')),
+  unique_key TEXT,
+  attempts INTEGER NOT NULL DEFAULT 0,
+  max_attempts INTEGER NOT NULL,
+  last_error TEXT,
+  run_at TIMESTAMP NOT NULL DEFAULT NOW(),
+  locked_at TIMESTAMP,
+  completed_at TIMESTAMP,
+  updated_at TIMESTAMP NOT NULL DEFAULT NOW(),
+  created_at TIMESTAMP NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX jobs_due_idx ON jobs (run_at) WHERE status IN ('pending', 'running');
+-- at most one queued or running job per key
+CREATE UNIQUE INDEX jobs_unique_key_idx ON jobs (kind, unique_key)
+  WHERE unique_key IS NOT NULL AND status IN ('pending', 'running');
+
+-- images uploaded before the queue still need their variants
+INSERT INTO jobs (kind, payload, unique_key, max_attempts)
+  SELECT 'generate_variants', jsonb_build_object('image_id', i.id), i.id::text, 5
+      FROM images i
+   WHERE i.storage_key IS NOT NULL
+     AND NOT EXISTS (SELECT 1 FROM image_variants v WHERE v.image_id = i.id);
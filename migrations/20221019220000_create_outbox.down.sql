@@ -0,0 +1,21 @@
+CREATE TABLE search_outbox (
+  id BIGSERIAL NOT NULL PRIMARY KEY,
+  product_id INTEGER NOT NULL,
+  operation TEXT NOT NULL CHECK (operation IN ('upsert', 'delete')),
+  attempts INTEGER NOT NULL DEFAULT 0,
+  last_error TEXT,
+  available_at TIMESTAMP NOT NULL DEFAULT NOW(),
+  created_at TIMESTAMP NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX search_outbox_available_at_idx ON search_outbox (available_at);
+
+INSERT INTO search_outbox (product_id, operation, created_at)
+  SELECT aggregate_id::integer,
+         CASE event_type WHEN 'product.deleted' THEN 'delete' ELSE 'upsert' END,
+         created_at
+      FROM outbox
+   WHERE aggregate_type = 'product' AND status = 'pending'
+  ORDER BY id;
+
+DROP TABLE outbox;
@@ -0,0 +1,30 @@
+-- domain events, written in the same transaction as the change they describe and
+-- removed once every subscriber handled them; events that kept failing are kept
+-- as `dead` for inspection
+CREATE TABLE outbox (
+  id BIGSERIAL NOT NULL PRIMARY KEY,
+  aggregate_type TEXT NOT NULL,
+  aggregate_id TEXT NOT NULL,
+  event_type TEXT NOT NULL,
+  payload JSONB NOT NULL,
+  status TEXT NOT NULL DEFAULT 'pending' CHECK (status IN ('pending', 'dead')),
+  attempts INTEGER NOT NULL DEFAULT 0,
+  last_error TEXT,
+  available_at TIMESTAMP NOT NULL DEFAULT NOW(),
+  created_at TIMESTAMP NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX outbox_aggregate_idx ON outbox (aggregate_type, aggregate_id, id);
+
+-- index changes that weren't applied yet become product events
+INSERT INTO outbox (aggregate_type, aggregate_id, event_type, payload, created_at)
+  SELECT 'product', product_id::text, event_type,
+         jsonb_build_object('type', event_type, 'product_id', product_id), created_at
+      FROM (
+        SELECT id, product_id, created_at,
+               CASE operation WHEN 'upsert' THEN 'product.updated' ELSE 'product.deleted' END AS event_type
+            FROM search_outbox
+      ) entries
+  ORDER BY id;
+
+DROP TABLE search_outbox;
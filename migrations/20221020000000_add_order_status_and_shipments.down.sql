@@ -0,0 +1,4 @@
+DROP TABLE order_events;
+DROP TABLE shipments;
+
+ALTER TABLE orders DROP COLUMN status;
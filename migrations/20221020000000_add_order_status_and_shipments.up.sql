@@ -0,0 +1,28 @@
+-- pending -> paid -> shipped -> delivered, or cancelled before shipping
+ALTER TABLE orders ADD COLUMN status TEXT NOT NULL DEFAULT 'pending'
+  CHECK (status IN ('pending', 'paid', 'shipped', 'delivered', 'cancelled'));
+
+CREATE TABLE shipments (
+  id SERIAL PRIMARY KEY,
+  order_id INTEGER NOT NULL REFERENCES orders (id) ON DELETE CASCADE,
+  carrier TEXT NOT NULL,
+  tracking_number TEXT,
+  status TEXT NOT NULL DEFAULT 'preparing'
+    CHECK (status IN ('preparing', 'in_transit', 'delivered', 'returned')),
+  updated_at TIMESTAMP NOT NULL DEFAULT NOW(),
+  created_at TIMESTAMP NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX shipments_order_id_idx ON shipments (order_id);
+
+-- bodies of the order events sent to feed listeners; NOTIFY only carries the id,
+-- as its payload is limited to 8000 bytes. Rows are kept for an hour.
+CREATE TABLE order_events (
+  id BIGINT PRIMARY KEY,
+  order_id INTEGER NOT NULL,
+  event_type TEXT NOT NULL,
+  body TEXT NOT NULL,
+  created_at TIMESTAMP NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX order_events_created_at_idx ON order_events (created_at);
@@ -1,10 +1,25 @@
+use crate::{errors::ServiceError, models::users::User, types::PostgresPool};
+use actix_session::Session;
 use argon2rs::argon2i_simple;
-use std::env;
 
-pub fn hash(password: &str) -> String {
-    let salt = env::var("AUTH_SALT").expect("environment variable: AUTH_SALT");
-    argon2i_simple(&password, &salt)
+/// Hashes `password` with the configured `auth.salt`.
+pub fn hash(password: &str, salt: &str) -> String {
+    argon2i_simple(password, salt)
         .iter()
         .map(|b| format!("{:02x}", b))
         .collect()
 }
+
+/// Checks that the session belongs to a user flagged `is_admin`, renewing it like
+/// the other protected handlers do.
+pub async fn require_admin(session: &Session, pool: &PostgresPool) -> Result<User, ServiceError> {
+    let user_id: Option<i64> = session.get("user_id").unwrap_or(None);
+    let user_id = user_id.ok_or(ServiceError::Unauthorized)?;
+    session.renew();
+
+    match User::find_by_id(user_id as i32, pool).await {
+        Ok(user) if user.is_admin => Ok(user),
+        Ok(_) => Err(ServiceError::Forbidden),
+        Err(_) => Err(ServiceError::Unauthorized),
+    }
+}
@@ -0,0 +1,220 @@
+use crate::{
+    models::categories::{Category, CategoryInput},
+    models::currencies::{ExchangeRate, ProductPrice, ProductPriceInput},
+    models::products::{Product, ProductInput},
+    types::PostgresPool,
+};
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::Value as JsonValue;
+use std::{collections::HashMap, fs, io, path::Path, path::PathBuf};
+
+const SEED: &str = include_str!("seed.json");
+
+/// The catalog as written by `export` and read by `import`. Categories refer to
+/// their parent, and products to their categories, by slug so the data can move
+/// between databases.
+#[derive(Serialize, Deserialize)]
+struct Catalog {
+    #[serde(default)]
+    exchange_rates: Vec<RateRecord>,
+    #[serde(default)]
+    categories: Vec<CategoryRecord>,
+    #[serde(default)]
+    products: Vec<ProductRecord>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct RateRecord {
+    currency: String,
+    rate: f64,
+}
+
+#[derive(Serialize, Deserialize)]
+struct CategoryRecord {
+    slug: String,
+    name: String,
+    parent: Option<String>,
+    #[serde(default)]
+    position: i32,
+}
+
+#[derive(Serialize, Deserialize)]
+struct ProductRecord {
+    name: String,
+    price: i64,
+    currency: String,
+    origin: String,
+    cultivar: String,
+    #[serde(default)]
+    attributes: JsonValue,
+    #[serde(default)]
+    categories: Vec<String>,
+    #[serde(default)]
+    prices: Vec<ProductPriceInput>,
+}
+
+pub async fn seed(pool: &PostgresPool) -> Result<()> {
+    load(serde_json::from_str(SEED)?, pool).await
+}
+
+pub async fn export(output: Option<PathBuf>, pool: &PostgresPool) -> Result<()> {
+    let categories = Category::find_all(pool).await?;
+    let slugs: HashMap<i32, String> = categories
+        .iter()
+        .map(|category| (category.id, category.slug.clone()))
+        .collect();
+
+    let mut products = Vec::new();
+    for product in Product::find_all(None, pool).await? {
+        let categories = Category::find_by_product(product.id, pool).await?;
+        let prices = ProductPrice::find_by_product(product.id, pool).await?;
+        products.push(ProductRecord {
+            name: product.name,
+            price: product.price,
+            currency: product.currency,
+            origin: product.origin,
+            cultivar: product.cultivar,
+            attributes: product.attributes,
+            categories: categories
+                .into_iter()
+                .map(|category| category.slug)
+                .collect(),
+            prices: prices
+                .into_iter()
+                .map(|price| ProductPriceInput {
+                    currency: price.currency,
+                    amount: price.amount,
+                })
+                .collect(),
+        });
+    }
+
+    let catalog = Catalog {
+        exchange_rates: ExchangeRate::find_all(pool)
+            .await?
+            .into_iter()
+            .map(|rate| RateRecord {
+                currency: rate.currency,
+                rate: rate.rate,
+            })
+            .collect(),
+        categories: categories
+            .iter()
+            .map(|category| CategoryRecord {
+                slug: category.slug.clone(),
+                name: category.name.clone(),
+                parent: category
+                    .parent_id
+                    .and_then(|parent_id| slugs.get(&parent_id).cloned()),
+                position: category.position,
+            })
+            .collect(),
+        products,
+    };
+
+    match output {
+        Some(path) => serde_json::to_writer_pretty(fs::File::create(path)?, &catalog)?,
+        None => serde_json::to_writer_pretty(io::stdout().lock(), &catalog)?,
+    }
+    Ok(())
+}
+
+pub async fn import(file: &Path, pool: &PostgresPool) -> Result<()> {
+    let catalog = serde_json::from_str(&fs::read_to_string(file)?)?;
+    load(catalog, pool).await
+}
+
+/// Upserts the exchange rates, creates categories whose slug doesn't exist yet and
+/// adds every product. Products have no natural key, so loading the same file
+/// twice duplicates them.
+async fn load(catalog: Catalog, pool: &PostgresPool) -> Result<()> {
+    for rate in &catalog.exchange_rates {
+        ExchangeRate::upsert(&rate.currency, rate.rate, pool).await?;
+    }
+
+    let mut category_ids: HashMap<String, i32> = Category::find_all(pool)
+        .await?
+        .into_iter()
+        .map(|category| (category.slug, category.id))
+        .collect();
+    let mut remaining: Vec<&CategoryRecord> = catalog
+        .categories
+        .iter()
+        .filter(|record| !category_ids.contains_key(&record.slug))
+        .collect();
+    let mut created = 0;
+    // parents have to exist before their children
+    while !remaining.is_empty() {
+        let (ready, waiting): (Vec<&CategoryRecord>, Vec<&CategoryRecord>) = remaining
+            .into_iter()
+            .partition(|record| match record.parent {
+                Some(ref parent) => category_ids.contains_key(parent),
+                None => true,
+            });
+        if ready.is_empty() {
+            let slugs: Vec<&str> = waiting.iter().map(|record| record.slug.as_str()).collect();
+            return Err(anyhow!(
+                "categories with unknown parents: {}",
+                slugs.join(", ")
+            ));
+        }
+        for record in ready {
+            let category = Category::create(
+                CategoryInput {
+                    parent_id: record.parent.as_ref().map(|parent| category_ids[parent]),
+                    name: record.name.clone(),
+                    slug: record.slug.clone(),
+                    position: Some(record.position),
+                },
+                pool,
+            )
+            .await?;
+            category_ids.insert(category.slug, category.id);
+            created += 1;
+        }
+        remaining = waiting;
+    }
+
+    let products = catalog.products.len();
+    for record in catalog.products {
+        let ids = record
+            .categories
+            .iter()
+            .map(|slug| {
+                category_ids
+                    .get(slug)
+                    .copied()
+                    .ok_or_else(|| anyhow!("product {} has unknown category {}", record.name, slug))
+            })
+            .collect::<Result<Vec<i32>>>()?;
+        let attributes = match record.attributes {
+            JsonValue::Null => None,
+            attributes => Some(attributes),
+        };
+        let product = Product::create(
+            ProductInput {
+                name: record.name,
+                price: record.price,
+                origin: record.origin,
+                cultivar: record.cultivar,
+                currency: Some(record.currency),
+                attributes,
+                category_ids: Some(ids),
+            },
+            pool,
+        )
+        .await?;
+        for price in record.prices {
+            ProductPrice::upsert(product.id, price, pool).await?;
+        }
+    }
+
+    println!(
+        "loaded {} exchange rate(s), {} new categories and {} product(s)",
+        catalog.exchange_rates.len(),
+        created,
+        products
+    );
+    Ok(())
+}
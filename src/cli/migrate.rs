@@ -0,0 +1,55 @@
+use crate::{
+    models::schema::{self, MIGRATOR},
+    types::PostgresPool,
+};
+use anyhow::Result;
+use clap::Subcommand;
+
+#[derive(Subcommand)]
+pub enum MigrateCommand {
+    /// Applies all pending migrations
+    Up,
+    /// Reverts migrations newer than the target version, by default only the latest
+    Down {
+        #[clap(long)]
+        target: Option<i64>,
+    },
+    /// Lists every migration and whether it is applied
+    Status,
+}
+
+pub async fn run(command: MigrateCommand, pool: &PostgresPool) -> Result<()> {
+    match command {
+        MigrateCommand::Up => {
+            let pending = schema::pending_migrations(pool).await?;
+            MIGRATOR.run(pool).await?;
+            println!("applied {} migration(s)", pending.len());
+        }
+        MigrateCommand::Down { target } => {
+            let applied = schema::applied_migrations(pool).await?;
+            let target = match target {
+                Some(target) => target,
+                None if applied.len() > 1 => applied[applied.len() - 2],
+                None => 0,
+            };
+            let reverted = schema::revert(target, pool).await?;
+            println!("reverted {} migration(s)", reverted);
+        }
+        MigrateCommand::Status => {
+            let applied = schema::applied_migrations(pool).await?;
+            for migration in schema::migrations() {
+                let state = if applied.contains(&migration.version) {
+                    "applied"
+                } else {
+                    "pending"
+                };
+                println!(
+                    "{:<16} {:<8} {}",
+                    migration.version, state, migration.description
+                );
+            }
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,86 @@
+use crate::{
+    models::schema::MIGRATOR,
+    server,
+    settings::Settings,
+    workers::{jobs, search_index::ReindexProducts},
+};
+use anyhow::Result;
+use clap::{Parser, Subcommand};
+use std::path::PathBuf;
+
+mod data;
+mod migrate;
+mod users;
+
+/// Shop API server and admin tools. Without a command the server is started.
+#[derive(Parser)]
+#[clap(name = "shopapi", version, about)]
+pub struct Cli {
+    #[clap(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Runs the HTTP server
+    Serve {
+        /// Apply pending migrations before starting
+        #[clap(long)]
+        migrate: bool,
+    },
+    /// Runs background jobs without serving the API
+    Worker,
+    /// Applies, reverts or lists the embedded migrations
+    #[clap(subcommand)]
+    Migrate(migrate::MigrateCommand),
+    /// Manages user accounts
+    #[clap(subcommand)]
+    User(users::UserCommand),
+    /// Maintains the search index
+    #[clap(subcommand)]
+    Search(SearchCommand),
+    /// Loads a small demo catalog
+    Seed,
+    /// Writes exchange rates, categories and products as JSON
+    Export {
+        /// File to write instead of stdout
+        #[clap(long, short)]
+        output: Option<PathBuf>,
+    },
+    /// Loads a file written by `export`
+    Import { file: PathBuf },
+}
+
+#[derive(Subcommand)]
+enum SearchCommand {
+    /// Queues a job indexing every product; a running worker picks it up
+    Reindex,
+}
+
+pub async fn run(cli: Cli, settings: Settings) -> Result<()> {
+    let pool = settings.database.connect().await?;
+
+    match cli.command.unwrap_or(Command::Serve { migrate: false }) {
+        Command::Serve { migrate } => {
+            if migrate {
+                MIGRATOR.run(&pool).await?;
+            }
+            server::run(settings, pool.clone()).await?;
+        }
+        Command::Worker => server::run_worker(settings, pool.clone()).await?,
+        Command::Migrate(command) => migrate::run(command, &pool).await?,
+        Command::User(command) => users::run(command, &settings, &pool).await?,
+        Command::Search(SearchCommand::Reindex) => {
+            match jobs::enqueue(&ReindexProducts {}, None, &pool).await? {
+                Some(id) => println!("queued reindex job {}", id),
+                None => println!("a reindex is already queued"),
+            }
+        }
+        Command::Seed => data::seed(&pool).await?,
+        Command::Export { output } => data::export(output, &pool).await?,
+        Command::Import { file } => data::import(&file, &pool).await?,
+    }
+
+    pool.close().await;
+    Ok(())
+}
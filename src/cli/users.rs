@@ -0,0 +1,93 @@
+use crate::{
+    models::users::{User, UserInput},
+    settings::Settings,
+    types::PostgresPool,
+};
+use anyhow::{anyhow, Result};
+use clap::Subcommand;
+use std::io::{self, BufRead, Write};
+
+#[derive(Subcommand)]
+pub enum UserCommand {
+    /// Creates a user; the password is read from stdin unless given
+    Create {
+        #[clap(long)]
+        username: String,
+        #[clap(long)]
+        email: String,
+        #[clap(long, default_value = "")]
+        first_name: String,
+        #[clap(long, default_value = "")]
+        last_name: String,
+        #[clap(long)]
+        password: Option<String>,
+        /// Allow access to the admin endpoints
+        #[clap(long)]
+        admin: bool,
+    },
+    /// Sets a new password; it is read from stdin unless given
+    ResetPassword {
+        username: String,
+        #[clap(long)]
+        password: Option<String>,
+    },
+}
+
+/// Prompts for a password on stderr so stdout stays clean, and reads one line from
+/// stdin, which also allows piping it in.
+fn read_password(password: Option<String>) -> Result<String> {
+    let password = match password {
+        Some(password) => password,
+        None => {
+            eprint!("Password: ");
+            io::stderr().flush()?;
+            let mut line = String::new();
+            io::stdin().lock().read_line(&mut line)?;
+            line.trim_end_matches(&['\r', '\n'][..]).to_string()
+        }
+    };
+    if password.is_empty() {
+        return Err(anyhow!("the password must not be empty"));
+    }
+    Ok(password)
+}
+
+pub async fn run(command: UserCommand, settings: &Settings, pool: &PostgresPool) -> Result<()> {
+    match command {
+        UserCommand::Create {
+            username,
+            email,
+            first_name,
+            last_name,
+            password,
+            admin,
+        } => {
+            if User::find_by_username(&username, pool).await.is_ok() {
+                return Err(anyhow!("user {} already exists", username));
+            }
+            let input = UserInput {
+                first_name,
+                last_name,
+                username,
+                password: read_password(password)?,
+                email,
+            };
+            let user = if admin {
+                User::create_admin(input, &settings.auth.salt, pool).await?
+            } else {
+                User::create(input, &settings.auth.salt, pool).await?
+            };
+            println!("created user {} with id {}", user.username, user.id);
+        }
+        UserCommand::ResetPassword { username, password } => {
+            let user = User::find_by_username(&username, pool)
+                .await
+                .map_err(|_| anyhow!("no user named {}", username))?;
+            let password = read_password(password)?;
+            User::set_password(user.id, &password, &settings.auth.salt, pool).await?;
+            println!("password of {} changed", user.username);
+        }
+    }
+
+    Ok(())
+}
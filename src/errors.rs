@@ -12,6 +12,15 @@ pub enum ServiceError {
 
     #[display(fmt = "Unauthorized")]
     Unauthorized,
+
+    #[display(fmt = "Forbidden")]
+    Forbidden,
+
+    #[display(fmt = "PayloadTooLarge: {}", _0)]
+    PayloadTooLarge(String),
+
+    #[display(fmt = "ServiceUnavailable: {}", _0)]
+    ServiceUnavailable(String),
 }
 
 // impl ResponseError trait allows to convert our errors into http responses with appropriate data
@@ -23,6 +32,13 @@ impl ResponseError for ServiceError {
             }
             ServiceError::BadRequest(ref message) => HttpResponse::BadRequest().json(message),
             ServiceError::Unauthorized => HttpResponse::Unauthorized().json("Unauthorized"),
+            ServiceError::Forbidden => HttpResponse::Forbidden().json("Forbidden"),
+            ServiceError::PayloadTooLarge(ref message) => {
+                HttpResponse::PayloadTooLarge().json(message)
+            }
+            ServiceError::ServiceUnavailable(ref message) => {
+                HttpResponse::ServiceUnavailable().json(message)
+            }
         }
     }
 }
\ No newline at end of file
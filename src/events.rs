@@ -0,0 +1,146 @@
+use anyhow::Result;
+use async_trait::async_trait;
+use chrono::NaiveDateTime;
+use serde::{Deserialize, Serialize};
+
+/// Every `DomainEvent::event_type`, for validating webhook subscriptions.
+pub const EVENT_TYPES: &[&str] = &[
+    "product.created",
+    "product.updated",
+    "product.deleted",
+    "product.categories_changed",
+    "category.updated",
+    "category.moved",
+    "category.deleted",
+    "order.created",
+    "order.updated",
+    "order.deleted",
+    "order.status_changed",
+    "shipment.created",
+    "shipment.updated",
+    "user.registered",
+];
+
+/// Something that happened to a product, category, order or user. Events are written to
+/// the `outbox` table by the model method making the change, inside its
+/// transaction, so they exist exactly when the change was committed.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(tag = "type")]
+pub enum DomainEvent {
+    #[serde(rename = "product.created")]
+    ProductCreated { product_id: i32 },
+    #[serde(rename = "product.updated")]
+    ProductUpdated { product_id: i32 },
+    #[serde(rename = "product.deleted")]
+    ProductDeleted { product_id: i32 },
+    #[serde(rename = "product.categories_changed")]
+    ProductCategoriesChanged {
+        product_id: i32,
+        category_ids: Vec<i32>,
+    },
+    /// `product_ids` are the products linked to the category or its descendants.
+    #[serde(rename = "category.updated")]
+    CategoryUpdated {
+        category_id: i32,
+        product_ids: Vec<i32>,
+    },
+    #[serde(rename = "category.moved")]
+    CategoryMoved {
+        category_id: i32,
+        parent_id: Option<i32>,
+        product_ids: Vec<i32>,
+    },
+    /// Descendants go with the category; `product_ids` were linked to any of them.
+    #[serde(rename = "category.deleted")]
+    CategoryDeleted {
+        category_id: i32,
+        product_ids: Vec<i32>,
+    },
+    #[serde(rename = "order.created")]
+    OrderCreated { order_id: i32 },
+    #[serde(rename = "order.updated")]
+    OrderUpdated { order_id: i32 },
+    #[serde(rename = "order.deleted")]
+    OrderDeleted { order_id: i32 },
+    #[serde(rename = "order.status_changed")]
+    OrderStatusChanged {
+        order_id: i32,
+        from: String,
+        to: String,
+    },
+    #[serde(rename = "shipment.created")]
+    ShipmentCreated { order_id: i32, shipment_id: i32 },
+    #[serde(rename = "shipment.updated")]
+    ShipmentUpdated {
+        order_id: i32,
+        shipment_id: i32,
+        status: String,
+    },
+    #[serde(rename = "user.registered")]
+    UserRegistered { user_id: i32 },
+}
+
+impl DomainEvent {
+    /// The `type` tag, e.g. `product.created`.
+    pub fn event_type(&self) -> &'static str {
+        match self {
+            DomainEvent::ProductCreated { .. } => "product.created",
+            DomainEvent::ProductUpdated { .. } => "product.updated",
+            DomainEvent::ProductDeleted { .. } => "product.deleted",
+            DomainEvent::ProductCategoriesChanged { .. } => "product.categories_changed",
+            DomainEvent::CategoryUpdated { .. } => "category.updated",
+            DomainEvent::CategoryMoved { .. } => "category.moved",
+            DomainEvent::CategoryDeleted { .. } => "category.deleted",
+            DomainEvent::OrderCreated { .. } => "order.created",
+            DomainEvent::OrderUpdated { .. } => "order.updated",
+            DomainEvent::OrderDeleted { .. } => "order.deleted",
+            DomainEvent::OrderStatusChanged { .. } => "order.status_changed",
+            DomainEvent::ShipmentCreated { .. } => "shipment.created",
+            DomainEvent::ShipmentUpdated { .. } => "shipment.updated",
+            DomainEvent::UserRegistered { .. } => "user.registered",
+        }
+    }
+
+    /// The entity the event belongs to; events of one aggregate are delivered in
+    /// the order they were written.
+    pub fn aggregate(&self) -> (&'static str, String) {
+        match self {
+            DomainEvent::ProductCreated { product_id }
+            | DomainEvent::ProductUpdated { product_id }
+            | DomainEvent::ProductDeleted { product_id }
+            | DomainEvent::ProductCategoriesChanged { product_id, .. } => {
+                ("product", product_id.to_string())
+            }
+            DomainEvent::CategoryUpdated { category_id, .. }
+            | DomainEvent::CategoryMoved { category_id, .. }
+            | DomainEvent::CategoryDeleted { category_id, .. } => {
+                ("category", category_id.to_string())
+            }
+            DomainEvent::OrderCreated { order_id }
+            | DomainEvent::OrderUpdated { order_id }
+            | DomainEvent::OrderDeleted { order_id }
+            | DomainEvent::OrderStatusChanged { order_id, .. }
+            // shipments are ordered with the rest of their order's events
+            | DomainEvent::ShipmentCreated { order_id, .. }
+            | DomainEvent::ShipmentUpdated { order_id, .. } => ("order", order_id.to_string()),
+            DomainEvent::UserRegistered { user_id } => ("user", user_id.to_string()),
+        }
+    }
+}
+
+/// An event read back from the outbox.
+#[derive(Clone, Debug)]
+pub struct Event {
+    pub id: i64,
+    pub data: DomainEvent,
+    pub created_at: NaiveDateTime,
+}
+
+/// Reacts to committed domain events, see `workers::outbox`. Delivery is at
+/// least once: when any subscriber fails, the event is handed to all of them
+/// again later, so handlers have to be idempotent.
+#[async_trait(?Send)]
+pub trait Subscriber {
+    fn name(&self) -> &'static str;
+    async fn handle(&self, event: &Event) -> Result<()>;
+}
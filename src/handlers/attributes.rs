@@ -0,0 +1,108 @@
+use crate::errors::ServiceError;
+use crate::{
+    models::attributes::{AttributeDefinition, AttributeDefinitionInput},
+    types::PostgresPool,
+};
+use actix_session::Session;
+use actix_web::{web, HttpResponse, Responder};
+
+async fn find_by_category(
+    category_id: web::Path<i32>,
+    pool: web::Data<PostgresPool>,
+) -> Result<impl Responder, ServiceError> {
+    let result =
+        AttributeDefinition::find_by_category(category_id.into_inner(), pool.get_ref()).await;
+    match result {
+        Ok(definitions) => Ok(HttpResponse::Ok().json(definitions)),
+        _ => Err(ServiceError::BadRequest(
+            "Error trying to read attribute definitions from database".to_string(),
+        )),
+    }
+}
+
+async fn create(
+    session: Session,
+    category_id: web::Path<i32>,
+    input: web::Json<AttributeDefinitionInput>,
+    pool: web::Data<PostgresPool>,
+) -> Result<impl Responder, ServiceError> {
+    let user_id: Option<i64> = session.get("user_id").unwrap_or(None);
+
+    match user_id {
+        Some(_id) => {
+            session.renew();
+            let result = AttributeDefinition::create(
+                category_id.into_inner(),
+                input.into_inner(),
+                pool.get_ref(),
+            )
+            .await;
+            match result {
+                Ok(definition) => Ok(HttpResponse::Ok().json(definition)),
+                Err(err) => Err(ServiceError::BadRequest(err.to_string())),
+            }
+        }
+        None => Err(ServiceError::Unauthorized),
+    }
+}
+
+async fn update(
+    session: Session,
+    id: web::Path<i32>,
+    input: web::Json<AttributeDefinitionInput>,
+    pool: web::Data<PostgresPool>,
+) -> Result<impl Responder, ServiceError> {
+    let user_id: Option<i64> = session.get("user_id").unwrap_or(None);
+    match user_id {
+        Some(_userid) => {
+            session.renew();
+            let result =
+                AttributeDefinition::update(id.into_inner(), input.into_inner(), pool.get_ref())
+                    .await;
+            match result {
+                Ok(definition) => Ok(HttpResponse::Ok().json(definition)),
+                Err(err) => Err(ServiceError::BadRequest(err.to_string())),
+            }
+        }
+        None => Err(ServiceError::Unauthorized),
+    }
+}
+
+async fn delete(
+    session: Session,
+    id: web::Path<i32>,
+    db_pool: web::Data<PostgresPool>,
+) -> Result<impl Responder, ServiceError> {
+    let user_id: Option<i64> = session.get("user_id").unwrap_or(None);
+    match user_id {
+        Some(_userid) => {
+            session.renew();
+            let result = AttributeDefinition::delete(id.into_inner(), db_pool.get_ref()).await;
+            match result {
+                Ok(rows) => {
+                    if rows > 0 {
+                        Ok(HttpResponse::Ok()
+                            .body(format!("Successfully deleted {} record(s)", rows)))
+                    } else {
+                        Ok(HttpResponse::NotFound().body("Attribute definition not found"))
+                    }
+                }
+                _ => Err(ServiceError::InternalServerError),
+            }
+        }
+        None => Err(ServiceError::Unauthorized),
+    }
+}
+
+pub fn config(cfg: &mut web::ServiceConfig) {
+    cfg.service(
+        web::resource("/categories/{id}/attributes")
+            .route(web::get().to(find_by_category))
+            .route(web::post().to(create)),
+    );
+    cfg.service(
+        web::resource("/attributes/{id}")
+            .route(web::put().to(update))
+            .route(web::delete().to(delete)),
+    );
+}
@@ -1,6 +1,8 @@
 use crate::errors::ServiceError;
 use crate::{
+    metrics::Metrics,
     models::auth::{Credentials, Auth},
+    settings::Settings,
     types::PostgresPool,
 };
 use actix_session::Session;
@@ -10,15 +12,20 @@ pub async fn login(
     session: Session,
     credentials: web::Json<Credentials>,
     db_pool: web::Data<PostgresPool>,
+    settings: web::Data<Settings>,
+    metrics: web::Data<Metrics>,
 ) -> Result<impl Responder, ServiceError> {
     let credentials = credentials.into_inner();
 
-    let user = match Auth::authenticate(credentials, db_pool.get_ref()).await {
+    let user = match Auth::authenticate(credentials, &settings.auth.salt, db_pool.get_ref()).await {
         Ok(user) => {
             session.insert("user_id", user.id).unwrap();
             user
         }
-        Err(_err) => return Err(ServiceError::Unauthorized),
+        Err(_err) => {
+            metrics.login_failed();
+            return Err(ServiceError::Unauthorized);
+        }
     };
 
     Ok(format!("Welcome!, {:?}", user))
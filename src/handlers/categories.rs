@@ -0,0 +1,176 @@
+use crate::errors::ServiceError;
+use crate::{
+    models::categories::{Category, CategoryInput, MoveInput},
+    models::products::Product,
+    types::PostgresPool,
+};
+use actix_session::Session;
+use actix_web::{web, HttpResponse, Responder};
+
+async fn find_tree(pool: web::Data<PostgresPool>) -> Result<impl Responder, ServiceError> {
+    let result = Category::find_tree(pool.get_ref()).await;
+    match result {
+        Ok(categories) => Ok(HttpResponse::Ok().json(categories)),
+        _ => Err(ServiceError::BadRequest(
+            "Error trying to read all categories from database".to_string(),
+        )),
+    }
+}
+
+async fn create(
+    session: Session,
+    input: web::Json<CategoryInput>,
+    pool: web::Data<PostgresPool>,
+) -> Result<impl Responder, ServiceError> {
+    let user_id: Option<i64> = session.get("user_id").unwrap_or(None);
+
+    match user_id {
+        Some(_id) => {
+            session.renew();
+            let result = Category::create(input.into_inner(), pool.get_ref()).await;
+            match result {
+                Ok(category) => Ok(HttpResponse::Ok().json(category)),
+                _ => Err(ServiceError::BadRequest(
+                    "Error trying to create new category".to_string(),
+                )),
+            }
+        }
+        None => Err(ServiceError::Unauthorized),
+    }
+}
+
+async fn find_products(
+    slug: web::Path<String>,
+    pool: web::Data<PostgresPool>,
+) -> Result<impl Responder, ServiceError> {
+    let result = Product::find_by_category(&slug, pool.get_ref()).await;
+    match result {
+        Ok(products) => Ok(HttpResponse::Ok().json(products)),
+        _ => Err(ServiceError::BadRequest(
+            "Error trying to read category products from database".to_string(),
+        )),
+    }
+}
+
+async fn update(
+    session: Session,
+    id: web::Path<i32>,
+    input: web::Json<CategoryInput>,
+    pool: web::Data<PostgresPool>,
+) -> Result<impl Responder, ServiceError> {
+    let user_id: Option<i64> = session.get("user_id").unwrap_or(None);
+    match user_id {
+        Some(_userid) => {
+            session.renew();
+            let result =
+                Category::update(id.into_inner(), input.into_inner(), pool.get_ref()).await;
+            match result {
+                Ok(category) => Ok(HttpResponse::Ok().json(category)),
+                _ => Ok(HttpResponse::NotFound().body("Category not found")),
+            }
+        }
+        None => Err(ServiceError::Unauthorized),
+    }
+}
+
+async fn move_to(
+    session: Session,
+    id: web::Path<i32>,
+    input: web::Json<MoveInput>,
+    pool: web::Data<PostgresPool>,
+) -> Result<impl Responder, ServiceError> {
+    let user_id: Option<i64> = session.get("user_id").unwrap_or(None);
+    match user_id {
+        Some(_userid) => {
+            session.renew();
+            let result =
+                Category::move_to(id.into_inner(), input.into_inner(), pool.get_ref()).await;
+            match result {
+                Ok(category) => Ok(HttpResponse::Ok().json(category)),
+                Err(err) => Err(ServiceError::BadRequest(err.to_string())),
+            }
+        }
+        None => Err(ServiceError::Unauthorized),
+    }
+}
+
+async fn delete(
+    session: Session,
+    id: web::Path<i32>,
+    db_pool: web::Data<PostgresPool>,
+) -> Result<impl Responder, ServiceError> {
+    let user_id: Option<i64> = session.get("user_id").unwrap_or(None);
+    match user_id {
+        Some(_userid) => {
+            session.renew();
+            let result = Category::delete(id.into_inner(), db_pool.get_ref()).await;
+            match result {
+                Ok(rows) => {
+                    if rows > 0 {
+                        Ok(HttpResponse::Ok()
+                            .body(format!("Successfully deleted {} record(s)", rows)))
+                    } else {
+                        Ok(HttpResponse::NotFound().body("Category not found"))
+                    }
+                }
+                _ => Err(ServiceError::InternalServerError),
+            }
+        }
+        None => Err(ServiceError::Unauthorized),
+    }
+}
+
+async fn find_product_categories(
+    id: web::Path<i32>,
+    pool: web::Data<PostgresPool>,
+) -> Result<impl Responder, ServiceError> {
+    let result = Category::find_by_product(id.into_inner(), pool.get_ref()).await;
+    match result {
+        Ok(categories) => Ok(HttpResponse::Ok().json(categories)),
+        _ => Err(ServiceError::BadRequest(
+            "Error trying to read product categories from database".to_string(),
+        )),
+    }
+}
+
+async fn set_product_categories(
+    session: Session,
+    id: web::Path<i32>,
+    input: web::Json<Vec<i32>>,
+    pool: web::Data<PostgresPool>,
+) -> Result<impl Responder, ServiceError> {
+    let user_id: Option<i64> = session.get("user_id").unwrap_or(None);
+    match user_id {
+        Some(_userid) => {
+            session.renew();
+            let result =
+                Category::set_for_product(id.into_inner(), input.into_inner(), pool.get_ref())
+                    .await;
+            match result {
+                Ok(categories) => Ok(HttpResponse::Ok().json(categories)),
+                Err(err) => Err(ServiceError::BadRequest(err.to_string())),
+            }
+        }
+        None => Err(ServiceError::Unauthorized),
+    }
+}
+
+pub fn config(cfg: &mut web::ServiceConfig) {
+    cfg.service(
+        web::resource("/categories")
+            .route(web::get().to(find_tree))
+            .route(web::post().to(create)),
+    );
+    cfg.service(
+        web::resource("/categories/{id}")
+            .route(web::put().to(update))
+            .route(web::delete().to(delete)),
+    );
+    cfg.service(web::resource("/categories/{id}/move").route(web::put().to(move_to)));
+    cfg.service(web::resource("/categories/{slug}/products").route(web::get().to(find_products)));
+    cfg.service(
+        web::resource("/products/{id}/categories")
+            .route(web::get().to(find_product_categories))
+            .route(web::put().to(set_product_categories)),
+    );
+}
@@ -0,0 +1,206 @@
+use crate::errors::ServiceError;
+use crate::{
+    models::currencies::{self, ExchangeRate, ExchangeRateInput, ProductPrice, ProductPriceInput},
+    types::PostgresPool,
+};
+use actix_session::Session;
+use actix_web::{web, HttpRequest, HttpResponse, Responder};
+use serde::Deserialize;
+
+#[derive(Deserialize)]
+struct CurrencyQuery {
+    currency: Option<String>,
+}
+
+/// Currency requested by the client via `?currency=` or, failing that, the
+/// `Accept-Currency` header. `None` means the product's own currency.
+pub fn requested_currency(req: &HttpRequest) -> Result<Option<String>, ServiceError> {
+    let from_query = web::Query::<CurrencyQuery>::from_query(req.query_string())
+        .ok()
+        .and_then(|query| query.into_inner().currency);
+    let from_header = || {
+        req.headers()
+            .get("Accept-Currency")
+            .and_then(|value| value.to_str().ok())
+            .map(|value| value.to_string())
+    };
+
+    match from_query.or_else(from_header) {
+        Some(code) => currencies::normalize(&code)
+            .map(Some)
+            .map_err(|err| ServiceError::BadRequest(err.to_string())),
+        None => Ok(None),
+    }
+}
+
+async fn find_all_rates(pool: web::Data<PostgresPool>) -> Result<impl Responder, ServiceError> {
+    let result = ExchangeRate::find_all(pool.get_ref()).await;
+    match result {
+        Ok(rates) => Ok(HttpResponse::Ok().json(rates)),
+        _ => Err(ServiceError::BadRequest(
+            "Error trying to read exchange rates from database".to_string(),
+        )),
+    }
+}
+
+async fn find_rate(currency: web::Path<String>, pool: web::Data<PostgresPool>) -> impl Responder {
+    let currency = currency.into_inner().to_ascii_uppercase();
+    let result = ExchangeRate::find(&currency, pool.get_ref()).await;
+    match result {
+        Ok(rate) => HttpResponse::Ok().json(rate),
+        _ => HttpResponse::NotFound().body("Exchange rate not found"),
+    }
+}
+
+async fn update_rate(
+    session: Session,
+    currency: web::Path<String>,
+    input: web::Json<ExchangeRateInput>,
+    pool: web::Data<PostgresPool>,
+) -> Result<impl Responder, ServiceError> {
+    let user_id: Option<i64> = session.get("user_id").unwrap_or(None);
+    match user_id {
+        Some(_userid) => {
+            session.renew();
+            let result = ExchangeRate::upsert(&currency, input.rate, pool.get_ref()).await;
+            match result {
+                Ok(rate) => Ok(HttpResponse::Ok().json(rate)),
+                Err(err) => Err(ServiceError::BadRequest(err.to_string())),
+            }
+        }
+        None => Err(ServiceError::Unauthorized),
+    }
+}
+
+async fn delete_rate(
+    session: Session,
+    currency: web::Path<String>,
+    pool: web::Data<PostgresPool>,
+) -> Result<impl Responder, ServiceError> {
+    let user_id: Option<i64> = session.get("user_id").unwrap_or(None);
+    match user_id {
+        Some(_userid) => {
+            session.renew();
+            let currency = currency.into_inner().to_ascii_uppercase();
+            let result = ExchangeRate::delete(&currency, pool.get_ref()).await;
+            match result {
+                Ok(rows) => {
+                    if rows > 0 {
+                        Ok(HttpResponse::Ok()
+                            .body(format!("Successfully deleted {} record(s)", rows)))
+                    } else {
+                        Ok(HttpResponse::NotFound().body("Exchange rate not found"))
+                    }
+                }
+                Err(err) => Err(ServiceError::BadRequest(err.to_string())),
+            }
+        }
+        None => Err(ServiceError::Unauthorized),
+    }
+}
+
+/// Accepts the body of an ECB `eurofxref-daily.xml` or `eurofxref.csv` file.
+async fn import_rates(
+    session: Session,
+    body: String,
+    pool: web::Data<PostgresPool>,
+) -> Result<impl Responder, ServiceError> {
+    let user_id: Option<i64> = session.get("user_id").unwrap_or(None);
+    match user_id {
+        Some(_userid) => {
+            session.renew();
+            let result = ExchangeRate::import(&body, pool.get_ref()).await;
+            match result {
+                Ok(rates) => Ok(HttpResponse::Ok().json(rates)),
+                Err(err) => Err(ServiceError::BadRequest(err.to_string())),
+            }
+        }
+        None => Err(ServiceError::Unauthorized),
+    }
+}
+
+async fn find_prices(
+    id: web::Path<i32>,
+    pool: web::Data<PostgresPool>,
+) -> Result<impl Responder, ServiceError> {
+    let result = ProductPrice::find_by_product(id.into_inner(), pool.get_ref()).await;
+    match result {
+        Ok(prices) => Ok(HttpResponse::Ok().json(prices)),
+        _ => Err(ServiceError::BadRequest(
+            "Error trying to read product prices from database".to_string(),
+        )),
+    }
+}
+
+async fn update_price(
+    session: Session,
+    id: web::Path<i32>,
+    input: web::Json<ProductPriceInput>,
+    pool: web::Data<PostgresPool>,
+) -> Result<impl Responder, ServiceError> {
+    let user_id: Option<i64> = session.get("user_id").unwrap_or(None);
+    match user_id {
+        Some(_userid) => {
+            session.renew();
+            let result =
+                ProductPrice::upsert(id.into_inner(), input.into_inner(), pool.get_ref()).await;
+            match result {
+                Ok(price) => Ok(HttpResponse::Ok().json(price)),
+                _ => Err(ServiceError::BadRequest(
+                    "Error trying to set product price".to_string(),
+                )),
+            }
+        }
+        None => Err(ServiceError::Unauthorized),
+    }
+}
+
+async fn delete_price(
+    session: Session,
+    path: web::Path<(i32, String)>,
+    pool: web::Data<PostgresPool>,
+) -> Result<impl Responder, ServiceError> {
+    let user_id: Option<i64> = session.get("user_id").unwrap_or(None);
+    match user_id {
+        Some(_userid) => {
+            session.renew();
+            let (id, currency) = path.into_inner();
+            let result =
+                ProductPrice::delete(id, &currency.to_ascii_uppercase(), pool.get_ref()).await;
+            match result {
+                Ok(rows) => {
+                    if rows > 0 {
+                        Ok(HttpResponse::Ok()
+                            .body(format!("Successfully deleted {} record(s)", rows)))
+                    } else {
+                        Ok(HttpResponse::NotFound().body("Product price not found"))
+                    }
+                }
+                _ => Err(ServiceError::InternalServerError),
+            }
+        }
+        None => Err(ServiceError::Unauthorized),
+    }
+}
+
+pub fn config(cfg: &mut web::ServiceConfig) {
+    cfg.service(
+        web::resource("/exchange-rates")
+            .route(web::get().to(find_all_rates))
+            .route(web::post().to(import_rates)),
+    );
+    cfg.service(
+        web::resource("/exchange-rates/{currency}")
+            .route(web::get().to(find_rate))
+            .route(web::put().to(update_rate))
+            .route(web::delete().to(delete_rate)),
+    );
+    cfg.service(
+        web::resource("/products/{id}/prices")
+            .route(web::get().to(find_prices))
+            .route(web::put().to(update_price)),
+    );
+    cfg.service(
+        web::resource("/products/{id}/prices/{currency}").route(web::delete().to(delete_price)),
+    );
+}
@@ -0,0 +1,153 @@
+use crate::{
+    models::schema, search::SearchBackend, settings::Settings, storage::BlobStore,
+    types::PostgresPool,
+};
+use actix_web::{web, HttpResponse, Responder};
+use serde::Serialize;
+use std::{
+    collections::BTreeMap,
+    future::Future,
+    time::{Duration, Instant},
+};
+
+/// How long a single dependency check may take before it counts as down.
+const CHECK_TIMEOUT: Duration = Duration::from_secs(2);
+
+#[derive(Serialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+enum Status {
+    Up,
+    Down,
+    Disabled,
+}
+
+#[derive(Serialize)]
+struct Check {
+    status: Status,
+    /// a down required dependency makes the service not ready
+    required: bool,
+    latency_ms: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+#[derive(Serialize)]
+struct HealthReport {
+    /// `ok`, `degraded` when an optional dependency is down, or `unavailable`
+    status: &'static str,
+    version: &'static str,
+    checks: BTreeMap<&'static str, Check>,
+}
+
+/// Runs `check` with `CHECK_TIMEOUT`, timing it.
+async fn run_check<F>(required: bool, check: F) -> Check
+where
+    F: Future<Output = Result<(), String>>,
+{
+    let started = Instant::now();
+    let result = match tokio::time::timeout(CHECK_TIMEOUT, check).await {
+        Ok(result) => result,
+        Err(_) => Err(format!("no answer within {}ms", CHECK_TIMEOUT.as_millis())),
+    };
+
+    Check {
+        status: if result.is_ok() {
+            Status::Up
+        } else {
+            Status::Down
+        },
+        required,
+        latency_ms: started.elapsed().as_millis() as u64,
+        error: result.err(),
+    }
+}
+
+async fn check_postgres(pool: &PostgresPool) -> Result<(), String> {
+    sqlx::query("SELECT 1")
+        .execute(pool)
+        .await
+        .map(|_| ())
+        .map_err(|err| err.to_string())
+}
+
+async fn check_migrations(pool: &PostgresPool) -> Result<(), String> {
+    match schema::pending_migrations(pool).await {
+        Ok(pending) if pending.is_empty() => Ok(()),
+        Ok(pending) => Err(format!("pending migrations: {:?}", pending)),
+        Err(err) => Err(err.to_string()),
+    }
+}
+
+/// `GET /health/live`: the process is up and serving requests.
+async fn live() -> impl Responder {
+    HttpResponse::Ok().json(serde_json::json!({ "status": "ok" }))
+}
+
+/// `GET /health/ready`: checks Postgres, migrations, search and the blob store.
+/// Answers 503 when a required dependency is down.
+async fn ready(
+    pool: web::Data<PostgresPool>,
+    search: web::Data<dyn SearchBackend>,
+    store: web::Data<dyn BlobStore>,
+    settings: web::Data<Settings>,
+) -> impl Responder {
+    let pool = pool.get_ref();
+    let search_check = async {
+        if !search.is_enabled() {
+            return Check {
+                status: Status::Disabled,
+                required: false,
+                latency_ms: 0,
+                error: None,
+            };
+        }
+        run_check(settings.search.required, async {
+            search.health().await.map_err(|err| err.to_string())
+        })
+        .await
+    };
+    let (postgres, migrations, storage, search) = futures::join!(
+        run_check(true, check_postgres(pool)),
+        run_check(true, check_migrations(pool)),
+        run_check(true, async {
+            store.check().await.map_err(|err| err.to_string())
+        }),
+        search_check,
+    );
+
+    let mut checks = BTreeMap::new();
+    checks.insert("postgres", postgres);
+    checks.insert("migrations", migrations);
+    checks.insert("storage", storage);
+    checks.insert("search", search);
+
+    let down = |required: bool| {
+        checks
+            .values()
+            .any(|check| check.required == required && check.status == Status::Down)
+    };
+    let status = if down(true) {
+        "unavailable"
+    } else if down(false) {
+        "degraded"
+    } else {
+        "ok"
+    };
+    let report = HealthReport {
+        status,
+        version: env!("CARGO_PKG_VERSION"),
+        checks,
+    };
+
+    if status == "unavailable" {
+        HttpResponse::ServiceUnavailable().json(report)
+    } else {
+        HttpResponse::Ok().json(report)
+    }
+}
+
+pub fn config(cfg: &mut web::ServiceConfig) {
+    cfg.service(web::resource("/health/live").route(web::get().to(live)));
+    cfg.service(web::resource("/health/ready").route(web::get().to(ready)));
+    cfg.service(web::resource("/ready").route(web::get().to(ready)));
+}
@@ -1,10 +1,24 @@
 use crate::errors::ServiceError;
 use crate::{
+    imaging::{self, Fit, RenderOptions, RENDER_SIZES},
     models::images::{Image, ImageInput},
+    settings::ImageConfig,
+    signing,
+    storage::{detect_image_type, BlobStore},
     types::PostgresPool,
+    workers::variants,
 };
-use actix_image::Session;
-use actix_web::{web, HttpResponse, Responder};
+use actix_multipart::Multipart;
+use actix_session::Session;
+use actix_web::{
+    http::header::{self, ETag, EntityTag, Header, IfNoneMatch},
+    web, HttpRequest, HttpResponse, Responder,
+};
+use futures::TryStreamExt;
+use serde::Deserialize;
+use serde_json::Value as JsonValue;
+use sha2::{Digest, Sha256};
+use uuid::Uuid;
 
 async fn find_all(
     session: Session,
@@ -53,7 +67,7 @@ async fn find_by_id(id: web::Path<i32>, pool: web::Data<PostgresPool>) -> impl R
     let result = Image::find_by_id(id.into_inner(), pool.get_ref()).await;
     match result {
         Ok(image) => HttpResponse::Ok().json(image),
-        _ => HttpResponse::NotFound().body("Session not found"),
+        _ => HttpResponse::NotFound().body("Image not found"),
     }
 }
 
@@ -70,7 +84,7 @@ async fn update(
             let result = Image::update(id.into_inner(), input.into_inner(), pool.get_ref()).await;
             match result {
                 Ok(image) => Ok(HttpResponse::Ok().json(image)),
-                _ => Ok(HttpResponse::NotFound().body("Session not found")),
+                _ => Ok(HttpResponse::NotFound().body("Image not found")),
             }
         }
         None => Err(ServiceError::Unauthorized),
@@ -81,19 +95,34 @@ async fn delete(
     session: Session,
     id: web::Path<i32>,
     db_pool: web::Data<PostgresPool>,
+    store: web::Data<dyn BlobStore>,
 ) -> Result<impl Responder, ServiceError> {
     let user_id: Option<i64> = session.get("user_id").unwrap_or(None);
     match user_id {
         Some(_userid) => {
             session.renew();
-            let result = Image::delete(id.into_inner(), db_pool.get_ref()).await;
+            let id = id.into_inner();
+            let stored_keys: Vec<String> = match Image::find_by_id(id, db_pool.get_ref()).await {
+                Ok(image) => image
+                    .storage_key
+                    .into_iter()
+                    .chain(image.variants.0.into_iter().map(|v| v.storage_key))
+                    .collect(),
+                _ => Vec::new(),
+            };
+            let result = Image::delete(id, db_pool.get_ref()).await;
             match result {
                 Ok(rows) => {
                     if rows > 0 {
+                        for key in stored_keys {
+                            if let Err(err) = store.delete(&key).await {
+                                log::warn!("could not remove stored image {}: {}", key, err);
+                            }
+                        }
                         Ok(HttpResponse::Ok()
                             .body(format!("Successfully deleted {} record(s)", rows)))
                     } else {
-                        Ok(HttpResponse::NotFound().body("Session not found"))
+                        Ok(HttpResponse::NotFound().body("Image not found"))
                     }
                 }
                 _ => Err(ServiceError::InternalServerError),
@@ -103,6 +132,372 @@ async fn delete(
     }
 }
 
+/// With render options, e.g. `?w=320&fit=cover`, every uploaded image gets a signed
+/// `renderUrl`.
+async fn find_by_product(
+    id: web::Path<i32>,
+    render: web::Query<RenderQuery>,
+    pool: web::Data<PostgresPool>,
+    images: web::Data<ImageConfig>,
+) -> Result<impl Responder, ServiceError> {
+    let result = Image::find_by_product(id.into_inner(), pool.get_ref()).await;
+    match result {
+        Ok(found) => {
+            let mut json =
+                serde_json::to_value(found).map_err(|_| ServiceError::InternalServerError)?;
+            add_render_urls(&mut json, &render, &images)?;
+            Ok(HttpResponse::Ok().json(json))
+        }
+        _ => Err(ServiceError::BadRequest(
+            "Error trying to read product images from database".to_string(),
+        )),
+    }
+}
+
+#[derive(Default)]
+struct Upload {
+    file: Option<Vec<u8>>,
+    filename: Option<String>,
+    name: Option<String>,
+    position: Option<i32>,
+    is_primary: bool,
+}
+
+/// Reads the parts of an upload; `limit` applies to all of them together.
+async fn read_upload(payload: &mut Multipart, limit: usize) -> Result<Upload, ServiceError> {
+    let mut upload = Upload::default();
+    let mut received = 0;
+
+    while let Some(mut field) = payload
+        .try_next()
+        .await
+        .map_err(|err| ServiceError::BadRequest(err.to_string()))?
+    {
+        let disposition = field.content_disposition();
+        let field_name = disposition.get_name().unwrap_or_default().to_string();
+        let filename = disposition.get_filename().map(|name| name.to_string());
+
+        let mut bytes = Vec::new();
+        while let Some(chunk) = field
+            .try_next()
+            .await
+            .map_err(|err| ServiceError::BadRequest(err.to_string()))?
+        {
+            received += chunk.len();
+            if received > limit {
+                return Err(ServiceError::PayloadTooLarge(format!(
+                    "Uploads are limited to {} bytes",
+                    limit
+                )));
+            }
+            bytes.extend_from_slice(&chunk);
+        }
+
+        let text = || String::from_utf8_lossy(&bytes).trim().to_string();
+        match field_name.as_str() {
+            "file" => {
+                upload.file = Some(bytes);
+                upload.filename = filename;
+            }
+            "name" => upload.name = Some(text()),
+            "position" => {
+                upload.position = Some(text().parse().map_err(|_| {
+                    ServiceError::BadRequest("position must be a number".to_string())
+                })?)
+            }
+            "is_primary" => upload.is_primary = matches!(text().as_str(), "true" | "1" | "on"),
+            _ => {}
+        }
+    }
+
+    Ok(upload)
+}
+
+/// Accepts a `multipart/form-data` body with a `file` part and optional `name`,
+/// `position` and `is_primary` parts.
+async fn upload(
+    session: Session,
+    id: web::Path<i32>,
+    mut payload: Multipart,
+    pool: web::Data<PostgresPool>,
+    store: web::Data<dyn BlobStore>,
+    images: web::Data<ImageConfig>,
+) -> Result<impl Responder, ServiceError> {
+    let user_id: Option<i64> = session.get("user_id").unwrap_or(None);
+
+    match user_id {
+        Some(_id) => {
+            session.renew();
+            let product_id = id.into_inner();
+            let upload = read_upload(&mut payload, images.upload_max_bytes).await?;
+            let bytes = upload
+                .file
+                .ok_or_else(|| ServiceError::BadRequest("Missing file part".to_string()))?;
+            let (content_type, extension) = detect_image_type(&bytes).ok_or_else(|| {
+                ServiceError::BadRequest(
+                    "Only JPEG, PNG, GIF and WebP images are accepted".to_string(),
+                )
+            })?;
+
+            let bytes = web::block(move || imaging::strip_metadata(bytes, content_type))
+                .await
+                .map_err(|_| ServiceError::InternalServerError)?
+                .map_err(|_| ServiceError::BadRequest("Could not read the image".to_string()))?;
+
+            let key = format!("products/{}/{}.{}", product_id, Uuid::new_v4(), extension);
+            if let Err(err) = store.put(&key, bytes, content_type).await {
+                log::error!("could not store image {}: {}", key, err);
+                return Err(ServiceError::InternalServerError);
+            }
+
+            let input = ImageInput {
+                name: upload
+                    .name
+                    .or(upload.filename)
+                    .unwrap_or_else(|| key.clone()),
+                path: store.url(&key),
+                product_id,
+                position: upload.position,
+                is_primary: upload.is_primary,
+                storage_key: Some(key.clone()),
+                content_type: Some(content_type.to_string()),
+            };
+            let result = variants::create_image(input, pool.get_ref()).await;
+            match result {
+                Ok(image) => Ok(HttpResponse::Created().json(image)),
+                _ => {
+                    let _ = store.delete(&key).await;
+                    Err(ServiceError::BadRequest(
+                        "Error trying to create new image".to_string(),
+                    ))
+                }
+            }
+        }
+        None => Err(ServiceError::Unauthorized),
+    }
+}
+
+/// Options of a render, also accepted by public reads that hand out render URLs.
+#[derive(Deserialize)]
+pub struct RenderQuery {
+    w: Option<u32>,
+    h: Option<u32>,
+    fit: Option<String>,
+    format: Option<String>,
+    sig: Option<String>,
+}
+
+impl RenderQuery {
+    /// Validated options plus the canonical form that signatures and ETags are computed over.
+    fn options(&self, id: i32, original: &str) -> Result<(RenderOptions, String), ServiceError> {
+        for size in [self.w, self.h].iter().flatten() {
+            if !RENDER_SIZES.contains(size) {
+                return Err(ServiceError::BadRequest(format!(
+                    "Size {} is not allowed, use one of {:?}",
+                    size, RENDER_SIZES
+                )));
+            }
+        }
+        if self.w.is_none() && self.h.is_none() {
+            return Err(ServiceError::BadRequest("w or h is required".to_string()));
+        }
+
+        let fit = match self.fit.as_deref() {
+            None => Fit::Contain,
+            Some(fit) => Fit::parse(fit)
+                .ok_or_else(|| ServiceError::BadRequest(format!("Unknown fit {}", fit)))?,
+        };
+        let content_type = match self.format.as_deref() {
+            None => match original {
+                "image/jpeg" => "image/jpeg",
+                "image/webp" => "image/webp",
+                _ => "image/png",
+            },
+            Some("webp") => "image/webp",
+            Some("jpeg") | Some("jpg") => "image/jpeg",
+            Some("png") => "image/png",
+            Some(format) => {
+                return Err(ServiceError::BadRequest(format!(
+                    "Unknown format {}",
+                    format
+                )))
+            }
+        };
+
+        let canonical = format!(
+            "{}:{}x{}:{}:{}",
+            id,
+            self.w.unwrap_or(0),
+            self.h.unwrap_or(0),
+            fit.as_str(),
+            self.format.as_deref().unwrap_or("original")
+        );
+        let options = RenderOptions {
+            width: self.w,
+            height: self.h,
+            fit,
+            content_type,
+        };
+        Ok((options, canonical))
+    }
+
+    /// The URL rendering image `id` with these options, signed with `secret`.
+    fn signed_url(&self, id: i32, original: &str, secret: &[u8]) -> Result<String, ServiceError> {
+        let (_, canonical) = self.options(id, original)?;
+
+        let mut params: Vec<String> = Vec::new();
+        if let Some(w) = self.w {
+            params.push(format!("w={}", w));
+        }
+        if let Some(h) = self.h {
+            params.push(format!("h={}", h));
+        }
+        if let Some(ref fit) = self.fit {
+            params.push(format!("fit={}", fit));
+        }
+        if let Some(ref format) = self.format {
+            params.push(format!("format={}", format));
+        }
+        params.push(format!("sig={}", signing::sign(secret, &canonical)));
+
+        Ok(format!("/api/v1/images/{}/render?{}", id, params.join("&")))
+    }
+}
+
+/// Sets `renderUrl` on every uploaded image of `images`, a JSON array of images,
+/// when `query` asks for a width or height. Images recorded by path can't be
+/// rendered and are left alone.
+pub fn add_render_urls(
+    images: &mut JsonValue,
+    query: &RenderQuery,
+    config: &ImageConfig,
+) -> Result<(), ServiceError> {
+    if query.w.is_none() && query.h.is_none() {
+        return Ok(());
+    }
+    for image in images.as_array_mut().into_iter().flatten() {
+        let id = image["id"].as_i64().unwrap_or_default() as i32;
+        let url = match image["content_type"].as_str() {
+            Some(original) => {
+                let secret = config.render_signing_key.as_bytes();
+                JsonValue::String(query.signed_url(id, original, secret)?)
+            }
+            None => JsonValue::Null,
+        };
+        if let Some(image) = image.as_object_mut() {
+            image.insert("renderUrl".to_string(), url);
+        }
+    }
+    Ok(())
+}
+
+/// `GET /images/{id}/render?w=400&h=300&fit=cover&format=webp&sig=…`, where `sig`
+/// comes from `render_url`.
+async fn render(
+    req: HttpRequest,
+    id: web::Path<i32>,
+    query: web::Query<RenderQuery>,
+    pool: web::Data<PostgresPool>,
+    store: web::Data<dyn BlobStore>,
+    images: web::Data<ImageConfig>,
+) -> Result<HttpResponse, ServiceError> {
+    let id = id.into_inner();
+    let image = match Image::find_by_id(id, pool.get_ref()).await {
+        Ok(image) => image,
+        _ => return Ok(HttpResponse::NotFound().body("Image not found")),
+    };
+    let (key, original_type) = match (image.storage_key, image.content_type) {
+        (Some(key), Some(content_type)) => (key, content_type),
+        _ => return Ok(HttpResponse::NotFound().body("Image has no stored file")),
+    };
+
+    let (options, canonical) = query.options(id, &original_type)?;
+    let signature = query.sig.as_deref().unwrap_or_default();
+    if !signing::verify(images.render_signing_key.as_bytes(), &canonical, signature) {
+        return Err(ServiceError::Forbidden);
+    }
+
+    // renders are deterministic, so the stored original plus the options identify the bytes
+    let digest = hex::encode(Sha256::digest(format!("{}|{}", key, canonical).as_bytes()));
+    let etag = EntityTag::new_strong(digest[..32].to_string());
+    let cache_control = "public, max-age=31536000, immutable";
+
+    let fresh = match IfNoneMatch::parse(&req) {
+        Ok(IfNoneMatch::Any) => true,
+        Ok(IfNoneMatch::Items(tags)) => tags.iter().any(|tag| tag.strong_eq(&etag)),
+        Err(_) => false,
+    };
+    if fresh {
+        return Ok(HttpResponse::NotModified()
+            .insert_header(ETag(etag))
+            .insert_header((header::CACHE_CONTROL, cache_control))
+            .finish());
+    }
+
+    let content_type = options.content_type;
+    let extension = match content_type {
+        "image/jpeg" => "jpg",
+        "image/webp" => "webp",
+        _ => "png",
+    };
+    let stem = key.rsplit_once('.').map(|(stem, _)| stem).unwrap_or(&key);
+    let cache_key = format!("{}-render-{}.{}", stem, &digest[..16], extension);
+
+    let bytes = match store.get(&cache_key).await {
+        Ok(bytes) => bytes,
+        Err(_) => {
+            let original = store.get(&key).await.map_err(|err| {
+                log::error!("could not read stored image {}: {}", key, err);
+                ServiceError::InternalServerError
+            })?;
+            let bytes = web::block(move || imaging::render(&original, &options))
+                .await
+                .map_err(|_| ServiceError::InternalServerError)?
+                .map_err(|err| {
+                    log::error!("rendering image {} failed: {}", id, err);
+                    ServiceError::InternalServerError
+                })?;
+            if let Err(err) = store.put(&cache_key, bytes.clone(), content_type).await {
+                log::warn!("could not cache render {}: {}", cache_key, err);
+            }
+            bytes
+        }
+    };
+
+    Ok(HttpResponse::Ok()
+        .content_type(content_type)
+        .insert_header(ETag(etag))
+        .insert_header((header::CACHE_CONTROL, cache_control))
+        .body(bytes))
+}
+
+/// Returns a signed render URL for the given options.
+async fn render_url(
+    session: Session,
+    id: web::Path<i32>,
+    query: web::Query<RenderQuery>,
+    pool: web::Data<PostgresPool>,
+    images: web::Data<ImageConfig>,
+) -> Result<impl Responder, ServiceError> {
+    let user_id: Option<i64> = session.get("user_id").unwrap_or(None);
+
+    match user_id {
+        Some(_id) => {
+            session.renew();
+            let id = id.into_inner();
+            let image = match Image::find_by_id(id, pool.get_ref()).await {
+                Ok(image) => image,
+                _ => return Ok(HttpResponse::NotFound().body("Image not found")),
+            };
+            let original_type = image.content_type.unwrap_or_default();
+            let secret = images.render_signing_key.as_bytes();
+            let url = query.signed_url(id, &original_type, secret)?;
+            Ok(HttpResponse::Ok().json(serde_json::json!({ "url": url })))
+        }
+        None => Err(ServiceError::Unauthorized),
+    }
+}
+
 pub fn config(cfg: &mut web::ServiceConfig) {
     cfg.service(
         web::resource("/images")
@@ -115,4 +510,394 @@ pub fn config(cfg: &mut web::ServiceConfig) {
             .route(web::put().to(update))
             .route(web::delete().to(delete)),
     );
+    cfg.service(web::resource("/images/{id}/render").route(web::get().to(render)));
+    cfg.service(web::resource("/images/{id}/render-url").route(web::get().to(render_url)));
+    cfg.service(
+        web::resource("/products/{id}/images")
+            .route(web::get().to(find_by_product))
+            .route(web::post().to(upload)),
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        models::products::{Product, ProductInput},
+        storage::LocalStore,
+        test_support,
+    };
+    use actix_web::{http::StatusCode, test, App};
+    use image::{DynamicImage, GenericImageView, ImageOutputFormat};
+    use std::{io::Cursor, path::PathBuf, sync::Arc};
+
+    const BOUNDARY: &str = "shopapi-test-boundary";
+
+    macro_rules! app {
+        ($pool:expr, $store:expr, $upload_max_bytes:expr) => {
+            test::init_service(
+                App::new()
+                    .wrap(test_support::session())
+                    .app_data(web::Data::new($pool))
+                    .app_data(web::Data::from($store))
+                    .app_data(web::Data::new(ImageConfig {
+                        upload_max_bytes: $upload_max_bytes,
+                        render_signing_key: "test-render-signing-key".to_string(),
+                    }))
+                    .configure(test_support::config)
+                    .service(web::scope("/api/v1").configure(config)),
+            )
+            .await
+        };
+    }
+
+    fn temp_store() -> (PathBuf, Arc<dyn BlobStore>) {
+        let root = std::env::temp_dir().join(format!("shopapi-images-{}", Uuid::new_v4()));
+        let store: Arc<dyn BlobStore> = Arc::new(LocalStore::new(&root, "/static/uploads"));
+        (root, store)
+    }
+
+    /// A small JPEG carrying an (empty) EXIF segment.
+    fn jpeg_with_exif() -> Vec<u8> {
+        let mut jpeg = Vec::new();
+        DynamicImage::new_rgb8(8, 8)
+            .write_to(&mut Cursor::new(&mut jpeg), ImageOutputFormat::Jpeg(90))
+            .unwrap();
+
+        let exif: &[u8] = b"Exif\0\0II*\0\x08\0\0\0\0\0\0\0\0\0";
+        let mut segment = vec![0xFF, 0xE1];
+        segment.extend_from_slice(&((exif.len() + 2) as u16).to_be_bytes());
+        segment.extend_from_slice(exif);
+        jpeg.splice(2..2, segment);
+        jpeg
+    }
+
+    /// A `multipart/form-data` body with a single `file` part.
+    fn multipart(filename: &str, bytes: &[u8]) -> Vec<u8> {
+        let mut body = format!(
+            "--{}\r\nContent-Disposition: form-data; name=\"file\"; filename=\"{}\"\r\n\
+             Content-Type: application/octet-stream\r\n\r\n",
+            BOUNDARY, filename
+        )
+        .into_bytes();
+        body.extend_from_slice(bytes);
+        body.extend_from_slice(format!("\r\n--{}--\r\n", BOUNDARY).as_bytes());
+        body
+    }
+
+    fn upload_request(product_id: i32, filename: &str, bytes: &[u8]) -> test::TestRequest {
+        test::TestRequest::post()
+            .uri(&format!("/api/v1/products/{}/images", product_id))
+            .insert_header((
+                header::CONTENT_TYPE,
+                format!("multipart/form-data; boundary={}", BOUNDARY),
+            ))
+            .set_payload(multipart(filename, bytes))
+    }
+
+    async fn create_product(pool: &PostgresPool) -> Product {
+        let input = ProductInput {
+            name: "Test coffee".to_string(),
+            price: 1200,
+            origin: "Test origin".to_string(),
+            cultivar: "Test cultivar".to_string(),
+            currency: None,
+            attributes: None,
+            category_ids: None,
+        };
+        Product::create(input, pool).await.unwrap()
+    }
+
+    #[actix_web::test]
+    async fn upload_requires_login() {
+        let (_, store) = temp_store();
+        let app = app!(test_support::unused_pool(), store, 1024 * 1024);
+
+        let response =
+            test::call_service(&app, upload_request(1, "a.jpg", b"x").to_request()).await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[actix_web::test]
+    async fn upload_rejects_files_that_are_not_images() {
+        let (_, store) = temp_store();
+        let app = app!(test_support::unused_pool(), store, 1024 * 1024);
+        let login = test::call_service(&app, test_support::login_request(1).to_request()).await;
+        let cookie = test_support::session_cookie(&login);
+
+        // the name and the part's content type claim an image, the bytes don't
+        let request = upload_request(1, "photo.jpg", b"<html><body>not an image</body></html>")
+            .cookie(cookie)
+            .to_request();
+        let response = test::call_service(&app, request).await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[actix_web::test]
+    async fn upload_rejects_oversize_files() {
+        let (_, store) = temp_store();
+        let app = app!(test_support::unused_pool(), store, 1024);
+        let login = test::call_service(&app, test_support::login_request(1).to_request()).await;
+        let cookie = test_support::session_cookie(&login);
+
+        let mut bytes = vec![0xFF, 0xD8, 0xFF];
+        bytes.resize(4096, 0);
+        let request = upload_request(1, "big.jpg", &bytes)
+            .cookie(cookie)
+            .to_request();
+        let response = test::call_service(&app, request).await;
+        assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+    }
+
+    #[actix_web::test]
+    async fn upload_limits_all_parts_together() {
+        let (_, store) = temp_store();
+        let app = app!(test_support::unused_pool(), store, 1024);
+        let login = test::call_service(&app, test_support::login_request(1).to_request()).await;
+        let cookie = test_support::session_cookie(&login);
+
+        // each part fits the limit, both together don't
+        let mut body = format!(
+            "--{}\r\nContent-Disposition: form-data; name=\"name\"\r\n\r\n{}\r\n",
+            BOUNDARY,
+            "n".repeat(700)
+        )
+        .into_bytes();
+        let mut bytes = vec![0xFF, 0xD8, 0xFF];
+        bytes.resize(700, 0);
+        body.extend_from_slice(&multipart("small.jpg", &bytes));
+        let request = upload_request(1, "small.jpg", &bytes)
+            .set_payload(body)
+            .cookie(cookie)
+            .to_request();
+        let response = test::call_service(&app, request).await;
+        assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+    }
+
+    #[actix_web::test]
+    #[ignore = "needs Postgres at TEST_DATABASE_URL"]
+    async fn upload_strips_exif_and_queues_variants() {
+        let pool = test_support::pool().await;
+        let (root, store) = temp_store();
+        let app = app!(pool.clone(), store.clone(), 1024 * 1024);
+        let login = test::call_service(&app, test_support::login_request(1).to_request()).await;
+        let cookie = test_support::session_cookie(&login);
+        let product = create_product(&pool).await;
+
+        // named .png, but the magic bytes decide
+        let request = upload_request(product.id, "photo.png", &jpeg_with_exif())
+            .cookie(cookie)
+            .to_request();
+        let response = test::call_service(&app, request).await;
+        assert_eq!(response.status(), StatusCode::CREATED);
+        let created: serde_json::Value = test::read_body_json(response).await;
+        assert_eq!(created["content_type"], "image/jpeg");
+
+        let image = Image::find_by_id(created["id"].as_i64().unwrap() as i32, &pool)
+            .await
+            .unwrap();
+        let key = image.storage_key.unwrap();
+        assert!(key.ends_with(".jpg"));
+        let stored = store.get(&key).await.unwrap();
+        assert!(stored.starts_with(&[0xFF, 0xD8, 0xFF]));
+        assert!(!stored.windows(4).any(|window| window == b"Exif"));
+
+        let queued: i64 = sqlx::query_scalar(
+            "SELECT COUNT(*) FROM jobs WHERE kind = 'generate_variants' AND payload->>'image_id' = $1",
+        )
+        .bind(image.id.to_string())
+        .fetch_one(&pool)
+        .await
+        .unwrap();
+        assert_eq!(queued, 1);
+
+        // the new image changes the product's search document
+        let published: i64 = sqlx::query_scalar(
+            "SELECT COUNT(*) FROM outbox WHERE event_type = 'product.updated' AND aggregate_id = $1",
+        )
+        .bind(product.id.to_string())
+        .fetch_one(&pool)
+        .await
+        .unwrap();
+        assert_eq!(published, 1);
+
+        let _ = std::fs::remove_dir_all(root);
+    }
+
+    /// A 16x8 PNG.
+    fn small_png() -> Vec<u8> {
+        let mut png = Vec::new();
+        DynamicImage::new_rgb8(16, 8)
+            .write_to(&mut Cursor::new(&mut png), ImageOutputFormat::Png)
+            .unwrap();
+        png
+    }
+
+    /// Lists a product's images with render `options`.
+    fn list_request(product_id: i32, options: &str) -> test::TestRequest {
+        test::TestRequest::get().uri(&format!(
+            "/api/v1/products/{}/images?{}",
+            product_id, options
+        ))
+    }
+
+    #[actix_web::test]
+    #[ignore = "needs Postgres at TEST_DATABASE_URL"]
+    async fn render_requires_a_valid_signature() {
+        let pool = test_support::pool().await;
+        let (root, store) = temp_store();
+        let app = app!(pool.clone(), store, 1024 * 1024);
+        let login = test::call_service(&app, test_support::login_request(1).to_request()).await;
+        let product = create_product(&pool).await;
+        let request = upload_request(product.id, "small.png", &small_png())
+            .cookie(test_support::session_cookie(&login))
+            .to_request();
+        assert_eq!(
+            test::call_service(&app, request).await.status(),
+            StatusCode::CREATED
+        );
+        let product_id = product.id;
+
+        let images: serde_json::Value = test::call_and_read_body_json(
+            &app,
+            list_request(product_id, "w=80&h=80&fit=cover").to_request(),
+        )
+        .await;
+        let url = images[0]["renderUrl"].as_str().unwrap().to_string();
+        let response =
+            test::call_service(&app, test::TestRequest::get().uri(&url).to_request()).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            response.headers().get(header::CACHE_CONTROL).unwrap(),
+            "public, max-age=31536000, immutable"
+        );
+        let etag = response.headers().get(header::ETAG).unwrap().clone();
+        let rendered = test::read_body(response).await;
+        assert_eq!(
+            image::load_from_memory(&rendered).unwrap().dimensions(),
+            (80, 80)
+        );
+
+        let request = test::TestRequest::get()
+            .uri(&url)
+            .insert_header((header::IF_NONE_MATCH, etag))
+            .to_request();
+        let response = test::call_service(&app, request).await;
+        assert_eq!(response.status(), StatusCode::NOT_MODIFIED);
+
+        // the signature covers the options and can't be left out
+        let resized = url.replace("w=80&h=80", "w=160&h=160");
+        let unsigned = url.split("&sig=").next().unwrap().to_string();
+        for url in [resized, unsigned] {
+            let response =
+                test::call_service(&app, test::TestRequest::get().uri(&url).to_request()).await;
+            assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        }
+
+        let _ = std::fs::remove_dir_all(root);
+    }
+
+    #[actix_web::test]
+    #[ignore = "needs Postgres at TEST_DATABASE_URL"]
+    async fn render_allows_listed_sizes_and_fits_only() {
+        let pool = test_support::pool().await;
+        let (root, store) = temp_store();
+        let app = app!(pool.clone(), store, 1024 * 1024);
+        let login = test::call_service(&app, test_support::login_request(1).to_request()).await;
+        let product = create_product(&pool).await;
+        let request = upload_request(product.id, "small.png", &small_png())
+            .cookie(test_support::session_cookie(&login))
+            .to_request();
+        assert_eq!(
+            test::call_service(&app, request).await.status(),
+            StatusCode::CREATED
+        );
+        let product_id = product.id;
+
+        for options in [
+            "w=81",
+            "w=160&h=100000",
+            "w=160&fit=stretch",
+            "w=160&format=bmp",
+        ] {
+            let response =
+                test::call_service(&app, list_request(product_id, options).to_request()).await;
+            assert_eq!(response.status(), StatusCode::BAD_REQUEST, "{}", options);
+        }
+        // the size is checked before the signature
+        let images: serde_json::Value =
+            test::call_and_read_body_json(&app, list_request(product_id, "").to_request()).await;
+        let request = test::TestRequest::get()
+            .uri(&format!(
+                "/api/v1/images/{}/render?w=81&sig=00",
+                images[0]["id"]
+            ))
+            .to_request();
+        let response = test::call_service(&app, request).await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+        // the original is 16x8
+        for (fit, dimensions) in [
+            ("cover", (160, 160)),
+            ("contain", (160, 80)),
+            ("fill", (160, 160)),
+        ] {
+            let options = format!("w=160&h=160&fit={}", fit);
+            let images: serde_json::Value = test::call_and_read_body_json(
+                &app,
+                list_request(product_id, &options).to_request(),
+            )
+            .await;
+            let url = images[0]["renderUrl"].as_str().unwrap();
+            let rendered =
+                test::call_and_read_body(&app, test::TestRequest::get().uri(url).to_request())
+                    .await;
+            assert_eq!(
+                image::load_from_memory(&rendered).unwrap().dimensions(),
+                dimensions,
+                "{}",
+                fit
+            );
+        }
+        let images: serde_json::Value = test::call_and_read_body_json(
+            &app,
+            list_request(product_id, "h=160&format=webp").to_request(),
+        )
+        .await;
+        let url = images[0]["renderUrl"].as_str().unwrap();
+        let response =
+            test::call_service(&app, test::TestRequest::get().uri(url).to_request()).await;
+        assert_eq!(
+            response.headers().get(header::CONTENT_TYPE).unwrap(),
+            "image/webp"
+        );
+
+        let _ = std::fs::remove_dir_all(root);
+    }
+
+    #[actix_web::test]
+    #[ignore = "needs Postgres at TEST_DATABASE_URL"]
+    async fn create_records_an_image_by_path() {
+        let pool = test_support::pool().await;
+        let (_, store) = temp_store();
+        let app = app!(pool.clone(), store, 1024 * 1024);
+        let login = test::call_service(&app, test_support::login_request(1).to_request()).await;
+        let cookie = test_support::session_cookie(&login);
+        let product = create_product(&pool).await;
+
+        let request = test::TestRequest::post()
+            .uri("/api/v1/images")
+            .cookie(cookie)
+            .set_json(serde_json::json!({
+                "name": "Front",
+                "path": "https://cdn.example.com/front.jpg",
+                "productId": product.id,
+            }))
+            .to_request();
+        let response = test::call_service(&app, request).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let created: serde_json::Value = test::read_body_json(response).await;
+        assert_eq!(created["path"], "https://cdn.example.com/front.jpg");
+        assert_eq!(created["productId"], product.id);
+    }
 }
@@ -0,0 +1,71 @@
+use crate::errors::ServiceError;
+use crate::{
+    auth,
+    models::jobs::{JobFilter, JobRecord},
+    types::PostgresPool,
+};
+use actix_session::Session;
+use actix_web::{web, HttpResponse, Responder};
+
+/// Lists jobs, newest first. Takes `status`, `kind`, `limit` and `offset` parameters.
+async fn find_all(
+    session: Session,
+    filter: web::Query<JobFilter>,
+    pool: web::Data<PostgresPool>,
+) -> Result<impl Responder, ServiceError> {
+    auth::require_admin(&session, pool.get_ref()).await?;
+    match JobRecord::find_all(&filter, pool.get_ref()).await {
+        Ok(jobs) => Ok(HttpResponse::Ok().json(jobs)),
+        _ => Err(ServiceError::InternalServerError),
+    }
+}
+
+async fn find(
+    session: Session,
+    id: web::Path<i64>,
+    pool: web::Data<PostgresPool>,
+) -> Result<impl Responder, ServiceError> {
+    auth::require_admin(&session, pool.get_ref()).await?;
+    match JobRecord::find_by_id(id.into_inner(), pool.get_ref()).await {
+        Ok(job) => Ok(HttpResponse::Ok().json(job)),
+        _ => Ok(HttpResponse::NotFound().body("Job not found")),
+    }
+}
+
+/// Runs a dead, cancelled or scheduled job now.
+async fn retry(
+    session: Session,
+    id: web::Path<i64>,
+    pool: web::Data<PostgresPool>,
+) -> Result<impl Responder, ServiceError> {
+    auth::require_admin(&session, pool.get_ref()).await?;
+    match JobRecord::retry(id.into_inner(), pool.get_ref()).await {
+        Ok(Some(job)) => Ok(HttpResponse::Ok().json(job)),
+        Ok(None) => Err(ServiceError::BadRequest(
+            "Only pending, dead or cancelled jobs can be retried".to_string(),
+        )),
+        Err(err) => Err(ServiceError::BadRequest(err.to_string())),
+    }
+}
+
+async fn cancel(
+    session: Session,
+    id: web::Path<i64>,
+    pool: web::Data<PostgresPool>,
+) -> Result<impl Responder, ServiceError> {
+    auth::require_admin(&session, pool.get_ref()).await?;
+    match JobRecord::cancel(id.into_inner(), pool.get_ref()).await {
+        Ok(Some(job)) => Ok(HttpResponse::Ok().json(job)),
+        Ok(None) => Err(ServiceError::BadRequest(
+            "Only pending jobs can be cancelled".to_string(),
+        )),
+        _ => Err(ServiceError::InternalServerError),
+    }
+}
+
+pub fn config(cfg: &mut web::ServiceConfig) {
+    cfg.service(web::resource("/admin/jobs").route(web::get().to(find_all)));
+    cfg.service(web::resource("/admin/jobs/{id}").route(web::get().to(find)));
+    cfg.service(web::resource("/admin/jobs/{id}/retry").route(web::post().to(retry)));
+    cfg.service(web::resource("/admin/jobs/{id}/cancel").route(web::post().to(cancel)));
+}
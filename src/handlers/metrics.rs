@@ -0,0 +1,38 @@
+use crate::errors::ServiceError;
+use crate::{metrics::Metrics, settings::Settings, types::PostgresPool};
+use actix_web::{http::header, web, HttpRequest, HttpResponse};
+
+/// `GET /metrics` in the Prometheus text format. Requires
+/// `Authorization: Bearer <metrics.token>` when a token is configured.
+async fn metrics(
+    req: HttpRequest,
+    metrics: web::Data<Metrics>,
+    pool: web::Data<PostgresPool>,
+    settings: web::Data<Settings>,
+) -> Result<HttpResponse, ServiceError> {
+    if let Some(ref token) = settings.metrics.token {
+        let authorized = req
+            .headers()
+            .get(header::AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .is_some_and(|given| given == token);
+        if !authorized {
+            return Err(ServiceError::Unauthorized);
+        }
+    }
+
+    match metrics.render(pool.get_ref()) {
+        Ok(body) => Ok(HttpResponse::Ok()
+            .content_type("text/plain; version=0.0.4")
+            .body(body)),
+        Err(err) => {
+            log::error!("rendering metrics failed: {}", err);
+            Err(ServiceError::InternalServerError)
+        }
+    }
+}
+
+pub fn config(cfg: &mut web::ServiceConfig) {
+    cfg.service(web::resource("/metrics").route(web::get().to(metrics)));
+}
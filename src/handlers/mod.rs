@@ -2,4 +2,15 @@ pub mod users;
 pub mod products;
 pub mod orders;
 pub mod auth;
-pub mod search;
\ No newline at end of file
+pub mod search;
+pub mod currencies;
+pub mod categories;
+pub mod attributes;
+pub mod images;
+pub mod health;
+pub mod metrics;
+pub mod search_settings;
+pub mod jobs;
+pub mod webhooks;
+pub mod order_events;
+pub mod shipments;
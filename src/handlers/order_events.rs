@@ -0,0 +1,124 @@
+use crate::errors::ServiceError;
+use crate::{
+    models::{orders::Order, shipments::Shipment},
+    types::PostgresPool,
+    workers::order_feed::OrderFeed,
+};
+use actix_session::Session;
+use actix_web::{
+    http::header,
+    rt,
+    web::{self, Bytes},
+    HttpRequest, HttpResponse, Responder,
+};
+use actix_ws::Message;
+use futures::{future, stream, StreamExt};
+use serde_json::json;
+use std::time::Duration;
+
+/// How long a connection may stay silent before a keep-alive is sent, so that
+/// proxies don't drop it.
+const KEEP_ALIVE: Duration = Duration::from_secs(15);
+
+fn sse_message(id: Option<i64>, event: &str, data: &str) -> Bytes {
+    let id = id.map(|id| format!("id: {}\n", id)).unwrap_or_default();
+    Bytes::from(format!("{}event: {}\ndata: {}\n\n", id, event, data))
+}
+
+/// Streams an order's events as server-sent events, starting with a `snapshot`
+/// of the order and its shipments. Every event is named after its type, e.g.
+/// `order.status_changed`, and carries the JSON webhooks get. Open to anyone
+/// who can read the order.
+async fn stream_order(
+    id: web::Path<i32>,
+    pool: web::Data<PostgresPool>,
+    feed: web::Data<OrderFeed>,
+) -> Result<impl Responder, ServiceError> {
+    let order_id = id.into_inner();
+    // subscribed before the snapshot is read, so no change falls in between
+    let subscription = feed.subscribe(Some(order_id));
+    let order = match Order::find_by_id(order_id, pool.get_ref()).await {
+        Ok(order) => order,
+        _ => return Ok(HttpResponse::NotFound().body("Order not found")),
+    };
+    let shipments = match Shipment::find_by_order(order_id, pool.get_ref()).await {
+        Ok(shipments) => shipments,
+        _ => return Err(ServiceError::InternalServerError),
+    };
+    let snapshot = json!({ "order": order, "shipments": shipments });
+    let snapshot = sse_message(None, "snapshot", &snapshot.to_string());
+
+    let events = stream::unfold(subscription, |mut subscription| async move {
+        let message = tokio::select! {
+            event = subscription.next() => {
+                let event = event?;
+                sse_message(Some(event.id), &event.event_type, &event.json)
+            }
+            _ = rt::time::sleep(KEEP_ALIVE) => Bytes::from_static(b": keep-alive\n\n"),
+        };
+        Some((Ok::<_, actix_web::Error>(message), subscription))
+    });
+
+    Ok(HttpResponse::Ok()
+        .content_type("text/event-stream")
+        .insert_header((header::CACHE_CONTROL, "no-cache"))
+        // keeps nginx from buffering the stream
+        .insert_header(("X-Accel-Buffering", "no"))
+        .streaming(stream::once(future::ready(Ok(snapshot))).chain(events)))
+}
+
+/// A WebSocket sending the events of every order as text messages, for staff
+/// dashboards. Requires the same login as listing orders.
+async fn stream_all(
+    req: HttpRequest,
+    body: web::Payload,
+    session: Session,
+    feed: web::Data<OrderFeed>,
+) -> Result<impl Responder, ServiceError> {
+    let user_id: Option<i64> = session.get("user_id").unwrap_or(None);
+    match user_id {
+        Some(_userid) => {
+            session.renew();
+            let (response, mut socket, mut messages) = actix_ws::handle(&req, body)
+                .map_err(|err| ServiceError::BadRequest(err.to_string()))?;
+            let mut subscription = feed.subscribe(None);
+
+            rt::spawn(async move {
+                loop {
+                    tokio::select! {
+                        event = subscription.next() => match event {
+                            Some(event) => {
+                                if socket.text(event.json).await.is_err() {
+                                    return;
+                                }
+                            }
+                            None => break,
+                        },
+                        message = messages.next() => match message {
+                            Some(Ok(Message::Ping(bytes)))
+                                if socket.pong(&bytes).await.is_err() => return,
+                            Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                            _ => {}
+                        },
+                        _ = rt::time::sleep(KEEP_ALIVE) => {
+                            if socket.ping(b"").await.is_err() {
+                                return;
+                            }
+                        }
+                    }
+                }
+                let _ = socket.close(None).await;
+            });
+
+            Ok(response)
+        }
+        None => Err(ServiceError::Unauthorized),
+    }
+}
+
+/// Has to be registered before `orders::config`, whose `/orders/{id}` would
+/// otherwise take `/orders/events`.
+pub fn config(cfg: &mut web::ServiceConfig) {
+    cfg.service(web::resource("/orders/events").route(web::get().to(stream_all)));
+    cfg.service(web::resource("/orders/{id}/events").route(web::get().to(stream_order)));
+}
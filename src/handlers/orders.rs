@@ -1,10 +1,12 @@
 use crate::errors::ServiceError;
 use crate::{
-    models::orders::{Order, OrderInput},
+    handlers::currencies::requested_currency,
+    metrics::Metrics,
+    models::orders::{Order, OrderInput, OrderStatusInput},
     types::PostgresPool,
 };
 use actix_session::Session;
-use actix_web::{web, HttpResponse, Responder};
+use actix_web::{web, HttpRequest, HttpResponse, Responder};
 
 async fn find_all(
     session: Session,
@@ -28,18 +30,27 @@ async fn find_all(
 }
 
 async fn create(
+    req: HttpRequest,
     session: Session,
     input: web::Json<OrderInput>,
     pool: web::Data<PostgresPool>,
+    metrics: web::Data<Metrics>,
 ) -> Result<impl Responder, ServiceError> {
     let user_id: Option<i64> = session.get("user_id").unwrap_or(None);
 
     match user_id {
         Some(_id) => {
             session.renew();
-            let result = Order::create(input.into_inner(), pool.get_ref()).await;
+            let mut input = input.into_inner();
+            if input.currency.is_none() {
+                input.currency = requested_currency(&req)?;
+            }
+            let result = Order::create(input, pool.get_ref()).await;
             match result {
-                Ok(order) => Ok(HttpResponse::Ok().json(order)),
+                Ok(order) => {
+                    metrics.order_created(&order.currency);
+                    Ok(HttpResponse::Ok().json(order))
+                }
                 _ => Err(ServiceError::BadRequest(
                     "Error trying to create new order".to_string(),
                 )),
@@ -77,6 +88,31 @@ async fn update(
     }
 }
 
+async fn update_status(
+    session: Session,
+    id: web::Path<i32>,
+    input: web::Json<OrderStatusInput>,
+    pool: web::Data<PostgresPool>,
+) -> Result<impl Responder, ServiceError> {
+    let user_id: Option<i64> = session.get("user_id").unwrap_or(None);
+    match user_id {
+        Some(_userid) => {
+            session.renew();
+            let result = Order::update_status(id.into_inner(), &input.status, pool.get_ref()).await;
+            match result {
+                Ok(order) => Ok(HttpResponse::Ok().json(order)),
+                Err(err) => match err.downcast_ref::<sqlx::Error>() {
+                    Some(sqlx::Error::RowNotFound) => {
+                        Ok(HttpResponse::NotFound().body("Order not found"))
+                    }
+                    _ => Err(ServiceError::BadRequest(err.to_string())),
+                },
+            }
+        }
+        None => Err(ServiceError::Unauthorized),
+    }
+}
+
 async fn delete(
     session: Session,
     id: web::Path<i32>,
@@ -115,4 +151,5 @@ pub fn config(cfg: &mut web::ServiceConfig) {
             .route(web::put().to(update))
             .route(web::delete().to(delete)),
     );
+    cfg.service(web::resource("/orders/{id}/status").route(web::put().to(update_status)));
 }
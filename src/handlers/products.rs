@@ -1,15 +1,43 @@
 use crate::errors::ServiceError;
 use crate::{
+    handlers::{
+        currencies::requested_currency,
+        images::{add_render_urls, RenderQuery},
+    },
+    models::attributes::AttributeDefinition,
     models::products::{Product, ProductInput},
+    settings::ImageConfig,
     types::PostgresPool,
 };
 use actix_session::Session;
-use actix_web::{web, HttpResponse, Responder};
+use actix_web::{web, HttpRequest, HttpResponse, Responder};
+use std::collections::HashMap;
 
-async fn find_all(pool: web::Data<PostgresPool>) -> Result<impl Responder, ServiceError> {
-    let result = Product::find_all(pool.get_ref()).await;
+/// Render options in the query, e.g. `?w=320&fit=cover`, add a signed `renderUrl`
+/// to the uploaded images, see `handlers::images::add_render_urls`.
+async fn find_all(
+    req: HttpRequest,
+    params: web::Query<HashMap<String, String>>,
+    render: web::Query<RenderQuery>,
+    pool: web::Data<PostgresPool>,
+    images: web::Data<ImageConfig>,
+) -> Result<impl Responder, ServiceError> {
+    let filter = AttributeDefinition::filter_from_query(&params, pool.get_ref())
+        .await
+        .map_err(|err| ServiceError::BadRequest(err.to_string()))?;
+    let result = match requested_currency(&req)? {
+        Some(currency) => Product::find_all_in(&currency, filter.as_ref(), pool.get_ref()).await,
+        None => Product::find_all(filter.as_ref(), pool.get_ref()).await,
+    };
     match result {
-        Ok(products) => Ok(HttpResponse::Ok().json(products)),
+        Ok(products) => {
+            let mut json =
+                serde_json::to_value(products).map_err(|_| ServiceError::InternalServerError)?;
+            for product in json.as_array_mut().into_iter().flatten() {
+                add_render_urls(&mut product["images"], &render, &images)?;
+            }
+            Ok(HttpResponse::Ok().json(json))
+        }
         _ => Err(ServiceError::BadRequest(
             "Error trying to read all products from database".to_string(),
         )),
@@ -29,20 +57,36 @@ async fn create(
             let result = Product::create(input.into_inner(), pool.get_ref()).await;
             match result {
                 Ok(product) => Ok(HttpResponse::Ok().json(product)),
-                _ => Err(ServiceError::BadRequest(
-                    "Error trying to create new product".to_string(),
-                )),
+                Err(err) => Err(ServiceError::BadRequest(err.to_string())),
             }
         }
         None => Err(ServiceError::Unauthorized),
     }
 }
 
-async fn find_by_id(id: web::Path<i32>, pool: web::Data<PostgresPool>) -> impl Responder {
-    let result = Product::find_by_id(id.into_inner(), pool.get_ref()).await;
+async fn find_by_id(
+    req: HttpRequest,
+    id: web::Path<i32>,
+    render: web::Query<RenderQuery>,
+    pool: web::Data<PostgresPool>,
+    images: web::Data<ImageConfig>,
+) -> Result<impl Responder, ServiceError> {
+    let result = match requested_currency(&req)? {
+        Some(currency) => Product::find_by_id_in(id.into_inner(), &currency, pool.get_ref()).await,
+        None => Product::find_by_id(id.into_inner(), pool.get_ref()).await,
+    };
     match result {
-        Ok(product) => HttpResponse::Ok().json(product),
-        _ => HttpResponse::NotFound().body("Product not found"),
+        Ok(product) => {
+            let mut json =
+                serde_json::to_value(product).map_err(|_| ServiceError::InternalServerError)?;
+            add_render_urls(&mut json["images"], &render, &images)?;
+            Ok(HttpResponse::Ok().json(json))
+        }
+        Err(err) => match err.downcast_ref::<sqlx::Error>() {
+            Some(_) => Ok(HttpResponse::NotFound().body("Product not found")),
+            // the product exists but can't be shown in the requested currency
+            None => Err(ServiceError::BadRequest(err.to_string())),
+        },
     }
 }
 
@@ -59,7 +103,12 @@ async fn update(
             let result = Product::update(id.into_inner(), input.into_inner(), pool.get_ref()).await;
             match result {
                 Ok(product) => Ok(HttpResponse::Ok().json(product)),
-                _ => Ok(HttpResponse::NotFound().body("Product not found")),
+                Err(err) => match err.downcast_ref::<sqlx::Error>() {
+                    Some(sqlx::Error::RowNotFound) => {
+                        Ok(HttpResponse::NotFound().body("Product not found"))
+                    }
+                    _ => Err(ServiceError::BadRequest(err.to_string())),
+                },
             }
         }
         None => Err(ServiceError::Unauthorized),
@@ -1,24 +1,266 @@
 use crate::errors::ServiceError;
+use actix_session::Session;
 use actix_web::{web, Responder, HttpResponse};
 use crate::{
-    models::search::meili_search,
+    auth,
+    metrics::Metrics,
+    models::search::Suggestions,
+    models::search_analytics::{SearchClickInput, SearchEvent},
+    models::search_settings::SearchSettings,
+    search::{
+        query::Sort, Rules, SearchBackend, SearchError, SearchParams, SearchRequest,
+        SearchResults, SuggestCache,
+    },
+    types::PostgresPool,
+    workers::{jobs, query_log::QueryLog, search_index::ReindexProducts},
 };
+use chrono::{Duration, NaiveDate, NaiveDateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::{
+    sync::{Arc, RwLock},
+    time::Instant,
+};
+use uuid::Uuid;
+
+const SUGGESTION_LIMIT: i64 = 5;
+
+#[derive(Deserialize)]
+pub struct SuggestParams {
+    q: String,
+}
+
+/// Date range of the analytics reports; `to` is inclusive. Defaults to the last 30 days.
+#[derive(Deserialize)]
+pub struct ReportParams {
+    from: Option<NaiveDate>,
+    to: Option<NaiveDate>,
+    limit: Option<i64>,
+}
+
+impl ReportParams {
+    fn range(&self) -> (NaiveDateTime, NaiveDateTime) {
+        let to = self.to.unwrap_or_else(|| Utc::now().naive_utc().date());
+        let from = self.from.unwrap_or(to - Duration::days(30));
+        (from.and_hms(0, 0, 0), (to + Duration::days(1)).and_hms(0, 0, 0))
+    }
+}
+
+/// `search_id` identifies the search in click events.
+#[derive(Serialize)]
+struct SearchResponse {
+    search_id: String,
+    #[serde(flatten)]
+    results: SearchResults,
+}
+
+/// A random id kept in the session cookie, so analytics can group one visitor's
+/// searches without knowing who they are.
+fn visitor_id(session: &Session) -> Option<String> {
+    match session.get::<String>("visitor_id") {
+        Ok(Some(id)) => Some(id),
+        _ => {
+            let id = Uuid::new_v4().to_string();
+            session.insert("visitor_id", &id).ok()?;
+            Some(id)
+        }
+    }
+}
 
 pub async fn search(
-    query: web::Path<String>,
+    session: Session,
+    params: web::Query<SearchParams>,
+    backend: web::Data<dyn SearchBackend>,
+    settings: web::Data<RwLock<Arc<SearchSettings>>>,
+    query_log: web::Data<QueryLog>,
+    pool: web::Data<PostgresPool>,
+    metrics: web::Data<Metrics>,
 ) -> Result<impl Responder, ServiceError> {
-    let data = meili_search(&query).await;
+    let request = match SearchRequest::try_from(params.into_inner()) {
+        Ok(request) => request,
+        Err(err) => return Err(ServiceError::BadRequest(err.to_string())),
+    };
+    let settings = settings.read().unwrap_or_else(|e| e.into_inner()).clone();
+    // without its rules a search is still answered, but logged as failed
+    let (rules, rules_failed) = match Rules::resolve(&request, &settings, pool.get_ref()).await {
+        Ok(rules) => (rules, false),
+        Err(err) => {
+            log::error!("applying merchandising rules failed: {}", err);
+            (Rules::default(), true)
+        }
+    };
+    let started = Instant::now();
+    let data = rules.search(backend.get_ref(), &request).await;
+    let outcome = match data {
+        Ok(_) => "ok",
+        Err(SearchError::Disabled) => "disabled",
+        Err(SearchError::Timeout) => "timeout",
+        Err(SearchError::Engine(_)) => "error",
+    };
+    metrics.observe_search(backend.name(), outcome, started);
+    // failed searches are logged too, with no results, so analytics can tell them apart
+    let search_event = |id: String, outcome: &str, result_count: i64| SearchEvent {
+        id,
+        query: request.query.clone(),
+        filters: request
+            .filters
+            .iter()
+            .map(|filter| format!("{}:{}", filter.field, filter.value))
+            .collect(),
+        sort: request.sort.map(Sort::to_param),
+        page: request.page as i32,
+        result_count,
+        latency_ms: started.elapsed().as_millis() as i32,
+        session_id: visitor_id(&session),
+        outcome: outcome.to_string(),
+    };
     match data {
-        Ok(documents) => Ok(HttpResponse::Ok().json(documents)),
+        Ok(results) => {
+            let outcome = if rules_failed { "error" } else { outcome };
+            let search_id = Uuid::new_v4().to_string();
+            query_log.record(search_event(search_id.clone(), outcome, results.total as i64));
+            Ok(HttpResponse::Ok().json(SearchResponse { search_id, results }))
+        }
+        Err(err) => {
+            query_log.record(search_event(Uuid::new_v4().to_string(), outcome, 0));
+            match err {
+                SearchError::Disabled => Err(ServiceError::ServiceUnavailable(
+                    "Search is disabled".to_string(),
+                )),
+                SearchError::Timeout => Err(ServiceError::ServiceUnavailable(
+                    "Search timed out".to_string(),
+                )),
+                err => {
+                    log::error!("{}", err);
+                    Err(ServiceError::BadRequest("Error searching".to_string()))
+                }
+            }
+        }
+    }
+}
+
+/// Search-as-you-type completions for `q`, served from memory when possible.
+async fn suggest(
+    params: web::Query<SuggestParams>,
+    cache: web::Data<SuggestCache>,
+    pool: web::Data<PostgresPool>,
+) -> Result<impl Responder, ServiceError> {
+    let prefix = params.q.trim().to_lowercase();
+    if prefix.is_empty() {
+        return Ok(HttpResponse::Ok().json(Suggestions::default()));
+    }
+    if let Some(suggestions) = cache.get(&prefix) {
+        return Ok(HttpResponse::Ok().json(suggestions));
+    }
+
+    match Suggestions::find(&prefix, SUGGESTION_LIMIT, pool.get_ref()).await {
+        Ok(suggestions) => {
+            cache.insert(prefix, suggestions.clone());
+            Ok(HttpResponse::Ok().json(suggestions))
+        }
         _ => Err(ServiceError::BadRequest(
-            "Error searching".to_string(),
+            "Error trying to read suggestions from database".to_string(),
         )),
     }
 }
 
+/// Records that a search result was opened.
+async fn click(
+    session: Session,
+    input: web::Json<SearchClickInput>,
+    pool: web::Data<PostgresPool>,
+) -> Result<impl Responder, ServiceError> {
+    let result =
+        SearchEvent::record_click(input.into_inner(), visitor_id(&session), pool.get_ref()).await;
+    match result {
+        Ok(()) => Ok(HttpResponse::NoContent().finish()),
+        _ => Err(ServiceError::BadRequest(
+            "Error trying to record search click".to_string(),
+        )),
+    }
+}
+
+async fn top_queries(
+    session: Session,
+    params: web::Query<ReportParams>,
+    pool: web::Data<PostgresPool>,
+) -> Result<impl Responder, ServiceError> {
+    auth::require_admin(&session, pool.get_ref()).await?;
+    let (from, to) = params.range();
+    let limit = params.limit.unwrap_or(50);
+    match SearchEvent::top_queries(from, to, false, limit, pool.get_ref()).await {
+        Ok(reports) => Ok(HttpResponse::Ok().json(reports)),
+        _ => Err(ServiceError::InternalServerError),
+    }
+}
+
+async fn zero_result_queries(
+    session: Session,
+    params: web::Query<ReportParams>,
+    pool: web::Data<PostgresPool>,
+) -> Result<impl Responder, ServiceError> {
+    auth::require_admin(&session, pool.get_ref()).await?;
+    let (from, to) = params.range();
+    let limit = params.limit.unwrap_or(50);
+    match SearchEvent::top_queries(from, to, true, limit, pool.get_ref()).await {
+        Ok(reports) => Ok(HttpResponse::Ok().json(reports)),
+        _ => Err(ServiceError::InternalServerError),
+    }
+}
+
+async fn click_through(
+    session: Session,
+    params: web::Query<ReportParams>,
+    pool: web::Data<PostgresPool>,
+) -> Result<impl Responder, ServiceError> {
+    auth::require_admin(&session, pool.get_ref()).await?;
+    let (from, to) = params.range();
+    match SearchEvent::click_through(from, to, pool.get_ref()).await {
+        Ok(report) => Ok(HttpResponse::Ok().json(report)),
+        _ => Err(ServiceError::InternalServerError),
+    }
+}
+
+/// Queues a job indexing every product, e.g. after changing the index settings.
+/// `job_id` is null when a reindex is already waiting.
+async fn reindex(
+    session: Session,
+    pool: web::Data<PostgresPool>,
+) -> Result<impl Responder, ServiceError> {
+    auth::require_admin(&session, pool.get_ref()).await?;
+    match jobs::enqueue(&ReindexProducts {}, None, pool.get_ref()).await {
+        Ok(job_id) => Ok(HttpResponse::Accepted().json(json!({ "job_id": job_id }))),
+        _ => Err(ServiceError::InternalServerError),
+    }
+}
+
 pub fn config(cfg: &mut web::ServiceConfig) {
     cfg.service(
-        web::resource("/search/{query}")
+        web::resource("/search")
             .route(web::get().to(search)),
     );
+    cfg.service(
+        web::resource("/search/suggest")
+            .route(web::get().to(suggest)),
+    );
+    cfg.service(
+        web::resource("/search/click")
+            .route(web::post().to(click)),
+    );
+    cfg.service(
+        web::resource("/admin/search/reports/top-queries")
+            .route(web::get().to(top_queries)),
+    );
+    cfg.service(
+        web::resource("/admin/search/reports/zero-results")
+            .route(web::get().to(zero_result_queries)),
+    );
+    cfg.service(
+        web::resource("/admin/search/reports/click-through")
+            .route(web::get().to(click_through)),
+    );
+    cfg.service(
+        web::resource("/admin/search/reindex")
+            .route(web::post().to(reindex)),
+    );
 }
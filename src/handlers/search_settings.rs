@@ -0,0 +1,104 @@
+use crate::errors::ServiceError;
+use crate::{
+    auth,
+    models::search_settings::{SearchSettings, SearchSettingsInput},
+    search::SearchBackend,
+    types::PostgresPool,
+    workers::search_settings,
+};
+use actix_session::Session;
+use actix_web::{web, HttpResponse, Responder};
+use std::sync::{Arc, RwLock};
+
+async fn find_current(
+    session: Session,
+    pool: web::Data<PostgresPool>,
+) -> Result<impl Responder, ServiceError> {
+    auth::require_admin(&session, pool.get_ref()).await?;
+    match SearchSettings::current(pool.get_ref()).await {
+        Ok(settings) => Ok(HttpResponse::Ok().json(settings)),
+        _ => Err(ServiceError::BadRequest(
+            "Error trying to read search settings from database".to_string(),
+        )),
+    }
+}
+
+async fn update(
+    session: Session,
+    input: web::Json<SearchSettingsInput>,
+    pool: web::Data<PostgresPool>,
+    current: web::Data<RwLock<Arc<SearchSettings>>>,
+    backend: web::Data<dyn SearchBackend>,
+) -> Result<impl Responder, ServiceError> {
+    let user = auth::require_admin(&session, pool.get_ref()).await?;
+    let result = SearchSettings::create(input.into_inner(), Some(user.id), pool.get_ref()).await;
+    match result {
+        Ok(settings) => {
+            search_settings::activate(settings.clone(), current.get_ref(), backend.get_ref()).await;
+            Ok(HttpResponse::Ok().json(settings))
+        }
+        Err(err) => Err(ServiceError::BadRequest(err.to_string())),
+    }
+}
+
+async fn find_versions(
+    session: Session,
+    pool: web::Data<PostgresPool>,
+) -> Result<impl Responder, ServiceError> {
+    auth::require_admin(&session, pool.get_ref()).await?;
+    match SearchSettings::find_versions(pool.get_ref()).await {
+        Ok(versions) => Ok(HttpResponse::Ok().json(versions)),
+        _ => Err(ServiceError::BadRequest(
+            "Error trying to read search settings from database".to_string(),
+        )),
+    }
+}
+
+async fn find_by_version(
+    session: Session,
+    version: web::Path<i32>,
+    pool: web::Data<PostgresPool>,
+) -> Result<impl Responder, ServiceError> {
+    auth::require_admin(&session, pool.get_ref()).await?;
+    match SearchSettings::find_by_version(version.into_inner(), pool.get_ref()).await {
+        Ok(settings) => Ok(HttpResponse::Ok().json(settings)),
+        _ => Ok(HttpResponse::NotFound().body("Search settings version not found")),
+    }
+}
+
+async fn restore(
+    session: Session,
+    version: web::Path<i32>,
+    pool: web::Data<PostgresPool>,
+    current: web::Data<RwLock<Arc<SearchSettings>>>,
+    backend: web::Data<dyn SearchBackend>,
+) -> Result<impl Responder, ServiceError> {
+    let user = auth::require_admin(&session, pool.get_ref()).await?;
+    let result = SearchSettings::restore(version.into_inner(), Some(user.id), pool.get_ref()).await;
+    match result {
+        Ok(settings) => {
+            search_settings::activate(settings.clone(), current.get_ref(), backend.get_ref()).await;
+            Ok(HttpResponse::Ok().json(settings))
+        }
+        _ => Ok(HttpResponse::NotFound().body("Search settings version not found")),
+    }
+}
+
+pub fn config(cfg: &mut web::ServiceConfig) {
+    cfg.service(
+        web::resource("/admin/search/settings")
+            .route(web::get().to(find_current))
+            .route(web::put().to(update)),
+    );
+    cfg.service(
+        web::resource("/admin/search/settings/versions").route(web::get().to(find_versions)),
+    );
+    cfg.service(
+        web::resource("/admin/search/settings/versions/{version}")
+            .route(web::get().to(find_by_version)),
+    );
+    cfg.service(
+        web::resource("/admin/search/settings/versions/{version}/restore")
+            .route(web::post().to(restore)),
+    );
+}
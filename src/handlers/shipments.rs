@@ -0,0 +1,75 @@
+use crate::errors::ServiceError;
+use crate::{
+    models::shipments::{Shipment, ShipmentInput},
+    types::PostgresPool,
+};
+use actix_session::Session;
+use actix_web::{web, HttpResponse, Responder};
+
+async fn find_by_order(
+    order_id: web::Path<i32>,
+    pool: web::Data<PostgresPool>,
+) -> Result<impl Responder, ServiceError> {
+    match Shipment::find_by_order(order_id.into_inner(), pool.get_ref()).await {
+        Ok(shipments) => Ok(HttpResponse::Ok().json(shipments)),
+        _ => Err(ServiceError::BadRequest(
+            "Error trying to read shipments from database".to_string(),
+        )),
+    }
+}
+
+async fn create(
+    session: Session,
+    order_id: web::Path<i32>,
+    input: web::Json<ShipmentInput>,
+    pool: web::Data<PostgresPool>,
+) -> Result<impl Responder, ServiceError> {
+    let user_id: Option<i64> = session.get("user_id").unwrap_or(None);
+    match user_id {
+        Some(_userid) => {
+            session.renew();
+            let result =
+                Shipment::create(order_id.into_inner(), input.into_inner(), pool.get_ref()).await;
+            match result {
+                Ok(shipment) => Ok(HttpResponse::Created().json(shipment)),
+                Err(err) => Err(ServiceError::BadRequest(err.to_string())),
+            }
+        }
+        None => Err(ServiceError::Unauthorized),
+    }
+}
+
+async fn update(
+    session: Session,
+    id: web::Path<i32>,
+    input: web::Json<ShipmentInput>,
+    pool: web::Data<PostgresPool>,
+) -> Result<impl Responder, ServiceError> {
+    let user_id: Option<i64> = session.get("user_id").unwrap_or(None);
+    match user_id {
+        Some(_userid) => {
+            session.renew();
+            let result =
+                Shipment::update(id.into_inner(), input.into_inner(), pool.get_ref()).await;
+            match result {
+                Ok(shipment) => Ok(HttpResponse::Ok().json(shipment)),
+                Err(err) => match err.downcast_ref::<sqlx::Error>() {
+                    Some(sqlx::Error::RowNotFound) => {
+                        Ok(HttpResponse::NotFound().body("Shipment not found"))
+                    }
+                    _ => Err(ServiceError::BadRequest(err.to_string())),
+                },
+            }
+        }
+        None => Err(ServiceError::Unauthorized),
+    }
+}
+
+pub fn config(cfg: &mut web::ServiceConfig) {
+    cfg.service(
+        web::resource("/orders/{id}/shipments")
+            .route(web::get().to(find_by_order))
+            .route(web::post().to(create)),
+    );
+    cfg.service(web::resource("/shipments/{id}").route(web::put().to(update)));
+}
@@ -1,6 +1,8 @@
 use crate::errors::ServiceError;
 use crate::{
+    metrics::Metrics,
     models::users::{Credentials, User, UserInput},
+    settings::Settings,
     types::PostgresPool,
 };
 use actix_session::Session;
@@ -31,12 +33,13 @@ async fn create(
     session: Session,
     input: web::Json<UserInput>,
     pool: web::Data<PostgresPool>,
+    settings: web::Data<Settings>,
 ) -> Result<impl Responder, ServiceError> {
     // let user_id: Option<i64> = session.get("user_id").unwrap_or(None);
     // match user_id {
     // Some(_userid) => {
     session.renew();
-    let result = User::create(input.into_inner(), pool.get_ref()).await;
+    let result = User::create(input.into_inner(), &settings.auth.salt, pool.get_ref()).await;
     match result {
         Ok(users) => Ok(HttpResponse::Ok().json(users)),
         _ => Err(ServiceError::BadRequest(
@@ -111,15 +114,20 @@ pub async fn login(
     session: Session,
     credentials: web::Json<Credentials>,
     db_pool: web::Data<PostgresPool>,
+    settings: web::Data<Settings>,
+    metrics: web::Data<Metrics>,
 ) -> Result<impl Responder, ServiceError> {
     let credentials = credentials.into_inner();
 
-    let user = match User::authenticate(credentials, db_pool.get_ref()).await {
+    let user = match User::authenticate(credentials, &settings.auth.salt, db_pool.get_ref()).await {
         Ok(user) => {
             session.insert("user_id", user.id).unwrap();
             user
         }
-        Err(_err) => return Err(ServiceError::Unauthorized),
+        Err(_err) => {
+            metrics.login_failed();
+            return Err(ServiceError::Unauthorized);
+        }
     };
 
     Ok(format!("Welcome!, {:?}", user))
@@ -0,0 +1,169 @@
+use crate::errors::ServiceError;
+use crate::{
+    auth,
+    models::webhooks::{
+        DeliveryFilter, WebhookAttempt, WebhookDelivery, WebhookSubscription,
+        WebhookSubscriptionInput,
+    },
+    types::PostgresPool,
+    workers::{jobs, webhooks::DeliverWebhook},
+};
+use actix_session::Session;
+use actix_web::{web, HttpResponse, Responder};
+use serde::Serialize;
+use serde_json::json;
+
+/// The secret is only shown once, in the response creating the subscription.
+#[derive(Serialize)]
+struct CreatedSubscription {
+    #[serde(flatten)]
+    subscription: WebhookSubscription,
+    secret: String,
+}
+
+async fn find_all(
+    session: Session,
+    pool: web::Data<PostgresPool>,
+) -> Result<impl Responder, ServiceError> {
+    auth::require_admin(&session, pool.get_ref()).await?;
+    match WebhookSubscription::find_all(pool.get_ref()).await {
+        Ok(subscriptions) => Ok(HttpResponse::Ok().json(subscriptions)),
+        _ => Err(ServiceError::InternalServerError),
+    }
+}
+
+async fn find(
+    session: Session,
+    id: web::Path<i32>,
+    pool: web::Data<PostgresPool>,
+) -> Result<impl Responder, ServiceError> {
+    auth::require_admin(&session, pool.get_ref()).await?;
+    match WebhookSubscription::find_by_id(id.into_inner(), pool.get_ref()).await {
+        Ok(subscription) => Ok(HttpResponse::Ok().json(subscription)),
+        _ => Ok(HttpResponse::NotFound().body("Webhook not found")),
+    }
+}
+
+async fn create(
+    session: Session,
+    input: web::Json<WebhookSubscriptionInput>,
+    pool: web::Data<PostgresPool>,
+) -> Result<impl Responder, ServiceError> {
+    auth::require_admin(&session, pool.get_ref()).await?;
+    match WebhookSubscription::create(input.into_inner(), pool.get_ref()).await {
+        Ok(subscription) => Ok(HttpResponse::Created().json(CreatedSubscription {
+            secret: subscription.secret.clone(),
+            subscription,
+        })),
+        Err(err) => Err(ServiceError::BadRequest(err.to_string())),
+    }
+}
+
+async fn update(
+    session: Session,
+    id: web::Path<i32>,
+    input: web::Json<WebhookSubscriptionInput>,
+    pool: web::Data<PostgresPool>,
+) -> Result<impl Responder, ServiceError> {
+    auth::require_admin(&session, pool.get_ref()).await?;
+    match WebhookSubscription::update(id.into_inner(), input.into_inner(), pool.get_ref()).await {
+        Ok(subscription) => Ok(HttpResponse::Ok().json(subscription)),
+        Err(err) => Err(ServiceError::BadRequest(err.to_string())),
+    }
+}
+
+async fn delete(
+    session: Session,
+    id: web::Path<i32>,
+    pool: web::Data<PostgresPool>,
+) -> Result<impl Responder, ServiceError> {
+    auth::require_admin(&session, pool.get_ref()).await?;
+    match WebhookSubscription::delete(id.into_inner(), pool.get_ref()).await {
+        Ok(rows) => {
+            if rows > 0 {
+                Ok(HttpResponse::Ok().body(format!("Successfully deleted {} record(s)", rows)))
+            } else {
+                Ok(HttpResponse::NotFound().body("Webhook not found"))
+            }
+        }
+        Err(err) => Err(ServiceError::BadRequest(err.to_string())),
+    }
+}
+
+/// Lists a webhook's deliveries, newest first. Takes `status`, `limit` and `offset` parameters.
+async fn find_deliveries(
+    session: Session,
+    id: web::Path<i32>,
+    filter: web::Query<DeliveryFilter>,
+    pool: web::Data<PostgresPool>,
+) -> Result<impl Responder, ServiceError> {
+    auth::require_admin(&session, pool.get_ref()).await?;
+    match WebhookDelivery::find_by_subscription(id.into_inner(), &filter, pool.get_ref()).await {
+        Ok(deliveries) => Ok(HttpResponse::Ok().json(deliveries)),
+        _ => Err(ServiceError::InternalServerError),
+    }
+}
+
+/// A delivery together with every request made for it.
+async fn find_delivery(
+    session: Session,
+    id: web::Path<i64>,
+    pool: web::Data<PostgresPool>,
+) -> Result<impl Responder, ServiceError> {
+    auth::require_admin(&session, pool.get_ref()).await?;
+    let delivery = match WebhookDelivery::find_by_id(id.into_inner(), pool.get_ref()).await {
+        Ok(delivery) => delivery,
+        _ => return Ok(HttpResponse::NotFound().body("Delivery not found")),
+    };
+    match WebhookAttempt::find_by_delivery(delivery.id, pool.get_ref()).await {
+        Ok(attempts) => Ok(HttpResponse::Ok().json(json!({
+            "delivery": delivery,
+            "attempts": attempts,
+        }))),
+        _ => Err(ServiceError::InternalServerError),
+    }
+}
+
+/// Sends a delivery again, whatever its status. `job_id` is null when the
+/// delivery is already queued.
+async fn redeliver(
+    session: Session,
+    id: web::Path<i64>,
+    pool: web::Data<PostgresPool>,
+) -> Result<impl Responder, ServiceError> {
+    auth::require_admin(&session, pool.get_ref()).await?;
+    let delivery = match WebhookDelivery::find_by_id(id.into_inner(), pool.get_ref()).await {
+        Ok(delivery) => delivery,
+        _ => return Ok(HttpResponse::NotFound().body("Delivery not found")),
+    };
+    let job = DeliverWebhook {
+        delivery_id: delivery.id,
+    };
+    match jobs::enqueue(&job, None, pool.get_ref()).await {
+        Ok(job_id) => Ok(HttpResponse::Accepted().json(json!({ "job_id": job_id }))),
+        _ => Err(ServiceError::InternalServerError),
+    }
+}
+
+pub fn config(cfg: &mut web::ServiceConfig) {
+    cfg.service(
+        web::resource("/admin/webhooks")
+            .route(web::get().to(find_all))
+            .route(web::post().to(create)),
+    );
+    cfg.service(
+        web::resource("/admin/webhooks/deliveries/{id}").route(web::get().to(find_delivery)),
+    );
+    cfg.service(
+        web::resource("/admin/webhooks/deliveries/{id}/redeliver").route(web::post().to(redeliver)),
+    );
+    cfg.service(
+        web::resource("/admin/webhooks/{id}")
+            .route(web::get().to(find))
+            .route(web::put().to(update))
+            .route(web::delete().to(delete)),
+    );
+    cfg.service(
+        web::resource("/admin/webhooks/{id}/deliveries").route(web::get().to(find_deliveries)),
+    );
+}
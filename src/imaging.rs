@@ -0,0 +1,415 @@
+use anyhow::{anyhow, Result};
+use image::{
+    imageops::FilterType,
+    io::{Limits, Reader},
+    DynamicImage, GenericImageView, ImageOutputFormat,
+};
+use std::io::Cursor;
+
+/// Bounding box edge, in pixels, of every generated derivative.
+pub const SIZES: [(&str, u32); 3] = [("thumbnail", 160), ("medium", 640), ("large", 1280)];
+
+/// Largest width or height of an image that gets decoded.
+const MAX_EDGE: u32 = 12_000;
+/// Memory one decode may allocate; a 12000x12000 RGB picture would need ~430 MB.
+const MAX_DECODE_BYTES: u64 = 256 * 1024 * 1024;
+
+/// Widths and heights the render endpoint accepts, so clients can't request
+/// (and fill the cache with) arbitrary sizes.
+pub const RENDER_SIZES: [u32; 10] = [80, 160, 320, 480, 640, 800, 1024, 1280, 1600, 1920];
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Fit {
+    /// scale and crop to exactly fill the box
+    Cover,
+    /// scale to fit inside the box, keeping the aspect ratio
+    Contain,
+    /// stretch to the box
+    Fill,
+}
+
+impl Fit {
+    pub fn parse(value: &str) -> Option<Fit> {
+        match value {
+            "cover" => Some(Fit::Cover),
+            "contain" => Some(Fit::Contain),
+            "fill" => Some(Fit::Fill),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Fit::Cover => "cover",
+            Fit::Contain => "contain",
+            Fit::Fill => "fill",
+        }
+    }
+}
+
+pub struct RenderOptions {
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    pub fit: Fit,
+    pub content_type: &'static str,
+}
+
+pub struct RenderedVariant {
+    pub name: &'static str,
+    pub format: &'static str,
+    pub extension: &'static str,
+    pub content_type: &'static str,
+    pub width: u32,
+    pub height: u32,
+    pub bytes: Vec<u8>,
+}
+
+/// Removes EXIF/XMP/IPTC metadata (GPS position, camera serials, ...) from an upload.
+/// Pictures carrying an EXIF orientation are re-encoded upright first, since dropping
+/// the tag would otherwise display them rotated.
+/// Images larger than `MAX_EDGE` are rejected, before anything is decoded.
+pub fn strip_metadata(bytes: Vec<u8>, content_type: &str) -> Result<Vec<u8>> {
+    let (width, height) = Reader::new(Cursor::new(&bytes))
+        .with_guessed_format()?
+        .into_dimensions()?;
+    limits().check_dimensions(width, height)?;
+
+    match orientation(&bytes) {
+        Some(orientation) if orientation != 1 => {
+            let image = apply_orientation(decode(&bytes)?, orientation);
+            encode(&image, content_type)
+        }
+        _ => match content_type {
+            "image/jpeg" => strip_jpeg(&bytes),
+            "image/png" => strip_png(&bytes),
+            "image/webp" => strip_webp(&bytes),
+            _ => Ok(bytes),
+        },
+    }
+}
+
+/// Renders every size in `SIZES` in the original's format (GIFs become PNGs) and as WebP.
+/// Images are never upscaled.
+pub fn render_variants(original: &[u8], content_type: &str) -> Result<Vec<RenderedVariant>> {
+    let image = decode(original)?;
+    let (format, extension, content_type) = match content_type {
+        "image/jpeg" => ("jpeg", "jpg", "image/jpeg"),
+        _ => ("png", "png", "image/png"),
+    };
+
+    let mut variants = Vec::with_capacity(SIZES.len() * 2);
+    for (name, edge) in SIZES {
+        let resized = if image.width() > edge || image.height() > edge {
+            image.resize(edge, edge, FilterType::Lanczos3)
+        } else {
+            image.clone()
+        };
+        let (width, height) = resized.dimensions();
+
+        variants.push(RenderedVariant {
+            name,
+            format,
+            extension,
+            content_type,
+            width,
+            height,
+            bytes: encode(&resized, content_type)?,
+        });
+        variants.push(RenderedVariant {
+            name,
+            format: "webp",
+            extension: "webp",
+            content_type: "image/webp",
+            width,
+            height,
+            bytes: encode_webp(&resized)?,
+        });
+    }
+
+    Ok(variants)
+}
+
+/// Resizes an image on demand. A missing width or height is derived from the
+/// original's aspect ratio.
+pub fn render(original: &[u8], options: &RenderOptions) -> Result<Vec<u8>> {
+    let image = decode(original)?;
+    let scaled = |other: u32, target: u32, base: u32| {
+        ((other as u64 * target as u64) / base.max(1) as u64).max(1) as u32
+    };
+    let (width, height) = match (options.width, options.height) {
+        (Some(width), Some(height)) => (width, height),
+        (Some(width), None) => (width, scaled(image.height(), width, image.width())),
+        (None, Some(height)) => (scaled(image.width(), height, image.height()), height),
+        (None, None) => image.dimensions(),
+    };
+
+    let resized = match options.fit {
+        Fit::Cover => image.resize_to_fill(width, height, FilterType::Lanczos3),
+        Fit::Contain => image.resize(width, height, FilterType::Lanczos3),
+        Fit::Fill => image.resize_exact(width, height, FilterType::Lanczos3),
+    };
+    encode(&resized, options.content_type)
+}
+
+fn limits() -> Limits {
+    let mut limits = Limits::default();
+    limits.max_image_width = Some(MAX_EDGE);
+    limits.max_image_height = Some(MAX_EDGE);
+    limits.max_alloc = Some(MAX_DECODE_BYTES);
+    limits
+}
+
+/// Decodes within `limits`, so a small file declaring a huge canvas (a
+/// decompression bomb) fails instead of exhausting memory.
+fn decode(bytes: &[u8]) -> Result<DynamicImage> {
+    let mut reader = Reader::new(Cursor::new(bytes)).with_guessed_format()?;
+    reader.limits(limits());
+    Ok(reader.decode()?)
+}
+
+fn encode(image: &DynamicImage, content_type: &str) -> Result<Vec<u8>> {
+    if content_type == "image/webp" {
+        return encode_webp(image);
+    }
+
+    let format = match content_type {
+        "image/jpeg" => ImageOutputFormat::Jpeg(85),
+        _ => ImageOutputFormat::Png,
+    };
+    let mut bytes = Vec::new();
+    image.write_to(&mut Cursor::new(&mut bytes), format)?;
+    Ok(bytes)
+}
+
+fn encode_webp(image: &DynamicImage) -> Result<Vec<u8>> {
+    let rgba = image.to_rgba8();
+    let encoder = webp::Encoder::from_rgba(rgba.as_raw(), rgba.width(), rgba.height());
+    Ok(encoder.encode(80.0).to_vec())
+}
+
+fn orientation(bytes: &[u8]) -> Option<u32> {
+    let exif = exif::Reader::new()
+        .read_from_container(&mut Cursor::new(bytes))
+        .ok()?;
+    exif.get_field(exif::Tag::Orientation, exif::In::PRIMARY)?
+        .value
+        .get_uint(0)
+}
+
+fn apply_orientation(image: DynamicImage, orientation: u32) -> DynamicImage {
+    match orientation {
+        2 => image.fliph(),
+        3 => image.rotate180(),
+        4 => image.flipv(),
+        5 => image.rotate90().fliph(),
+        6 => image.rotate90(),
+        7 => image.rotate270().fliph(),
+        8 => image.rotate270(),
+        _ => image,
+    }
+}
+
+/// Drops APP1 (EXIF, XMP) and APP13 (IPTC) segments, keeping the compressed data untouched.
+fn strip_jpeg(bytes: &[u8]) -> Result<Vec<u8>> {
+    let mut out = Vec::with_capacity(bytes.len());
+    out.extend_from_slice(&bytes[..2]);
+
+    let mut i = 2;
+    while i + 4 <= bytes.len() {
+        if bytes[i] != 0xFF {
+            return Err(anyhow!("malformed jpeg segment"));
+        }
+        let marker = bytes[i + 1];
+        match marker {
+            // fill byte
+            0xFF => {
+                i += 1;
+                continue;
+            }
+            // start of scan: the rest is entropy coded data
+            0xDA => break,
+            0x01 | 0xD0..=0xD7 => {
+                out.extend_from_slice(&bytes[i..i + 2]);
+                i += 2;
+                continue;
+            }
+            _ => {}
+        }
+
+        let length = u16::from_be_bytes([bytes[i + 2], bytes[i + 3]]) as usize;
+        let end = i + 2 + length;
+        if end > bytes.len() {
+            return Err(anyhow!("truncated jpeg segment"));
+        }
+        if marker != 0xE1 && marker != 0xED {
+            out.extend_from_slice(&bytes[i..end]);
+        }
+        i = end;
+    }
+
+    out.extend_from_slice(&bytes[i.min(bytes.len())..]);
+    Ok(out)
+}
+
+/// Drops the eXIf chunk and textual chunks that may carry XMP or comments.
+fn strip_png(bytes: &[u8]) -> Result<Vec<u8>> {
+    const DROPPED: [&[u8; 4]; 5] = [b"eXIf", b"tEXt", b"iTXt", b"zTXt", b"tIME"];
+
+    let mut out = Vec::with_capacity(bytes.len());
+    out.extend_from_slice(&bytes[..8]);
+
+    let mut i = 8;
+    while i + 8 <= bytes.len() {
+        let length = u32::from_be_bytes([bytes[i], bytes[i + 1], bytes[i + 2], bytes[i + 3]]);
+        let end = i + 12 + length as usize;
+        if end > bytes.len() {
+            return Err(anyhow!("truncated png chunk"));
+        }
+        let kind = &bytes[i + 4..i + 8];
+        if !DROPPED.iter().any(|dropped| &dropped[..] == kind) {
+            out.extend_from_slice(&bytes[i..end]);
+        }
+        i = end;
+    }
+
+    Ok(out)
+}
+
+/// Drops the EXIF and XMP chunks of an extended (VP8X) WebP file and clears their flags.
+fn strip_webp(bytes: &[u8]) -> Result<Vec<u8>> {
+    let mut out = Vec::with_capacity(bytes.len());
+    out.extend_from_slice(&bytes[..12]);
+
+    let mut i = 12;
+    while i + 8 <= bytes.len() {
+        let kind = &bytes[i..i + 4];
+        let length = u32::from_le_bytes([bytes[i + 4], bytes[i + 5], bytes[i + 6], bytes[i + 7]]);
+        let end = i + 8 + length as usize + (length as usize & 1);
+        if end > bytes.len() {
+            return Err(anyhow!("truncated webp chunk"));
+        }
+        match kind {
+            b"EXIF" | b"XMP " => {}
+            b"VP8X" if length >= 1 => {
+                let start = out.len();
+                out.extend_from_slice(&bytes[i..end]);
+                // clear the "has EXIF" and "has XMP" bits
+                out[start + 8] &= !(0x08 | 0x04);
+            }
+            _ => out.extend_from_slice(&bytes[i..end]),
+        }
+        i = end;
+    }
+
+    let riff_size = (out.len() - 8) as u32;
+    out[4..8].copy_from_slice(&riff_size.to_le_bytes());
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// EXIF data whose GPS block holds a latitude reference.
+    const GPS_EXIF: &[u8] = b"Exif\0\0II*\0\x08\0\0\0\
+        \x01\0\x25\x88\x04\0\x01\0\0\0\x1a\0\0\0\0\0\0\0\
+        \x01\0\x01\0\x02\0\x02\0\0\0N\0\0\0\0\0\0\0";
+    /// EXIF data telling viewers to rotate the picture by 90 degrees.
+    const ROTATED_EXIF: &[u8] = b"Exif\0\0II*\0\x08\0\0\0\
+        \x01\0\x12\x01\x03\0\x01\0\0\0\x06\0\0\0\0\0\0\0";
+
+    fn gradient(width: u32, height: u32) -> DynamicImage {
+        DynamicImage::ImageRgb8(image::RgbImage::from_fn(width, height, |x, y| {
+            image::Rgb([(x * 16) as u8, (y * 16) as u8, 128])
+        }))
+    }
+
+    fn jpeg_with_exif(image: &DynamicImage, exif: &[u8]) -> Vec<u8> {
+        let mut jpeg = Vec::new();
+        image
+            .write_to(&mut Cursor::new(&mut jpeg), ImageOutputFormat::Jpeg(90))
+            .unwrap();
+        let mut segment = vec![0xFF, 0xE1];
+        segment.extend_from_slice(&((exif.len() + 2) as u16).to_be_bytes());
+        segment.extend_from_slice(exif);
+        jpeg.splice(2..2, segment);
+        jpeg
+    }
+
+    /// A PNG with an eXIf and a tEXt chunk after the header; their CRCs are not checked.
+    fn png_with_exif(image: &DynamicImage) -> Vec<u8> {
+        let mut png = Vec::new();
+        image
+            .write_to(&mut Cursor::new(&mut png), ImageOutputFormat::Png)
+            .unwrap();
+        let mut chunks = Vec::new();
+        for (kind, data) in [
+            (b"eXIf", &GPS_EXIF[6..]),
+            (b"tEXt", &b"Comment\0taken at home"[..]),
+        ] {
+            chunks.extend_from_slice(&(data.len() as u32).to_be_bytes());
+            chunks.extend_from_slice(kind);
+            chunks.extend_from_slice(data);
+            chunks.extend_from_slice(&[0; 4]);
+        }
+        // signature (8) and IHDR (25)
+        png.splice(33..33, chunks);
+        png
+    }
+
+    fn contains(haystack: &[u8], needle: &[u8]) -> bool {
+        haystack
+            .windows(needle.len())
+            .any(|window| window == needle)
+    }
+
+    #[test]
+    fn strips_the_gps_block_from_jpegs_and_keeps_the_pixels() {
+        let jpeg = jpeg_with_exif(&gradient(16, 8), GPS_EXIF);
+        assert!(contains(&jpeg, b"Exif"));
+
+        let stripped = strip_metadata(jpeg.clone(), "image/jpeg").unwrap();
+        assert!(!contains(&stripped, b"Exif"));
+        assert_eq!(stripped.len(), jpeg.len() - GPS_EXIF.len() - 4);
+        assert_eq!(
+            decode(&stripped).unwrap().to_rgb8(),
+            decode(&jpeg).unwrap().to_rgb8()
+        );
+    }
+
+    #[test]
+    fn strips_metadata_chunks_from_pngs_and_keeps_the_pixels() {
+        let image = gradient(16, 8);
+        let stripped = strip_metadata(png_with_exif(&image), "image/png").unwrap();
+        assert!(!contains(&stripped, b"eXIf"));
+        assert!(!contains(&stripped, b"taken at home"));
+        assert_eq!(decode(&stripped).unwrap().to_rgb8(), image.to_rgb8());
+    }
+
+    #[test]
+    fn turns_rotated_jpegs_upright_without_their_exif() {
+        let jpeg = jpeg_with_exif(&gradient(16, 8), ROTATED_EXIF);
+        let stripped = strip_metadata(jpeg, "image/jpeg").unwrap();
+        assert!(!contains(&stripped, b"Exif"));
+        assert_eq!(decode(&stripped).unwrap().dimensions(), (8, 16));
+    }
+
+    fn png(width: u32, height: u32) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        DynamicImage::new_luma8(width, height)
+            .write_to(&mut Cursor::new(&mut bytes), ImageOutputFormat::Png)
+            .unwrap();
+        bytes
+    }
+
+    #[test]
+    fn refuses_to_decode_oversized_images() {
+        // a few kilobytes of PNG, but 13000 pixels wide
+        let wide = png(MAX_EDGE + 1000, 1);
+        assert!(decode(&wide).is_err());
+        assert!(strip_metadata(wide.clone(), "image/png").is_err());
+        assert!(render_variants(&wide, "image/png").is_err());
+
+        assert!(decode(&png(64, 64)).is_ok());
+    }
+}
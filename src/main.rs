@@ -1,76 +1,45 @@
-use actix_cors::Cors;
-use actix_files::Files;
-use actix_session::{storage::CookieSessionStore, SessionMiddleware};
-use actix_web::{
-    cookie::{Key, SameSite},
-    middleware::Logger,
-    web, App, HttpServer,
-};
+use clap::Parser;
+use cli::Cli;
 use dotenv::dotenv;
-use sqlx::postgres::PgPoolOptions;
-use std::env;
+use settings::Settings;
 
 mod auth;
+mod cli;
 mod errors;
+mod events;
 mod handlers;
+mod imaging;
+mod metrics;
 mod models;
 mod routes;
+mod search;
+mod server;
+mod settings;
+mod signing;
+mod storage;
+mod telemetry;
+#[cfg(test)]
+mod test_support;
+mod workers;
 
 
 pub mod types;
 
 #[actix_web::main]
-async fn main() -> std::io::Result<()> {
+async fn main() -> anyhow::Result<()> {
     dotenv().ok();
-    env_logger::init();
-
-    let database_url = env::var("DATABASE_URL").expect("environment variable: DATABASE_URL");
-    let host = env::var("HOST").expect("environment variable: HOST");
-    let port = env::var("PORT").expect("environment variable: PORT");
-    let addr = format!("{}:{}", host, port);
-
-    let signing_key = Key::generate();
-
-    let pool = PgPoolOptions::new()
-        .max_connections(5)
-        .connect(&database_url)
-        .await
-        .expect("Failed to create pg pool");
-
-        
-    HttpServer::new(move || {
-        App::new()
-            .app_data(web::Data::new(pool.clone()))
-            .wrap(Logger::default())
-            // .wrap(HttpAuthentication::bearer(validator))
-            .wrap(Cors::permissive())
-            .wrap(
-                SessionMiddleware::builder(CookieSessionStore::default(), signing_key.clone())
-                    .cookie_http_only(false)
-                    .cookie_same_site(SameSite::Strict)
-                    .build(),
-            )
-            .route("/", web::get().to(routes::index))
-            .route("/login", web::post().to(handlers::users::login))
-            .service(
-                web::scope("/api").service(
-                    web::scope("/v1")
-                        .configure(handlers::users::config)
-                        .configure(handlers::products::config)
-                        .configure(handlers::orders::config)
-                        .configure(handlers::search::config),
-                ),
-            )
-            .configure(handlers::auth::config)
-            .service(
-                web::scope("/static").default_service(
-                    Files::new("", "./static")
-                        .index_file("index.html")
-                        .use_last_modified(true),
-                ),
-            )
-    })
-    .bind(addr)?
-    .run()
-    .await
+    let cli = Cli::parse();
+
+    let settings = match Settings::load() {
+        Ok(settings) => settings,
+        Err(err) => {
+            eprintln!("{}", err);
+            std::process::exit(1);
+        }
+    };
+    telemetry::init(&settings.telemetry)?;
+
+    let result = cli::run(cli, settings).await;
+    telemetry::shutdown();
+    result
 }
@@ -0,0 +1,172 @@
+use crate::types::PostgresPool;
+use actix_web::{
+    body::MessageBody,
+    dev::{Service, ServiceRequest, ServiceResponse, Transform},
+    Error,
+};
+use anyhow::Result;
+use futures::future::{ready, LocalBoxFuture, Ready};
+use prometheus::{
+    Encoder, HistogramOpts, HistogramVec, IntCounter, IntCounterVec, IntGauge, IntGaugeVec, Opts,
+    Registry, TextEncoder,
+};
+use std::{rc::Rc, time::Instant};
+
+/// Route label of requests that matched no resource, so scanners probing random
+/// paths can't blow up the label cardinality.
+const UNMATCHED_ROUTE: &str = "unmatched";
+
+/// The Prometheus registry and every metric the server exports. Cloning is cheap,
+/// the metrics are shared.
+#[derive(Clone)]
+pub struct Metrics {
+    registry: Registry,
+    http_requests: HistogramVec,
+    pool_connections: IntGaugeVec,
+    orders_created: IntCounterVec,
+    login_failures: IntCounter,
+    search_duration: HistogramVec,
+}
+
+impl Metrics {
+    pub fn new(max_connections: u32) -> Result<Metrics> {
+        let registry = Registry::new_custom(Some("shopapi".to_string()), None)?;
+
+        let http_requests = HistogramVec::new(
+            HistogramOpts::new(
+                "http_request_duration_seconds",
+                "HTTP request latency by route pattern and status",
+            ),
+            &["method", "route", "status"],
+        )?;
+        let pool_connections = IntGaugeVec::new(
+            Opts::new(
+                "db_pool_connections",
+                "Database pool connections by state (idle, in_use)",
+            ),
+            &["state"],
+        )?;
+        let pool_max_connections = IntGauge::new(
+            "db_pool_max_connections",
+            "Configured maximum of database pool connections",
+        )?;
+        let orders_created = IntCounterVec::new(
+            Opts::new("orders_created_total", "Orders created, by currency"),
+            &["currency"],
+        )?;
+        let login_failures = IntCounter::new("login_failures_total", "Rejected login attempts")?;
+        let search_duration = HistogramVec::new(
+            HistogramOpts::new(
+                "search_duration_seconds",
+                "Search backend latency by backend and outcome",
+            )
+            .buckets(vec![0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5]),
+            &["backend", "outcome"],
+        )?;
+
+        registry.register(Box::new(http_requests.clone()))?;
+        registry.register(Box::new(pool_connections.clone()))?;
+        registry.register(Box::new(pool_max_connections.clone()))?;
+        registry.register(Box::new(orders_created.clone()))?;
+        registry.register(Box::new(login_failures.clone()))?;
+        registry.register(Box::new(search_duration.clone()))?;
+        pool_max_connections.set(max_connections as i64);
+
+        Ok(Metrics {
+            registry,
+            http_requests,
+            pool_connections,
+            orders_created,
+            login_failures,
+            search_duration,
+        })
+    }
+
+    pub fn order_created(&self, currency: &str) {
+        self.orders_created.with_label_values(&[currency]).inc();
+    }
+
+    pub fn login_failed(&self) {
+        self.login_failures.inc();
+    }
+
+    pub fn observe_search(&self, backend: &str, outcome: &str, started: Instant) {
+        self.search_duration
+            .with_label_values(&[backend, outcome])
+            .observe(started.elapsed().as_secs_f64());
+    }
+
+    /// Samples the pool gauges and renders everything in the text exposition format.
+    pub fn render(&self, pool: &PostgresPool) -> Result<String> {
+        let size = pool.size() as i64;
+        let idle = pool.num_idle() as i64;
+        self.pool_connections.with_label_values(&["idle"]).set(idle);
+        self.pool_connections
+            .with_label_values(&["in_use"])
+            .set(size - idle);
+
+        let mut buffer = Vec::new();
+        TextEncoder::new().encode(&self.registry.gather(), &mut buffer)?;
+        Ok(String::from_utf8(buffer)?)
+    }
+}
+
+/// Middleware timing every request into `http_request_duration_seconds`. Requests
+/// are labelled with the matched route pattern, e.g. `/api/v1/products/{id}`.
+pub struct RequestMetrics(pub Metrics);
+
+impl<S, B> Transform<S, ServiceRequest> for RequestMetrics
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Transform = RequestMetricsMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(RequestMetricsMiddleware {
+            service: Rc::new(service),
+            metrics: self.0.clone(),
+        }))
+    }
+}
+
+pub struct RequestMetricsMiddleware<S> {
+    service: Rc<S>,
+    metrics: Metrics,
+}
+
+impl<S, B> Service<ServiceRequest> for RequestMetricsMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    actix_web::dev::forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let started = Instant::now();
+        let method = req.method().to_string();
+        let service = self.service.clone();
+        let metrics = self.metrics.clone();
+
+        Box::pin(async move {
+            let response = service.call(req).await?;
+            let route = response
+                .request()
+                .match_pattern()
+                .unwrap_or_else(|| UNMATCHED_ROUTE.to_string());
+            metrics
+                .http_requests
+                .with_label_values(&[&method, &route, response.status().as_str()])
+                .observe(started.elapsed().as_secs_f64());
+            Ok(response)
+        })
+    }
+}
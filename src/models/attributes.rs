@@ -0,0 +1,361 @@
+use crate::types::PostgresPool;
+use anyhow::{anyhow, Result};
+use chrono::NaiveDateTime;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value as JsonValue};
+use sqlx::{FromRow, Postgres, Transaction};
+use std::collections::HashMap;
+use tracing::instrument;
+
+const KINDS: [&str; 4] = ["string", "number", "enum", "boolean"];
+
+#[derive(Serialize, Deserialize)]
+pub struct AttributeDefinitionInput {
+    pub key: String,
+    pub kind: String,
+    #[serde(default)]
+    pub required: bool,
+    pub unit: Option<String>,
+    #[serde(default)]
+    pub options: Vec<String>,
+}
+
+#[derive(Serialize, FromRow, Debug)]
+pub struct AttributeDefinition {
+    pub id: i32,
+    pub category_id: i32,
+    pub key: String,
+    pub kind: String,
+    pub required: bool,
+    pub unit: Option<String>,
+    pub options: Vec<String>,
+    pub updated_at: NaiveDateTime,
+    pub created_at: NaiveDateTime,
+}
+
+impl AttributeDefinitionInput {
+    fn validate(&self) -> Result<()> {
+        if self.key.is_empty() {
+            return Err(anyhow!("attribute key must not be empty"));
+        }
+        if !KINDS.contains(&self.kind.as_str()) {
+            return Err(anyhow!(
+                "unknown attribute kind {}, expected one of {}",
+                self.kind,
+                KINDS.join(", ")
+            ));
+        }
+        if self.kind == "enum" && self.options.is_empty() {
+            return Err(anyhow!("enum attribute {} needs options", self.key));
+        }
+        Ok(())
+    }
+}
+
+impl AttributeDefinition {
+    #[instrument(name = "AttributeDefinition::find_by_category", skip_all, err)]
+    pub async fn find_by_category(
+        category_id: i32,
+        pool: &PostgresPool,
+    ) -> Result<Vec<AttributeDefinition>> {
+        let definitions = sqlx::query_as!(
+            AttributeDefinition,
+            r#"
+              SELECT id, category_id, key, kind, required, unit, options, updated_at, created_at
+                  FROM attribute_definitions
+               WHERE category_id = $1
+              ORDER BY key
+            "#,
+            category_id
+        )
+        .fetch_all(pool)
+        .await?;
+
+        Ok(definitions)
+    }
+
+    /// Definitions that apply to a product: those of its categories and all of their ancestors.
+    /// Read within `tx`, so that categories linked in the same transaction count.
+    #[instrument(name = "AttributeDefinition::find_for_product", skip_all, err)]
+    pub async fn find_for_product(
+        product_id: i32,
+        tx: &mut Transaction<'_, Postgres>,
+    ) -> Result<Vec<AttributeDefinition>> {
+        let definitions = sqlx::query_as!(
+            AttributeDefinition,
+            r#"
+              WITH RECURSIVE ancestors AS (
+                  SELECT c.id, c.parent_id FROM categories c
+                      JOIN product_categories pc ON pc.category_id = c.id
+                   WHERE pc.product_id = $1
+                UNION
+                  SELECT c.id, c.parent_id FROM categories c JOIN ancestors a ON c.id = a.parent_id
+              )
+              SELECT d.id, d.category_id, d.key, d.kind, d.required, d.unit, d.options, d.updated_at, d.created_at
+                  FROM attribute_definitions d
+                  JOIN ancestors a ON a.id = d.category_id
+              ORDER BY d.key
+            "#,
+            product_id
+        )
+        .fetch_all(&mut *tx)
+        .await?;
+
+        Ok(definitions)
+    }
+
+    #[instrument(name = "AttributeDefinition::create", skip_all, err)]
+    pub async fn create(
+        category_id: i32,
+        input: AttributeDefinitionInput,
+        pool: &PostgresPool,
+    ) -> Result<AttributeDefinition> {
+        input.validate()?;
+
+        let mut tx = pool.begin().await?;
+        let definition = sqlx::query_as!(
+            AttributeDefinition,
+            r#"
+              INSERT INTO attribute_definitions (category_id, key, kind, required, unit, options)
+                  VALUES ($1, $2, $3, $4, $5, $6)
+               RETURNING id, category_id, key, kind, required, unit, options, updated_at, created_at
+            "#,
+            category_id,
+            input.key,
+            input.kind,
+            input.required,
+            input.unit,
+            &input.options
+        )
+        .fetch_one(&mut tx)
+        .await?;
+        validate_category_products(category_id, &mut tx).await?;
+        tx.commit().await?;
+
+        Ok(definition)
+    }
+
+    #[instrument(name = "AttributeDefinition::update", skip_all, err)]
+    pub async fn update(
+        id: i32,
+        input: AttributeDefinitionInput,
+        pool: &PostgresPool,
+    ) -> Result<AttributeDefinition> {
+        input.validate()?;
+
+        let mut tx = pool.begin().await?;
+        let definition = sqlx::query_as!(
+            AttributeDefinition,
+            r#"
+              UPDATE attribute_definitions
+                 SET key = $1, kind = $2, required = $3, unit = $4, options = $5, updated_at = NOW()
+               WHERE id = $6
+               RETURNING id, category_id, key, kind, required, unit, options, updated_at, created_at
+            "#,
+            input.key,
+            input.kind,
+            input.required,
+            input.unit,
+            &input.options,
+            id
+        )
+        .fetch_one(&mut tx)
+        .await?;
+        validate_category_products(definition.category_id, &mut tx).await?;
+        tx.commit().await?;
+
+        Ok(definition)
+    }
+
+    #[instrument(name = "AttributeDefinition::delete", skip_all, err)]
+    pub async fn delete(id: i32, pool: &PostgresPool) -> Result<u64> {
+        let mut tx = pool.begin().await?;
+        let result = sqlx::query!("DELETE FROM attribute_definitions WHERE id = $1", id)
+            .execute(&mut tx)
+            .await?;
+
+        tx.commit().await?;
+        Ok(result.rows_affected())
+    }
+
+    /// Turns `attr.<key>=<value>` query parameters into a JSON array of JSONB
+    /// containment filters, of which a product has to match one. A key defined
+    /// with different kinds in different categories gets a filter for every kind
+    /// its value can be read as.
+    #[instrument(name = "AttributeDefinition::filter_from_query", skip_all, err)]
+    pub async fn filter_from_query(
+        params: &HashMap<String, String>,
+        pool: &PostgresPool,
+    ) -> Result<Option<JsonValue>> {
+        let wanted: HashMap<&str, &str> = params
+            .iter()
+            .filter_map(|(name, value)| Some((name.strip_prefix("attr.")?, value.as_str())))
+            .collect();
+        if wanted.is_empty() {
+            return Ok(None);
+        }
+
+        let keys: Vec<String> = wanted.keys().map(|key| key.to_string()).collect();
+        let mut kinds: HashMap<String, Vec<String>> = HashMap::new();
+        for row in sqlx::query!(
+            "SELECT DISTINCT key, kind FROM attribute_definitions WHERE key = ANY($1) ORDER BY key, kind",
+            &keys
+        )
+        .fetch_all(pool)
+        .await?
+        {
+            kinds.entry(row.key).or_default().push(row.kind);
+        }
+
+        let mut filters = vec![Map::new()];
+        for (key, value) in wanted {
+            let values = typed_values(key, value, kinds.get(key).map(Vec::as_slice))?;
+            filters = filters
+                .iter()
+                .flat_map(|filter| {
+                    values.iter().map(move |value| {
+                        let mut filter = filter.clone();
+                        filter.insert(key.to_string(), value.clone());
+                        filter
+                    })
+                })
+                .collect();
+        }
+
+        Ok(Some(JsonValue::Array(
+            filters.into_iter().map(JsonValue::Object).collect(),
+        )))
+    }
+}
+
+/// The values a query parameter stands for under each of `kinds`; a plain string
+/// when the key has no definition.
+fn typed_values(key: &str, value: &str, kinds: Option<&[String]>) -> Result<Vec<JsonValue>> {
+    let kinds = match kinds {
+        Some(kinds) => kinds,
+        None => return Ok(vec![JsonValue::String(value.to_string())]),
+    };
+
+    let mut values = Vec::new();
+    let mut problem = None;
+    for kind in kinds {
+        let typed = match kind.as_str() {
+            "number" => serde_json::from_str::<serde_json::Number>(value)
+                .map(JsonValue::Number)
+                .map_err(|_| anyhow!("attribute {} must be a number", key)),
+            "boolean" => value
+                .parse::<bool>()
+                .map(JsonValue::Bool)
+                .map_err(|_| anyhow!("attribute {} must be true or false", key)),
+            _ => Ok(JsonValue::String(value.to_string())),
+        };
+        match typed {
+            Ok(typed) if !values.contains(&typed) => values.push(typed),
+            Ok(_) => {}
+            Err(err) => problem = Some(err),
+        }
+    }
+
+    match problem {
+        Some(err) if values.is_empty() => Err(err),
+        _ => Ok(values),
+    }
+}
+
+/// Checks the products linked to category `category_id` or its descendants against
+/// the definitions that apply to them, e.g. after a definition changed or the
+/// category moved. The products are locked until `tx` ends.
+pub async fn validate_category_products(
+    category_id: i32,
+    tx: &mut Transaction<'_, Postgres>,
+) -> Result<()> {
+    let products = sqlx::query!(
+        r#"
+          WITH RECURSIVE subtree AS (
+              SELECT id FROM categories WHERE id = $1
+            UNION ALL
+              SELECT c.id FROM categories c JOIN subtree s ON c.parent_id = s.id
+          )
+          SELECT p.id, p.attributes FROM products p
+           WHERE p.id IN (
+              SELECT pc.product_id FROM product_categories pc JOIN subtree s ON s.id = pc.category_id
+           )
+          ORDER BY p.id
+          FOR UPDATE OF p
+        "#,
+        category_id
+    )
+    .fetch_all(&mut *tx)
+    .await?;
+
+    for product in products {
+        let definitions = AttributeDefinition::find_for_product(product.id, tx).await?;
+        validate(&product.attributes, &definitions)
+            .map_err(|err| anyhow!("product {}: {}", product.id, err))?;
+    }
+    Ok(())
+}
+
+/// Checks a product's attribute object against the definitions that apply to it.
+/// Keys without a definition are kept as free-form values.
+pub fn validate(attributes: &JsonValue, definitions: &[AttributeDefinition]) -> Result<()> {
+    let attributes = attributes
+        .as_object()
+        .ok_or_else(|| anyhow!("attributes must be a JSON object"))?;
+
+    for definition in definitions {
+        let value = match attributes.get(&definition.key) {
+            Some(JsonValue::Null) | None if definition.required => {
+                return Err(anyhow!("attribute {} is required", definition.key))
+            }
+            Some(JsonValue::Null) | None => continue,
+            Some(value) => value,
+        };
+
+        let valid = match definition.kind.as_str() {
+            "string" => value.is_string(),
+            "number" => value.is_number(),
+            "boolean" => value.is_boolean(),
+            "enum" => value
+                .as_str()
+                .map(|value| definition.options.iter().any(|option| option == value))
+                .unwrap_or(false),
+            _ => false,
+        };
+        if !valid {
+            return Err(anyhow!(
+                "attribute {} must be of kind {}",
+                definition.key,
+                definition.kind
+            ));
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn reads_a_value_under_every_kind_of_its_key() {
+        let kinds = vec!["number".to_string(), "text".to_string()];
+        let values = typed_values("size", "42", Some(&kinds)).unwrap();
+        assert_eq!(values, vec![json!(42), json!("42")]);
+
+        let values = typed_values("size", "large", Some(&kinds)).unwrap();
+        assert_eq!(values, vec![json!("large")]);
+    }
+
+    #[test]
+    fn rejects_a_value_no_kind_can_read() {
+        let kinds = vec!["number".to_string(), "boolean".to_string()];
+        assert!(typed_values("size", "large", Some(&kinds)).is_err());
+        assert_eq!(
+            typed_values("size", "large", None).unwrap(),
+            vec![json!("large")]
+        );
+    }
+}
@@ -6,6 +6,7 @@ use crate::{
 };
 use anyhow::Result;
 use serde::{Deserialize, Serialize};
+use tracing::instrument;
 
 
 #[derive(Deserialize)]
@@ -20,20 +21,22 @@ pub struct Auth {
 }
 
 impl Auth {
+    #[instrument(name = "Auth::authenticate", skip_all)]
     pub async fn authenticate(
         credentials: Credentials,
+        salt: &str,
         pool: &PostgresPool,
     ) -> Result<User, ServiceError> {
         let result = User::find_by_username(&credentials.username, pool).await;
         match result {
             Ok(user) => {
                 // TODO: figure out why I keep getting hacked
-                if auth::hash(&credentials.password) == user.password {
+                if auth::hash(&credentials.password, salt) == user.password {
                     return Ok(user);
                 }
-                return Err(ServiceError::Unauthorized);
+                Err(ServiceError::Unauthorized)
             }
-            _ => return Err(ServiceError::Unauthorized),
+            _ => Err(ServiceError::Unauthorized),
         }
     }
 }
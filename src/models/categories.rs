@@ -0,0 +1,529 @@
+use crate::{
+    events::DomainEvent,
+    models::attributes::{self, AttributeDefinition},
+    models::outbox::Outbox,
+    types::PostgresPool,
+};
+use anyhow::{anyhow, Result};
+use chrono::NaiveDateTime;
+use serde::{Deserialize, Serialize};
+use sqlx::{FromRow, Postgres, Transaction};
+use std::collections::HashMap;
+use tracing::instrument;
+
+#[derive(Serialize, Deserialize)]
+pub struct CategoryInput {
+    pub parent_id: Option<i32>,
+    pub name: String,
+    pub slug: String,
+    pub position: Option<i32>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct MoveInput {
+    pub parent_id: Option<i32>,
+    pub position: i32,
+}
+
+#[derive(Serialize, FromRow, Debug)]
+pub struct Category {
+    pub id: i32,
+    pub parent_id: Option<i32>,
+    pub name: String,
+    pub slug: String,
+    pub position: i32,
+    pub updated_at: NaiveDateTime,
+    pub created_at: NaiveDateTime,
+}
+
+#[derive(Serialize, Debug)]
+pub struct CategoryNode {
+    #[serde(flatten)]
+    pub category: Category,
+    pub children: Vec<CategoryNode>,
+}
+
+fn validate_slug(slug: &str) -> Result<()> {
+    let valid = !slug.is_empty()
+        && slug
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-');
+    if valid {
+        Ok(())
+    } else {
+        Err(anyhow!("invalid slug: {}", slug))
+    }
+}
+
+/// Nests `categories` under their parents, keeping their order among siblings.
+/// Categories whose parent is missing are left out.
+fn build_tree(categories: Vec<Category>) -> Vec<CategoryNode> {
+    let mut by_parent: HashMap<Option<i32>, Vec<Category>> = HashMap::new();
+    for category in categories {
+        by_parent
+            .entry(category.parent_id)
+            .or_default()
+            .push(category);
+    }
+
+    fn build(
+        parent_id: Option<i32>,
+        by_parent: &mut HashMap<Option<i32>, Vec<Category>>,
+    ) -> Vec<CategoryNode> {
+        by_parent
+            .remove(&parent_id)
+            .unwrap_or_default()
+            .into_iter()
+            .map(|category| {
+                let children = build(Some(category.id), by_parent);
+                CategoryNode { category, children }
+            })
+            .collect()
+    }
+
+    build(None, &mut by_parent)
+}
+
+/// Products linked to category `id` or any of its descendants, whose search
+/// documents change with the category.
+async fn subtree_product_ids(id: i32, tx: &mut Transaction<'_, Postgres>) -> Result<Vec<i32>> {
+    let ids = sqlx::query_scalar!(
+        r#"
+          WITH RECURSIVE subtree AS (
+              SELECT id FROM categories WHERE id = $1
+            UNION ALL
+              SELECT c.id FROM categories c JOIN subtree s ON c.parent_id = s.id
+          )
+          SELECT DISTINCT pc.product_id AS "product_id!"
+              FROM product_categories pc
+              JOIN subtree s ON s.id = pc.category_id
+          ORDER BY 1
+        "#,
+        id
+    )
+    .fetch_all(&mut *tx)
+    .await?;
+
+    Ok(ids)
+}
+
+impl Category {
+    #[instrument(name = "Category::find_all", skip_all, err)]
+    pub async fn find_all(pool: &PostgresPool) -> Result<Vec<Category>> {
+        let categories = sqlx::query_as!(
+            Category,
+            r#"
+              SELECT id, parent_id, name, slug, position, updated_at, created_at
+                  FROM categories
+              ORDER BY position, name
+            "#
+        )
+        .fetch_all(pool)
+        .await?;
+
+        Ok(categories)
+    }
+
+    /// Returns all categories nested under their parents, siblings ordered by position.
+    #[instrument(name = "Category::find_tree", skip_all, err)]
+    pub async fn find_tree(pool: &PostgresPool) -> Result<Vec<CategoryNode>> {
+        Ok(build_tree(Category::find_all(pool).await?))
+    }
+
+    #[instrument(name = "Category::find_by_id", skip_all, err)]
+    pub async fn find_by_id(id: i32, pool: &PostgresPool) -> Result<Category> {
+        let category = sqlx::query_as!(
+            Category,
+            r#"
+              SELECT * FROM categories WHERE id = $1
+            "#,
+            id
+        )
+        .fetch_one(&*pool)
+        .await?;
+
+        Ok(category)
+    }
+
+    #[instrument(name = "Category::find_by_product", skip_all, err)]
+    pub async fn find_by_product(product_id: i32, pool: &PostgresPool) -> Result<Vec<Category>> {
+        let categories = sqlx::query_as!(
+            Category,
+            r#"
+              SELECT c.id, c.parent_id, c.name, c.slug, c.position, c.updated_at, c.created_at
+                  FROM categories c
+                  JOIN product_categories pc ON pc.category_id = c.id
+               WHERE pc.product_id = $1
+              ORDER BY c.position, c.name
+            "#,
+            product_id
+        )
+        .fetch_all(pool)
+        .await?;
+
+        Ok(categories)
+    }
+
+    #[instrument(name = "Category::create", skip_all, err)]
+    pub async fn create(input: CategoryInput, pool: &PostgresPool) -> Result<Category> {
+        validate_slug(&input.slug)?;
+
+        let mut tx = pool.begin().await?;
+        let category = sqlx::query_as!(
+            Category,
+            r#"
+              INSERT INTO categories (parent_id, name, slug, position) VALUES ($1, $2, $3, $4)
+               RETURNING id, parent_id, name, slug, position, updated_at, created_at
+            "#,
+            input.parent_id,
+            input.name,
+            input.slug,
+            input.position.unwrap_or(0)
+        )
+        .fetch_one(&mut tx)
+        .await?;
+        tx.commit().await?;
+
+        Ok(category)
+    }
+
+    /// Renames a category. Use `move_to` to change its place in the tree.
+    #[instrument(name = "Category::update", skip_all, err)]
+    pub async fn update(id: i32, input: CategoryInput, pool: &PostgresPool) -> Result<Category> {
+        validate_slug(&input.slug)?;
+
+        let mut tx = pool.begin().await?;
+        let category = sqlx::query_as!(
+            Category,
+            r#"
+              UPDATE categories SET name = $1, slug = $2, updated_at = NOW() WHERE id = $3
+               RETURNING id, parent_id, name, slug, position, updated_at, created_at
+            "#,
+            input.name,
+            input.slug,
+            id
+        )
+        .fetch_one(&mut tx)
+        .await?;
+        let event = DomainEvent::CategoryUpdated {
+            category_id: id,
+            product_ids: subtree_product_ids(id, &mut tx).await?,
+        };
+        Outbox::publish(&event, &mut tx).await?;
+        tx.commit().await?;
+
+        Ok(category)
+    }
+
+    /// Moves a category, together with its whole subtree, below `parent_id` at `position`.
+    /// Siblings at or after the target position are shifted down by one.
+    #[instrument(name = "Category::move_to", skip_all, err)]
+    pub async fn move_to(id: i32, input: MoveInput, pool: &PostgresPool) -> Result<Category> {
+        let mut tx = pool.begin().await?;
+
+        // The category and every ancestor of its new parent are locked while walking
+        // up, so a concurrent move can't change the path checked for a cycle.
+        sqlx::query!("SELECT id FROM categories WHERE id = $1 FOR UPDATE", id)
+            .fetch_one(&mut tx)
+            .await?;
+        let mut ancestor = input.parent_id;
+        while let Some(ancestor_id) = ancestor {
+            if ancestor_id == id {
+                return Err(anyhow!("cannot move a category below itself"));
+            }
+            ancestor = sqlx::query_scalar!(
+                "SELECT parent_id FROM categories WHERE id = $1 FOR UPDATE",
+                ancestor_id
+            )
+            .fetch_one(&mut tx)
+            .await?;
+        }
+
+        sqlx::query!(
+            r#"
+              UPDATE categories SET position = position + 1
+               WHERE parent_id IS NOT DISTINCT FROM $1 AND position >= $2 AND id <> $3
+            "#,
+            input.parent_id,
+            input.position,
+            id
+        )
+        .execute(&mut tx)
+        .await?;
+
+        let category = sqlx::query_as!(
+            Category,
+            r#"
+              UPDATE categories SET parent_id = $1, position = $2, updated_at = NOW() WHERE id = $3
+               RETURNING id, parent_id, name, slug, position, updated_at, created_at
+            "#,
+            input.parent_id,
+            input.position,
+            id
+        )
+        .fetch_one(&mut tx)
+        .await?;
+        // the subtree's products now fall under the definitions of other ancestors
+        attributes::validate_category_products(id, &mut tx).await?;
+        let event = DomainEvent::CategoryMoved {
+            category_id: id,
+            parent_id: input.parent_id,
+            product_ids: subtree_product_ids(id, &mut tx).await?,
+        };
+        Outbox::publish(&event, &mut tx).await?;
+        tx.commit().await?;
+
+        Ok(category)
+    }
+
+    /// Deletes a category and, through the foreign key, all of its descendants.
+    #[instrument(name = "Category::delete", skip_all, err)]
+    pub async fn delete(id: i32, pool: &PostgresPool) -> Result<u64> {
+        let mut tx = pool.begin().await?;
+        // read first, the links go with the categories
+        let product_ids = subtree_product_ids(id, &mut tx).await?;
+        let result = sqlx::query!("DELETE FROM categories WHERE id = $1", id)
+            .execute(&mut tx)
+            .await?;
+        if result.rows_affected() > 0 {
+            let event = DomainEvent::CategoryDeleted {
+                category_id: id,
+                product_ids,
+            };
+            Outbox::publish(&event, &mut tx).await?;
+        }
+
+        tx.commit().await?;
+        Ok(result.rows_affected())
+    }
+
+    /// Replaces the set of categories a product is linked to. Fails when the product's
+    /// attributes don't satisfy the definitions of its new categories.
+    #[instrument(name = "Category::set_for_product", skip_all, err)]
+    pub async fn set_for_product(
+        product_id: i32,
+        category_ids: Vec<i32>,
+        pool: &PostgresPool,
+    ) -> Result<Vec<Category>> {
+        let mut tx = pool.begin().await?;
+        // locked, so that a concurrent update can't slip in attributes that weren't checked
+        let product_attributes = sqlx::query_scalar!(
+            "SELECT attributes FROM products WHERE id = $1 FOR UPDATE",
+            product_id
+        )
+        .fetch_one(&mut tx)
+        .await?;
+        sqlx::query!(
+            "DELETE FROM product_categories WHERE product_id = $1",
+            product_id
+        )
+        .execute(&mut tx)
+        .await?;
+
+        sqlx::query!(
+            r#"
+              INSERT INTO product_categories (product_id, category_id)
+                  SELECT $1, UNNEST($2::int[])
+              ON CONFLICT DO NOTHING
+            "#,
+            product_id,
+            &category_ids
+        )
+        .execute(&mut tx)
+        .await?;
+        let definitions = AttributeDefinition::find_for_product(product_id, &mut tx).await?;
+        attributes::validate(&product_attributes, &definitions)?;
+        let event = DomainEvent::ProductCategoriesChanged {
+            product_id,
+            category_ids,
+        };
+        Outbox::publish(&event, &mut tx).await?;
+        tx.commit().await?;
+
+        Category::find_by_product(product_id, pool).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support;
+    use chrono::Utc;
+    use uuid::Uuid;
+
+    fn category(id: i32, parent_id: Option<i32>) -> Category {
+        let now = Utc::now().naive_utc();
+        Category {
+            id,
+            parent_id,
+            name: format!("Category {}", id),
+            slug: format!("category-{}", id),
+            position: 0,
+            updated_at: now,
+            created_at: now,
+        }
+    }
+
+    fn ids(nodes: &[CategoryNode]) -> Vec<i32> {
+        nodes.iter().map(|node| node.category.id).collect()
+    }
+
+    #[test]
+    fn builds_the_tree_in_order() {
+        let tree = build_tree(vec![
+            category(3, Some(1)),
+            category(1, None),
+            category(4, Some(3)),
+            category(2, None),
+            category(5, Some(1)),
+        ]);
+
+        assert_eq!(ids(&tree), vec![1, 2]);
+        assert_eq!(ids(&tree[0].children), vec![3, 5]);
+        assert_eq!(ids(&tree[0].children[0].children), vec![4]);
+        assert!(tree[1].children.is_empty());
+    }
+
+    #[test]
+    fn leaves_out_categories_without_a_parent() {
+        let tree = build_tree(vec![category(1, None), category(2, Some(9))]);
+        assert_eq!(ids(&tree), vec![1]);
+        assert!(tree[0].children.is_empty());
+    }
+
+    async fn create(parent_id: Option<i32>, position: i32, pool: &PostgresPool) -> Category {
+        let input = CategoryInput {
+            parent_id,
+            name: "Test category".to_string(),
+            slug: format!("test-{}", Uuid::new_v4()),
+            position: Some(position),
+        };
+        Category::create(input, pool).await.unwrap()
+    }
+
+    async fn events(category_id: i32, pool: &PostgresPool) -> Vec<DomainEvent> {
+        let payloads = sqlx::query_scalar!(
+            r#"
+              SELECT payload FROM outbox
+               WHERE aggregate_type = 'category' AND aggregate_id = $1
+              ORDER BY id
+            "#,
+            category_id.to_string()
+        )
+        .fetch_all(pool)
+        .await
+        .unwrap();
+        payloads
+            .into_iter()
+            .map(|payload| serde_json::from_value(payload).unwrap())
+            .collect()
+    }
+
+    #[actix_web::test]
+    #[ignore = "needs Postgres at TEST_DATABASE_URL"]
+    async fn refuses_to_move_a_category_below_itself() {
+        let pool = test_support::pool().await;
+        let root = create(None, 0, &pool).await;
+        let child = create(Some(root.id), 0, &pool).await;
+        let grandchild = create(Some(child.id), 0, &pool).await;
+
+        for parent_id in [root.id, grandchild.id] {
+            let input = MoveInput {
+                parent_id: Some(parent_id),
+                position: 0,
+            };
+            assert!(Category::move_to(root.id, input, &pool).await.is_err());
+        }
+        let root = Category::find_by_id(root.id, &pool).await.unwrap();
+        assert_eq!(root.parent_id, None);
+
+        Category::delete(root.id, &pool).await.unwrap();
+    }
+
+    #[actix_web::test]
+    #[ignore = "needs Postgres at TEST_DATABASE_URL"]
+    async fn moves_a_subtree_and_shifts_its_new_siblings() {
+        let pool = test_support::pool().await;
+        let root = create(None, 0, &pool).await;
+        let first = create(Some(root.id), 0, &pool).await;
+        let second = create(Some(root.id), 1, &pool).await;
+        let other = create(None, 0, &pool).await;
+        let moved = create(Some(other.id), 0, &pool).await;
+        let below = create(Some(moved.id), 0, &pool).await;
+
+        let input = MoveInput {
+            parent_id: Some(root.id),
+            position: 1,
+        };
+        let category = Category::move_to(moved.id, input, &pool).await.unwrap();
+        assert_eq!((category.parent_id, category.position), (Some(root.id), 1));
+
+        let first = Category::find_by_id(first.id, &pool).await.unwrap();
+        let second = Category::find_by_id(second.id, &pool).await.unwrap();
+        let below = Category::find_by_id(below.id, &pool).await.unwrap();
+        assert_eq!(first.position, 0);
+        assert_eq!(second.position, 2);
+        assert_eq!(below.parent_id, Some(moved.id));
+        assert!(matches!(
+            events(moved.id, &pool).await.as_slice(),
+            [DomainEvent::CategoryMoved { parent_id, .. }] if *parent_id == Some(root.id)
+        ));
+
+        Category::delete(root.id, &pool).await.unwrap();
+        Category::delete(other.id, &pool).await.unwrap();
+    }
+
+    #[actix_web::test]
+    #[ignore = "needs Postgres at TEST_DATABASE_URL"]
+    async fn publishes_the_affected_products() {
+        let pool = test_support::pool().await;
+        let root = create(None, 0, &pool).await;
+        let child = create(Some(root.id), 0, &pool).await;
+        let product_id = sqlx::query_scalar!(
+            r#"
+              INSERT INTO products (name, price, origin, cultivar)
+                  VALUES ('Test coffee', 1200, 'Test origin', 'Test cultivar')
+               RETURNING id
+            "#
+        )
+        .fetch_one(&pool)
+        .await
+        .unwrap();
+        Category::set_for_product(product_id, vec![child.id], &pool)
+            .await
+            .unwrap();
+
+        let input = CategoryInput {
+            parent_id: None,
+            name: "Renamed".to_string(),
+            slug: format!("renamed-{}", Uuid::new_v4()),
+            position: None,
+        };
+        Category::update(root.id, input, &pool).await.unwrap();
+        assert_eq!(Category::delete(root.id, &pool).await.unwrap(), 1);
+
+        match events(root.id, &pool).await.as_slice() {
+            [DomainEvent::CategoryUpdated {
+                product_ids: updated,
+                ..
+            }, DomainEvent::CategoryDeleted {
+                product_ids: deleted,
+                ..
+            }] => {
+                assert_eq!(updated, &vec![product_id]);
+                assert_eq!(deleted, &vec![product_id]);
+            }
+            events => panic!("unexpected events: {:?}", events),
+        }
+        assert!(Category::find_by_id(child.id, &pool).await.is_err());
+        assert!(Category::find_by_product(product_id, &pool)
+            .await
+            .unwrap()
+            .is_empty());
+
+        sqlx::query!("DELETE FROM products WHERE id = $1", product_id)
+            .execute(&pool)
+            .await
+            .unwrap();
+    }
+}
@@ -0,0 +1,368 @@
+use crate::types::PostgresPool;
+use anyhow::{anyhow, Result};
+use chrono::NaiveDateTime;
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+use std::collections::HashSet;
+use tracing::instrument;
+
+// rates are stored ECB style: units of `currency` per one unit of BASE_CURRENCY
+pub const BASE_CURRENCY: &str = "EUR";
+
+#[derive(Serialize, Deserialize)]
+pub struct ExchangeRateInput {
+    pub rate: f64,
+}
+
+#[derive(Serialize, FromRow, Debug)]
+pub struct ExchangeRate {
+    pub currency: String,
+    pub rate: f64,
+    pub updated_at: NaiveDateTime,
+    pub created_at: NaiveDateTime,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ProductPriceInput {
+    pub currency: String,
+    pub amount: i64,
+}
+
+#[derive(Serialize, FromRow, Debug)]
+pub struct ProductPrice {
+    pub product_id: i32,
+    pub currency: String,
+    pub amount: i64,
+    pub updated_at: NaiveDateTime,
+    pub created_at: NaiveDateTime,
+}
+
+/// Validates and upper-cases an ISO 4217 currency code.
+pub fn normalize(code: &str) -> Result<String> {
+    let code = code.trim().to_ascii_uppercase();
+    if code.len() == 3 && code.chars().all(|c| c.is_ascii_alphabetic()) {
+        Ok(code)
+    } else {
+        Err(anyhow!("invalid currency code: {}", code))
+    }
+}
+
+impl ExchangeRate {
+    #[instrument(name = "ExchangeRate::find_all", skip_all, err)]
+    pub async fn find_all(pool: &PostgresPool) -> Result<Vec<ExchangeRate>> {
+        let rates = sqlx::query_as!(
+            ExchangeRate,
+            r#"
+              SELECT currency, rate, updated_at, created_at
+                  FROM exchange_rates
+              ORDER BY currency
+            "#
+        )
+        .fetch_all(pool)
+        .await?;
+
+        Ok(rates)
+    }
+
+    #[instrument(name = "ExchangeRate::find", skip_all, err)]
+    pub async fn find(currency: &str, pool: &PostgresPool) -> Result<ExchangeRate> {
+        let rate = sqlx::query_as!(
+            ExchangeRate,
+            r#"
+              SELECT * FROM exchange_rates WHERE currency = $1
+            "#,
+            currency
+        )
+        .fetch_one(&*pool)
+        .await?;
+
+        Ok(rate)
+    }
+
+    #[instrument(name = "ExchangeRate::upsert", skip_all, err)]
+    pub async fn upsert(currency: &str, rate: f64, pool: &PostgresPool) -> Result<ExchangeRate> {
+        let currency = normalize(currency)?;
+        if !rate.is_finite() || rate <= 0.0 {
+            return Err(anyhow!("exchange rate must be positive"));
+        }
+
+        let mut tx = pool.begin().await?;
+        let rate = sqlx::query_as!(
+            ExchangeRate,
+            r#"
+              INSERT INTO exchange_rates (currency, rate) VALUES ($1, $2)
+                  ON CONFLICT (currency) DO UPDATE SET rate = EXCLUDED.rate, updated_at = NOW()
+               RETURNING currency, rate, updated_at, created_at
+            "#,
+            currency,
+            rate
+        )
+        .fetch_one(&mut tx)
+        .await?;
+        tx.commit().await?;
+
+        Ok(rate)
+    }
+
+    /// Imports an ECB reference rate file (eurofxref XML or CSV) in a single transaction.
+    #[instrument(name = "ExchangeRate::import", skip_all, err)]
+    pub async fn import(body: &str, pool: &PostgresPool) -> Result<Vec<ExchangeRate>> {
+        let parsed = parse_rates(body)?;
+
+        let mut tx = pool.begin().await?;
+        let mut rates = Vec::with_capacity(parsed.len());
+        for (currency, rate) in parsed {
+            let rate = sqlx::query_as!(
+                ExchangeRate,
+                r#"
+                  INSERT INTO exchange_rates (currency, rate) VALUES ($1, $2)
+                      ON CONFLICT (currency) DO UPDATE SET rate = EXCLUDED.rate, updated_at = NOW()
+                   RETURNING currency, rate, updated_at, created_at
+                "#,
+                currency,
+                rate
+            )
+            .fetch_one(&mut tx)
+            .await?;
+            rates.push(rate);
+        }
+        tx.commit().await?;
+
+        Ok(rates)
+    }
+
+    #[instrument(name = "ExchangeRate::delete", skip_all, err)]
+    pub async fn delete(currency: &str, pool: &PostgresPool) -> Result<u64> {
+        if currency == BASE_CURRENCY {
+            return Err(anyhow!("the base currency rate cannot be removed"));
+        }
+
+        let mut tx = pool.begin().await?;
+        let result = sqlx::query!("DELETE FROM exchange_rates WHERE currency = $1", currency)
+            .execute(&mut tx)
+            .await?;
+
+        tx.commit().await?;
+        Ok(result.rows_affected())
+    }
+}
+
+impl ProductPrice {
+    #[instrument(name = "ProductPrice::find_by_product", skip_all, err)]
+    pub async fn find_by_product(
+        product_id: i32,
+        pool: &PostgresPool,
+    ) -> Result<Vec<ProductPrice>> {
+        let prices = sqlx::query_as!(
+            ProductPrice,
+            r#"
+              SELECT product_id, currency, amount, updated_at, created_at
+                  FROM product_prices
+               WHERE product_id = $1
+              ORDER BY currency
+            "#,
+            product_id
+        )
+        .fetch_all(pool)
+        .await?;
+
+        Ok(prices)
+    }
+
+    #[instrument(name = "ProductPrice::upsert", skip_all, err)]
+    pub async fn upsert(
+        product_id: i32,
+        input: ProductPriceInput,
+        pool: &PostgresPool,
+    ) -> Result<ProductPrice> {
+        let currency = normalize(&input.currency)?;
+
+        let mut tx = pool.begin().await?;
+        let price = sqlx::query_as!(
+            ProductPrice,
+            r#"
+              INSERT INTO product_prices (product_id, currency, amount) VALUES ($1, $2, $3)
+                  ON CONFLICT (product_id, currency) DO UPDATE SET amount = EXCLUDED.amount, updated_at = NOW()
+               RETURNING product_id, currency, amount, updated_at, created_at
+            "#,
+            product_id,
+            currency,
+            input.amount
+        )
+        .fetch_one(&mut tx)
+        .await?;
+        tx.commit().await?;
+
+        Ok(price)
+    }
+
+    #[instrument(name = "ProductPrice::delete", skip_all, err)]
+    pub async fn delete(product_id: i32, currency: &str, pool: &PostgresPool) -> Result<u64> {
+        let mut tx = pool.begin().await?;
+        let result = sqlx::query!(
+            "DELETE FROM product_prices WHERE product_id = $1 AND currency = $2",
+            product_id,
+            currency
+        )
+        .execute(&mut tx)
+        .await?;
+
+        tx.commit().await?;
+        Ok(result.rows_affected())
+    }
+}
+
+/// Parses either the ECB daily XML (`<Cube currency='USD' rate='1.07'/>`) or CSV.
+/// CSV may be the ECB layout (a `Date, USD, JPY, ...` header followed by one row
+/// of rates) or plain `currency,rate` lines. A currency listed more than once keeps
+/// its first rate, the newest one in the ECB historical files.
+pub fn parse_rates(body: &str) -> Result<Vec<(String, f64)>> {
+    let body = body.trim_start_matches('\u{feff}').trim();
+    let mut rates = if body.starts_with('<') {
+        parse_xml(body)?
+    } else {
+        parse_csv(body)?
+    };
+    let mut seen = HashSet::new();
+    rates.retain(|(currency, _)| seen.insert(currency.clone()));
+
+    if rates.is_empty() {
+        return Err(anyhow!("no exchange rates found"));
+    }
+    Ok(rates)
+}
+
+fn parse_xml(body: &str) -> Result<Vec<(String, f64)>> {
+    let mut rates = Vec::new();
+    for tag in body.split('<').filter(|tag| tag.starts_with("Cube ")) {
+        if let (Some(currency), Some(rate)) = (attribute(tag, "currency"), attribute(tag, "rate")) {
+            rates.push((normalize(currency)?, parse_rate(rate)?));
+        }
+    }
+    Ok(rates)
+}
+
+fn attribute<'a>(tag: &'a str, name: &str) -> Option<&'a str> {
+    let start = tag.find(&format!("{}=", name))? + name.len() + 1;
+    let quote = tag[start..].chars().next()?;
+    let value = &tag[start + 1..];
+    let end = value.find(quote)?;
+    Some(&value[..end])
+}
+
+fn parse_csv(body: &str) -> Result<Vec<(String, f64)>> {
+    let mut lines = body.lines().filter(|line| !line.trim().is_empty());
+    let fields = |line: &str| -> Vec<String> {
+        line.split(',')
+            .map(|field| field.trim().to_string())
+            .filter(|field| !field.is_empty())
+            .collect()
+    };
+
+    let first = match lines.next() {
+        Some(line) => fields(line),
+        None => return Ok(Vec::new()),
+    };
+
+    if matches!(first.first(), Some(field) if field.eq_ignore_ascii_case("date")) {
+        let values = lines.next().map(fields).unwrap_or_default();
+        return first
+            .iter()
+            .zip(values.iter())
+            .skip(1)
+            .filter(|(_, rate)| !rate.eq_ignore_ascii_case("n/a"))
+            .map(|(currency, rate)| -> Result<(String, f64)> {
+                Ok((normalize(currency)?, parse_rate(rate)?))
+            })
+            .collect();
+    }
+
+    let mut rates = Vec::new();
+    for (index, row) in std::iter::once(first).chain(lines.map(fields)).enumerate() {
+        match row.as_slice() {
+            [currency, rate] if rate.parse::<f64>().is_ok() => {
+                rates.push((normalize(currency)?, parse_rate(rate)?))
+            }
+            // header line
+            [_, _] if index == 0 => continue,
+            _ => return Err(anyhow!("malformed exchange rate line: {}", row.join(","))),
+        }
+    }
+    Ok(rates)
+}
+
+fn parse_rate(value: &str) -> Result<f64> {
+    match value.trim().parse::<f64>() {
+        Ok(rate) if rate.is_finite() && rate > 0.0 => Ok(rate),
+        _ => Err(anyhow!("invalid exchange rate: {}", value)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ECB_DAILY: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<gesmes:Envelope xmlns:gesmes="http://www.gesmes.org/xml/2002-08-01" xmlns="http://www.ecb.int/vocabulary/2002-08-01/eurofxref">
+  <gesmes:subject>Reference rates</gesmes:subject>
+  <Cube>
+    <Cube time='2022-10-19'>
+      <Cube currency='USD' rate='0.9835'/>
+      <Cube currency='JPY' rate='147.21'/>
+    </Cube>
+  </Cube>
+</gesmes:Envelope>"#;
+
+    const ECB_HISTORY: &str = r#"<gesmes:Envelope>
+  <Cube>
+    <Cube time="2022-10-19"><Cube currency="USD" rate="0.9835"/><Cube currency="JPY" rate="147.21"/></Cube>
+    <Cube time="2022-10-18"><Cube currency="USD" rate="0.9863"/><Cube currency="JPY" rate="146.82"/></Cube>
+  </Cube>
+</gesmes:Envelope>"#;
+
+    #[test]
+    fn parses_the_ecb_daily_xml() {
+        let rates = parse_rates(ECB_DAILY).unwrap();
+        assert_eq!(
+            rates,
+            vec![("USD".to_string(), 0.9835), ("JPY".to_string(), 147.21)]
+        );
+    }
+
+    #[test]
+    fn keeps_the_newest_rate_of_the_ecb_history() {
+        let rates = parse_rates(ECB_HISTORY).unwrap();
+        assert_eq!(
+            rates,
+            vec![("USD".to_string(), 0.9835), ("JPY".to_string(), 147.21)]
+        );
+    }
+
+    #[test]
+    fn parses_the_ecb_csv_layout() {
+        let body = "\u{feff}Date, USD, JPY, BGN, \n19 October 2022, 0.9835, 147.21, N/A, \n";
+        let rates = parse_rates(body).unwrap();
+        assert_eq!(
+            rates,
+            vec![("USD".to_string(), 0.9835), ("JPY".to_string(), 147.21)]
+        );
+    }
+
+    #[test]
+    fn parses_plain_csv_lines() {
+        let rates = parse_rates("currency,rate\nusd,0.98\n\nchf,0.97\nusd,1.5\n").unwrap();
+        assert_eq!(
+            rates,
+            vec![("USD".to_string(), 0.98), ("CHF".to_string(), 0.97)]
+        );
+    }
+
+    #[test]
+    fn rejects_bad_input() {
+        assert!(parse_rates("").is_err());
+        assert!(parse_rates("<Cube></Cube>").is_err());
+        assert!(parse_rates("USD,0.98\nJPY").is_err());
+        assert!(parse_rates("USD,-1").is_err());
+        assert!(parse_rates("<Cube currency='US1' rate='1.0'/>").is_err());
+    }
+}
@@ -1,32 +1,67 @@
-use crate::types::PostgresPool;
+use crate::{events::DomainEvent, models::outbox::Outbox, types::PostgresPool};
 use anyhow::Result;
 use chrono::NaiveDateTime;
 use serde::{Deserialize, Serialize};
-use sqlx::FromRow;
+use sqlx::{types::Json, FromRow, Postgres, Transaction};
+use tracing::instrument;
 
 #[derive(Serialize, Deserialize)]
 pub struct ImageInput {
     pub name: String,
     pub path: String,
-    pub productId: i32,
+    // taken from the path on /products/{id}/images
+    #[serde(rename = "productId", alias = "product_id", default)]
+    pub product_id: i32,
+    pub position: Option<i32>,
+    #[serde(default)]
+    pub is_primary: bool,
+    // only set for files uploaded through a BlobStore
+    #[serde(skip)]
+    pub storage_key: Option<String>,
+    #[serde(skip)]
+    pub content_type: Option<String>,
 }
 
-#[derive(Serialize, FromRow, Debug)]
+#[derive(Serialize, Deserialize, FromRow, Debug)]
 pub struct Image {
     pub id: i32,
     pub name: String,
     pub path: String,
-    pub productId: i32,
+    #[serde(rename = "productId", alias = "product_id")]
+    pub product_id: i32,
+    pub position: i32,
+    pub is_primary: bool,
+    #[serde(skip_serializing)]
+    pub storage_key: Option<String>,
+    pub content_type: Option<String>,
+    pub variants: Json<Vec<ImageVariant>>,
     pub updated_at: NaiveDateTime,
     pub created_at: NaiveDateTime,
 }
 
+/// A derivative of an uploaded image, ordered by width so the list can be
+/// turned into a `srcset` per format.
+#[derive(Serialize, Deserialize, FromRow, Debug)]
+pub struct ImageVariant {
+    pub name: String,
+    pub format: String,
+    pub width: i32,
+    pub height: i32,
+    pub path: String,
+    #[serde(skip_serializing)]
+    pub storage_key: String,
+    pub content_type: String,
+}
+
 impl Image {
+    #[instrument(name = "Image::find_all", skip_all, err)]
     pub async fn find_all(pool: &PostgresPool) -> Result<Vec<Image>> {
         let images = sqlx::query_as!(
             Image,
             r#"
-              SELECT id, name, path, productId, updated_at, created_at
+              SELECT id, name, path, product_id, position, is_primary, storage_key, content_type,
+                     COALESCE((SELECT json_agg(v ORDER BY v.width, v.format) FROM image_variants v WHERE v.image_id = images.id), '[]') AS "variants!: Json<Vec<ImageVariant>>",
+                     updated_at, created_at
                   FROM images
                ORDER BY updated_at
             "#
@@ -37,11 +72,35 @@ impl Image {
         Ok(images)
     }
 
+    #[instrument(name = "Image::find_by_product", skip_all, err)]
+    pub async fn find_by_product(product_id: i32, pool: &PostgresPool) -> Result<Vec<Image>> {
+        let images = sqlx::query_as!(
+            Image,
+            r#"
+              SELECT id, name, path, product_id, position, is_primary, storage_key, content_type,
+                     COALESCE((SELECT json_agg(v ORDER BY v.width, v.format) FROM image_variants v WHERE v.image_id = images.id), '[]') AS "variants!: Json<Vec<ImageVariant>>",
+                     updated_at, created_at
+                  FROM images
+               WHERE product_id = $1
+               ORDER BY position, id
+            "#,
+            product_id
+        )
+        .fetch_all(pool)
+        .await?;
+
+        Ok(images)
+    }
+
+    #[instrument(name = "Image::find_by_id", skip_all, err)]
     pub async fn find_by_id(id: i32, pool: &PostgresPool) -> Result<Image> {
         let image = sqlx::query_as!(
             Image,
             r#"
-              SELECT * FROM images WHERE id = $1
+              SELECT id, name, path, product_id, position, is_primary, storage_key, content_type,
+                     COALESCE((SELECT json_agg(v ORDER BY v.width, v.format) FROM image_variants v WHERE v.image_id = images.id), '[]') AS "variants!: Json<Vec<ImageVariant>>",
+                     updated_at, created_at
+                  FROM images WHERE id = $1
             "#,
             id
         )
@@ -51,52 +110,159 @@ impl Image {
         Ok(image)
     }
 
+    /// Appends an image to its product. The first image of a product, or one flagged
+    /// `is_primary`, becomes the primary image.
+    #[instrument(name = "Image::create", skip_all, err)]
     pub async fn create(input: ImageInput, pool: &PostgresPool) -> Result<Image> {
         let mut tx = pool.begin().await?;
+        let image = Image::insert(input, &mut tx).await?;
+        tx.commit().await?;
+
+        Ok(image)
+    }
+
+    /// Like `create`, as part of the caller's transaction.
+    pub async fn insert(input: ImageInput, tx: &mut Transaction<'_, Postgres>) -> Result<Image> {
+        if input.is_primary {
+            sqlx::query!(
+                "UPDATE images SET is_primary = FALSE WHERE product_id = $1 AND is_primary",
+                input.product_id
+            )
+            .execute(&mut *tx)
+            .await?;
+        }
+
         let image = sqlx::query_as!(
             Image,
             r#"
-              INSERT INTO images (name, path, productId) VALUES ($1, $2, $3)
-               RETURNING id, name, path, productId, updated_at, created_at
+              INSERT INTO images (name, path, product_id, position, is_primary, storage_key, content_type)
+                  SELECT $1, $2, $3,
+                         COALESCE($4, (SELECT COALESCE(MAX(position) + 1, 0) FROM images WHERE product_id = $3)),
+                         $5 OR NOT EXISTS (SELECT 1 FROM images WHERE product_id = $3),
+                         $6, $7
+               RETURNING id, name, path, product_id, position, is_primary, storage_key, content_type,
+                         '[]'::json AS "variants!: Json<Vec<ImageVariant>>", updated_at, created_at
             "#,
             input.name,
             input.path,
-            input.productId
+            input.product_id,
+            input.position,
+            input.is_primary,
+            input.storage_key,
+            input.content_type
         )
-        .fetch_one(&mut tx)
+        .fetch_one(&mut *tx)
         .await?;
-        tx.commit().await?;
+        product_changed(image.product_id, tx).await?;
 
         Ok(image)
     }
 
+    #[instrument(name = "Image::update", skip_all, err)]
     pub async fn update(id: i32, input: ImageInput, pool: &PostgresPool) -> Result<Image> {
         let mut tx = pool.begin().await.unwrap();
+        let previous_product_id =
+            sqlx::query_scalar!("SELECT product_id FROM images WHERE id = $1 FOR UPDATE", id)
+                .fetch_one(&mut tx)
+                .await?;
+        if input.is_primary {
+            sqlx::query!(
+                "UPDATE images SET is_primary = FALSE WHERE product_id = $1 AND is_primary AND id <> $2",
+                input.product_id,
+                id
+            )
+            .execute(&mut tx)
+            .await?;
+        }
+
         let image = sqlx::query_as!(
             Image,
             r#"
-              UPDATE images SET name = $1, path = $2, productId = $3 WHERE id = $4
-               RETURNING id, name, path, productId, updated_at, created_at
+              UPDATE images
+                 SET name = $1, path = $2, product_id = $3, position = COALESCE($4, position),
+                     is_primary = $5, updated_at = NOW()
+               WHERE id = $6
+               RETURNING id, name, path, product_id, position, is_primary, storage_key, content_type,
+                         COALESCE((SELECT json_agg(v ORDER BY v.width, v.format) FROM image_variants v WHERE v.image_id = images.id), '[]') AS "variants!: Json<Vec<ImageVariant>>",
+                         updated_at, created_at
             "#,
             input.name,
             input.path,
-            input.productId,
+            input.product_id,
+            input.position,
+            input.is_primary,
             id
         )
         .fetch_one(&mut tx)
         .await?;
+        product_changed(image.product_id, &mut tx).await?;
+        if previous_product_id != image.product_id {
+            product_changed(previous_product_id, &mut tx).await?;
+        }
         tx.commit().await.unwrap();
 
         Ok(image)
     }
 
+    #[instrument(name = "Image::delete", skip_all, err)]
     pub async fn delete(id: i32, pool: &PostgresPool) -> Result<u64> {
         let mut tx = pool.begin().await?;
-        let result = sqlx::query_as!(Image, "DELETE FROM images WHERE id = $1", id)
-            .execute(&mut tx)
-            .await?;
+        let product_ids =
+            sqlx::query_scalar!("DELETE FROM images WHERE id = $1 RETURNING product_id", id)
+                .fetch_all(&mut tx)
+                .await?;
+        for &product_id in &product_ids {
+            product_changed(product_id, &mut tx).await?;
+        }
 
         tx.commit().await?;
-        Ok(result.rows_affected())
+        Ok(product_ids.len() as u64)
     }
 }
+
+impl ImageVariant {
+    #[instrument(name = "ImageVariant::upsert", skip_all, err)]
+    pub async fn upsert(
+        image_id: i32,
+        variant: ImageVariant,
+        pool: &PostgresPool,
+    ) -> Result<ImageVariant> {
+        let mut tx = pool.begin().await?;
+        let variant = sqlx::query_as!(
+            ImageVariant,
+            r#"
+              INSERT INTO image_variants (image_id, name, format, width, height, path, storage_key, content_type)
+                  VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
+                  ON CONFLICT (image_id, name, format) DO UPDATE
+                     SET width = EXCLUDED.width, height = EXCLUDED.height, path = EXCLUDED.path,
+                         storage_key = EXCLUDED.storage_key, content_type = EXCLUDED.content_type,
+                         updated_at = NOW()
+               RETURNING name, format, width, height, path, storage_key, content_type
+            "#,
+            image_id,
+            variant.name,
+            variant.format,
+            variant.width,
+            variant.height,
+            variant.path,
+            variant.storage_key,
+            variant.content_type
+        )
+        .fetch_one(&mut tx)
+        .await?;
+        let product_id =
+            sqlx::query_scalar!("SELECT product_id FROM images WHERE id = $1", image_id)
+                .fetch_one(&mut tx)
+                .await?;
+        product_changed(product_id, &mut tx).await?;
+        tx.commit().await?;
+
+        Ok(variant)
+    }
+}
+
+/// The search document of a product carries its primary image, so image changes
+/// are product changes.
+async fn product_changed(product_id: i32, tx: &mut Transaction<'_, Postgres>) -> Result<()> {
+    Outbox::publish(&DomainEvent::ProductUpdated { product_id }, tx).await
+}
@@ -0,0 +1,261 @@
+use crate::types::PostgresPool;
+use anyhow::Result;
+use chrono::NaiveDateTime;
+use serde::{Deserialize, Serialize};
+use serde_json::Value as JsonValue;
+use sqlx::{FromRow, Postgres, Transaction};
+use tracing::instrument;
+
+/// A job waiting to be inserted, see `workers::jobs::Job` for the typed side.
+pub struct NewJob {
+    pub kind: &'static str,
+    pub payload: JsonValue,
+    pub unique_key: Option<String>,
+    pub max_attempts: i32,
+    /// defaults to now
+    pub run_at: Option<NaiveDateTime>,
+}
+
+#[derive(Serialize, FromRow, Debug)]
+pub struct JobRecord {
+    pub id: i64,
+    pub kind: String,
+    pub payload: JsonValue,
+    /// `pending`, `running`, `completed`, `dead` (out of attempts) or `cancelled`
+    pub status: String,
+    pub unique_key: Option<String>,
+    pub attempts: i32,
+    pub max_attempts: i32,
+    pub last_error: Option<String>,
+    pub run_at: NaiveDateTime,
+    pub locked_at: Option<NaiveDateTime>,
+    pub completed_at: Option<NaiveDateTime>,
+    pub updated_at: NaiveDateTime,
+    pub created_at: NaiveDateTime,
+}
+
+#[derive(Deserialize)]
+pub struct JobFilter {
+    pub status: Option<String>,
+    pub kind: Option<String>,
+    pub limit: Option<i64>,
+    pub offset: Option<i64>,
+}
+
+impl JobRecord {
+    /// Inserts `job` unless a pending or running job of the same kind has its
+    /// unique key. Returns the new job's id, `None` for such a duplicate.
+    #[instrument(name = "JobRecord::insert", skip_all, err)]
+    pub async fn insert(job: NewJob, tx: &mut Transaction<'_, Postgres>) -> Result<Option<i64>> {
+        let id = sqlx::query_scalar!(
+            r#"
+              INSERT INTO jobs (kind, payload, unique_key, max_attempts, run_at)
+                  VALUES ($1, $2, $3, $4, COALESCE($5, LOCALTIMESTAMP))
+              ON CONFLICT (kind, unique_key) WHERE unique_key IS NOT NULL AND status IN ('pending', 'running')
+                  DO NOTHING
+               RETURNING id
+            "#,
+            job.kind,
+            job.payload,
+            job.unique_key,
+            job.max_attempts,
+            job.run_at
+        )
+        .fetch_optional(&mut *tx)
+        .await?;
+
+        Ok(id)
+    }
+
+    /// Marks up to `limit` due jobs as running and returns them. Jobs locked by
+    /// another worker are skipped; running jobs whose worker hasn't renewed the
+    /// lease within `lease_secs` are assumed lost and claimed again, or
+    /// dead-lettered when the lost run was their last attempt.
+    #[instrument(name = "JobRecord::claim", skip_all, err)]
+    pub async fn claim(limit: i64, lease_secs: f64, pool: &PostgresPool) -> Result<Vec<JobRecord>> {
+        let mut tx = pool.begin().await?;
+        sqlx::query!(
+            r#"
+              UPDATE jobs SET status = 'dead', last_error = 'worker lost during the last attempt',
+                              locked_at = NULL, updated_at = NOW()
+               WHERE id IN (
+                  SELECT id FROM jobs
+                   WHERE status = 'running' AND locked_at < NOW() - make_interval(secs => $1)
+                     AND attempts >= max_attempts
+                  FOR UPDATE SKIP LOCKED
+               )
+            "#,
+            lease_secs
+        )
+        .execute(&mut tx)
+        .await?;
+
+        let jobs = sqlx::query_as!(
+            JobRecord,
+            r#"
+              UPDATE jobs SET status = 'running', attempts = attempts + 1, locked_at = NOW(), updated_at = NOW()
+               WHERE id IN (
+                  SELECT id FROM jobs
+                   WHERE ((status = 'pending' AND run_at <= NOW())
+                      OR (status = 'running' AND locked_at < NOW() - make_interval(secs => $2)))
+                     AND attempts < max_attempts
+                  ORDER BY run_at, id
+                  LIMIT $1
+                  FOR UPDATE SKIP LOCKED
+               )
+               RETURNING id, kind, payload, status, unique_key, attempts, max_attempts, last_error,
+                         run_at, locked_at, completed_at, updated_at, created_at
+            "#,
+            limit,
+            lease_secs
+        )
+        .fetch_all(&mut tx)
+        .await?;
+        tx.commit().await?;
+
+        Ok(jobs)
+    }
+
+    /// Renews the lease of a running job, so that it isn't claimed again while
+    /// its handler is still busy.
+    #[instrument(name = "JobRecord::heartbeat", skip_all, err)]
+    pub async fn heartbeat(id: i64, pool: &PostgresPool) -> Result<()> {
+        sqlx::query!(
+            "UPDATE jobs SET locked_at = NOW() WHERE id = $1 AND status = 'running'",
+            id
+        )
+        .execute(pool)
+        .await?;
+
+        Ok(())
+    }
+
+    #[instrument(name = "JobRecord::complete", skip_all, err)]
+    pub async fn complete(id: i64, pool: &PostgresPool) -> Result<()> {
+        let mut tx = pool.begin().await?;
+        sqlx::query!(
+            r#"
+              UPDATE jobs SET status = 'completed', locked_at = NULL, completed_at = NOW(), updated_at = NOW()
+               WHERE id = $1 AND status = 'running'
+            "#,
+            id
+        )
+        .execute(&mut tx)
+        .await?;
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    /// Records a failed attempt: the job runs again after `backoff_secs`, or is
+    /// dead-lettered once it used up its attempts. Returns the new status.
+    #[instrument(name = "JobRecord::fail", skip_all, err)]
+    pub async fn fail(
+        id: i64,
+        error: &str,
+        backoff_secs: f64,
+        pool: &PostgresPool,
+    ) -> Result<String> {
+        let mut tx = pool.begin().await?;
+        let status = sqlx::query_scalar!(
+            r#"
+              UPDATE jobs SET status = CASE WHEN attempts >= max_attempts THEN 'dead' ELSE 'pending' END,
+                              run_at = NOW() + make_interval(secs => $3),
+                              last_error = $2, locked_at = NULL, updated_at = NOW()
+               WHERE id = $1
+               RETURNING status
+            "#,
+            id,
+            error,
+            backoff_secs
+        )
+        .fetch_one(&mut tx)
+        .await?;
+        tx.commit().await?;
+
+        Ok(status)
+    }
+
+    /// Lists jobs, newest first.
+    #[instrument(name = "JobRecord::find_all", skip_all, err)]
+    pub async fn find_all(filter: &JobFilter, pool: &PostgresPool) -> Result<Vec<JobRecord>> {
+        let jobs = sqlx::query_as!(
+            JobRecord,
+            r#"
+              SELECT id, kind, payload, status, unique_key, attempts, max_attempts, last_error,
+                     run_at, locked_at, completed_at, updated_at, created_at
+                  FROM jobs
+               WHERE ($1::text IS NULL OR status = $1)
+                 AND ($2::text IS NULL OR kind = $2)
+              ORDER BY id DESC
+              LIMIT $3 OFFSET $4
+            "#,
+            filter.status,
+            filter.kind,
+            filter.limit.unwrap_or(50),
+            filter.offset.unwrap_or(0)
+        )
+        .fetch_all(pool)
+        .await?;
+
+        Ok(jobs)
+    }
+
+    #[instrument(name = "JobRecord::find_by_id", skip_all, err)]
+    pub async fn find_by_id(id: i64, pool: &PostgresPool) -> Result<JobRecord> {
+        let job = sqlx::query_as!(
+            JobRecord,
+            r#"
+              SELECT * FROM jobs WHERE id = $1
+            "#,
+            id
+        )
+        .fetch_one(pool)
+        .await?;
+
+        Ok(job)
+    }
+
+    /// Queues a dead, cancelled or waiting job to run now with a fresh set of
+    /// attempts. Returns `None` when the job is running or already completed.
+    #[instrument(name = "JobRecord::retry", skip_all, err)]
+    pub async fn retry(id: i64, pool: &PostgresPool) -> Result<Option<JobRecord>> {
+        let mut tx = pool.begin().await?;
+        let job = sqlx::query_as!(
+            JobRecord,
+            r#"
+              UPDATE jobs SET status = 'pending', attempts = 0, run_at = NOW(), updated_at = NOW()
+               WHERE id = $1 AND status IN ('pending', 'dead', 'cancelled')
+               RETURNING id, kind, payload, status, unique_key, attempts, max_attempts, last_error,
+                         run_at, locked_at, completed_at, updated_at, created_at
+            "#,
+            id
+        )
+        .fetch_optional(&mut tx)
+        .await?;
+        tx.commit().await?;
+
+        Ok(job)
+    }
+
+    /// Cancels a job that hasn't started yet. Returns `None` for any other status.
+    #[instrument(name = "JobRecord::cancel", skip_all, err)]
+    pub async fn cancel(id: i64, pool: &PostgresPool) -> Result<Option<JobRecord>> {
+        let mut tx = pool.begin().await?;
+        let job = sqlx::query_as!(
+            JobRecord,
+            r#"
+              UPDATE jobs SET status = 'cancelled', updated_at = NOW()
+               WHERE id = $1 AND status = 'pending'
+               RETURNING id, kind, payload, status, unique_key, attempts, max_attempts, last_error,
+                         run_at, locked_at, completed_at, updated_at, created_at
+            "#,
+            id
+        )
+        .fetch_optional(&mut tx)
+        .await?;
+        tx.commit().await?;
+
+        Ok(job)
+    }
+}
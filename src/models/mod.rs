@@ -2,4 +2,15 @@ pub mod users;
 pub mod products;
 pub mod orders;
 pub mod auth;
-pub mod search;
\ No newline at end of file
+pub mod search;
+pub mod currencies;
+pub mod categories;
+pub mod attributes;
+pub mod images;
+pub mod schema;
+pub mod search_analytics;
+pub mod search_settings;
+pub mod jobs;
+pub mod outbox;
+pub mod webhooks;
+pub mod shipments;
@@ -1,28 +1,57 @@
-use crate::types::PostgresPool;
-use anyhow::Result;
+use crate::{
+    events::DomainEvent,
+    models::currencies::{self, ExchangeRate, BASE_CURRENCY},
+    models::outbox::Outbox,
+    types::PostgresPool,
+};
+use anyhow::{anyhow, Result};
 use chrono::NaiveDateTime;
 use serde::{Deserialize, Serialize};
 use sqlx::FromRow;
+use tracing::instrument;
 
 #[derive(Serialize, Deserialize)]
 pub struct OrderInput {
     pub name: String,
+    pub currency: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct OrderStatusInput {
+    pub status: String,
 }
 
 #[derive(Serialize, FromRow, Debug)]
 pub struct Order {
     pub id: i32,
     pub name: String,
+    pub currency: String,
+    pub exchange_rate: f64,
+    /// `pending`, `paid`, `shipped`, `delivered` or `cancelled`
+    pub status: String,
     pub updated_at: NaiveDateTime,
     pub created_at: NaiveDateTime,
 }
 
+/// Whether an order may move from status `from` to `to`.
+fn can_transition(from: &str, to: &str) -> bool {
+    matches!(
+        (from, to),
+        ("pending", "paid")
+            | ("pending", "cancelled")
+            | ("paid", "shipped")
+            | ("paid", "cancelled")
+            | ("shipped", "delivered")
+    )
+}
+
 impl Order {
+    #[instrument(name = "Order::find_all", skip_all, err)]
     pub async fn find_all(pool: &PostgresPool) -> Result<Vec<Order>> {
         let order = sqlx::query_as!(
             Order,
             r#"
-              SELECT id, name, updated_at, created_at
+              SELECT id, name, currency, exchange_rate, status, updated_at, created_at
                   FROM orders
               ORDER BY updated_at
             "#
@@ -33,6 +62,7 @@ impl Order {
         Ok(order)
     }
 
+    #[instrument(name = "Order::find_by_id", skip_all, err)]
     pub async fn find_by_id(id: i32, pool: &PostgresPool) -> Result<Order> {
         let order = sqlx::query_as!(
             Order,
@@ -47,46 +77,100 @@ impl Order {
         Ok(order)
     }
 
+    /// Creates an order, snapshotting the exchange rate of its currency at creation time.
+    #[instrument(name = "Order::create", skip_all, err)]
     pub async fn create(input: OrderInput, pool: &PostgresPool) -> Result<Order> {
+        let currency = match input.currency {
+            Some(ref code) => currencies::normalize(code)?,
+            None => BASE_CURRENCY.to_string(),
+        };
+        let rate = ExchangeRate::find(&currency, pool).await?;
+
         let mut tx = pool.begin().await?;
         let order = sqlx::query_as!(
             Order,
             r#"
-              INSERT INTO orders (name) VALUES ($1)
-               RETURNING id, name, updated_at, created_at
+              INSERT INTO orders (name, currency, exchange_rate) VALUES ($1, $2, $3)
+               RETURNING id, name, currency, exchange_rate, status, updated_at, created_at
             "#,
-            input.name
+            input.name,
+            currency,
+            rate.rate
         )
         .fetch_one(&mut tx)
         .await?;
+        let event = DomainEvent::OrderCreated { order_id: order.id };
+        Outbox::publish(&event, &mut tx).await?;
         tx.commit().await?;
 
         Ok(order)
     }
 
+    #[instrument(name = "Order::update", skip_all, err)]
     pub async fn update(id: i32, input: OrderInput, pool: &PostgresPool) -> Result<Order> {
         let mut tx = pool.begin().await.unwrap();
         let order = sqlx::query_as!(
             Order,
             r#"
               UPDATE orders SET name = $1 WHERE id = $2
-               RETURNING id, name, updated_at, created_at
+               RETURNING id, name, currency, exchange_rate, status, updated_at, created_at
             "#,
             input.name,
             id
         )
         .fetch_one(&mut tx)
         .await?;
+        let event = DomainEvent::OrderUpdated { order_id: order.id };
+        Outbox::publish(&event, &mut tx).await?;
         tx.commit().await.unwrap();
 
         Ok(order)
     }
 
+    /// Moves the order to `status`, rejecting transitions the order's current
+    /// status doesn't allow.
+    #[instrument(name = "Order::update_status", skip_all, err)]
+    pub async fn update_status(id: i32, status: &str, pool: &PostgresPool) -> Result<Order> {
+        let mut tx = pool.begin().await?;
+        let current = sqlx::query_scalar!("SELECT status FROM orders WHERE id = $1 FOR UPDATE", id)
+            .fetch_one(&mut tx)
+            .await?;
+        if !can_transition(&current, status) {
+            return Err(anyhow!("an order can't go from {} to {}", current, status));
+        }
+
+        let order = sqlx::query_as!(
+            Order,
+            r#"
+              UPDATE orders SET status = $1, updated_at = NOW() WHERE id = $2
+               RETURNING id, name, currency, exchange_rate, status, updated_at, created_at
+            "#,
+            status,
+            id
+        )
+        .fetch_one(&mut tx)
+        .await?;
+        let event = DomainEvent::OrderStatusChanged {
+            order_id: order.id,
+            from: current,
+            to: order.status.clone(),
+        };
+        Outbox::publish(&event, &mut tx).await?;
+        tx.commit().await?;
+
+        Ok(order)
+    }
+
+    #[instrument(name = "Order::delete", skip_all, err)]
     pub async fn delete(id: i32, pool: &PostgresPool) -> Result<u64> {
         let mut tx = pool.begin().await?;
         let result = sqlx::query_as!(Order, "DELETE FROM orders WHERE id = $1", id)
             .execute(&mut tx)
             .await?;
+        if result.rows_affected() > 0 {
+            let event = DomainEvent::OrderDeleted { order_id: id };
+            Outbox::publish(&event, &mut tx).await?;
+        }
 
         tx.commit().await?;
         Ok(result.rows_affected())
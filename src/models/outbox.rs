@@ -0,0 +1,135 @@
+use crate::{events::DomainEvent, types::PostgresPool};
+use anyhow::Result;
+use chrono::NaiveDateTime;
+use serde_json::Value as JsonValue;
+use sqlx::{FromRow, Postgres, Transaction};
+use tracing::instrument;
+
+#[derive(Debug, FromRow)]
+pub struct OutboxEntry {
+    pub id: i64,
+    pub event_type: String,
+    pub payload: JsonValue,
+    pub attempts: i32,
+    pub created_at: NaiveDateTime,
+}
+
+/// Domain events waiting for the dispatcher.
+pub struct Outbox;
+
+impl Outbox {
+    /// Records `event` as part of the caller's transaction.
+    #[instrument(name = "Outbox::publish", skip_all, err)]
+    pub async fn publish(event: &DomainEvent, tx: &mut Transaction<'_, Postgres>) -> Result<()> {
+        let (aggregate_type, aggregate_id) = event.aggregate();
+        sqlx::query!(
+            r#"
+              INSERT INTO outbox (aggregate_type, aggregate_id, event_type, payload)
+                  VALUES ($1, $2, $3, $4)
+            "#,
+            aggregate_type,
+            aggregate_id,
+            event.event_type(),
+            serde_json::to_value(event)?
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Claims up to `limit` due events, only the oldest pending one of each
+    /// aggregate so that later ones wait for it. Claimed events count an attempt
+    /// and are hidden from other dispatchers for `lease_secs`; the claim is
+    /// committed before they are delivered. Events whose dispatcher was lost
+    /// during their last attempt are marked dead instead.
+    #[instrument(name = "Outbox::claim", skip_all, err)]
+    pub async fn claim(
+        limit: i64,
+        lease_secs: f64,
+        max_attempts: i32,
+        pool: &PostgresPool,
+    ) -> Result<Vec<OutboxEntry>> {
+        let mut tx = pool.begin().await?;
+        sqlx::query!(
+            r#"
+              UPDATE outbox SET status = 'dead', last_error = 'dispatcher lost during the last attempt'
+               WHERE status = 'pending' AND available_at <= NOW() AND attempts >= $1
+            "#,
+            max_attempts
+        )
+        .execute(&mut tx)
+        .await?;
+
+        let mut entries = sqlx::query_as!(
+            OutboxEntry,
+            r#"
+              UPDATE outbox
+                 SET attempts = attempts + 1,
+                     available_at = NOW() + make_interval(secs => $2)
+               WHERE id IN (
+                  SELECT o.id FROM outbox o
+                   WHERE o.status = 'pending'
+                     AND o.available_at <= NOW()
+                     AND NOT EXISTS (
+                        SELECT 1 FROM outbox e
+                         WHERE e.aggregate_type = o.aggregate_type
+                           AND e.aggregate_id = o.aggregate_id
+                           AND e.status = 'pending'
+                           AND e.id < o.id
+                     )
+                  ORDER BY o.id
+                  LIMIT $1
+                  FOR UPDATE SKIP LOCKED
+               )
+               RETURNING id, event_type, payload, attempts, created_at
+            "#,
+            limit,
+            lease_secs
+        )
+        .fetch_all(&mut tx)
+        .await?;
+        tx.commit().await?;
+
+        entries.sort_by_key(|entry| entry.id);
+        Ok(entries)
+    }
+
+    #[instrument(name = "Outbox::complete", skip_all, err)]
+    pub async fn complete(id: i64, pool: &PostgresPool) -> Result<()> {
+        sqlx::query!("DELETE FROM outbox WHERE id = $1", id)
+            .execute(pool)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Records a failure and backs the event off exponentially, up to five
+    /// minutes, or marks it dead once it used `max_attempts`, which lets later
+    /// events of its aggregate through. Returns the new status.
+    #[instrument(name = "Outbox::retry", skip_all, err)]
+    pub async fn retry(
+        id: i64,
+        error: &str,
+        max_attempts: i32,
+        pool: &PostgresPool,
+    ) -> Result<String> {
+        let status = sqlx::query_scalar!(
+            r#"
+              UPDATE outbox
+                 SET status = CASE WHEN attempts >= $3 THEN 'dead' ELSE 'pending' END,
+                     last_error = $2,
+                     available_at = NOW() + LEAST(POWER(2, attempts), 300) * INTERVAL '1 second'
+               WHERE id = $1
+               RETURNING status
+            "#,
+            id,
+            error,
+            max_attempts
+        )
+        .fetch_one(pool)
+        .await?;
+
+        Ok(status)
+    }
+}
@@ -1,8 +1,17 @@
-use crate::types::PostgresPool;
-use anyhow::Result;
+use crate::{
+    events::DomainEvent,
+    models::attributes::{self, AttributeDefinition},
+    models::currencies::{self, BASE_CURRENCY},
+    models::images::Image,
+    models::outbox::Outbox,
+    types::PostgresPool,
+};
+use anyhow::{anyhow, Result};
 use chrono::NaiveDateTime;
 use serde::{Deserialize, Serialize};
-use sqlx::FromRow;
+use serde_json::Value as JsonValue;
+use sqlx::{types::Json, FromRow};
+use tracing::instrument;
 
 #[derive(Serialize, Deserialize)]
 pub struct ProductInput {
@@ -10,7 +19,12 @@ pub struct ProductInput {
     pub price: i64,
     pub origin: String,
     pub cultivar: String,
-    pub images: String,
+    pub currency: Option<String>,
+    pub attributes: Option<JsonValue>,
+    /// categories to link a new product to; ignored on update, where
+    /// `PUT /products/{id}/categories` replaces them
+    #[serde(default)]
+    pub category_ids: Option<Vec<i32>>,
 }
 
 #[derive(Serialize, FromRow, Debug)]
@@ -20,20 +34,29 @@ pub struct Product {
     pub price: i64,
     pub origin: String,
     pub cultivar: String,
-    pub images: String,
+    pub images: Json<Vec<Image>>,
+    pub currency: String,
+    pub attributes: JsonValue,
     pub updated_at: NaiveDateTime,
     pub created_at: NaiveDateTime,
 }
 
 impl Product {
-    pub async fn find_all(pool: &PostgresPool) -> Result<Vec<Product>> {
+    /// Lists products, optionally restricted to those whose attributes contain one of
+    /// the objects in the `filter` array, see `AttributeDefinition::filter_from_query`.
+    #[instrument(name = "Product::find_all", skip_all, err)]
+    pub async fn find_all(filter: Option<&JsonValue>, pool: &PostgresPool) -> Result<Vec<Product>> {
         let product = sqlx::query_as!(
             Product,
             r#"
-              SELECT id, name, price, origin, cultivar, images, updated_at, created_at
-                  FROM products
-              ORDER BY updated_at
-            "#
+              SELECT p.id, p.name, p.price, p.origin, p.cultivar,
+                     COALESCE((SELECT json_agg(i ORDER BY i.position, i.id) FROM image_listing i WHERE i.product_id = p.id), '[]') AS "images!: Json<Vec<Image>>",
+                     p.currency, p.attributes, p.updated_at, p.created_at
+                  FROM products p
+               WHERE ($1::jsonb IS NULL OR EXISTS (SELECT 1 FROM jsonb_array_elements($1) f WHERE p.attributes @> f))
+              ORDER BY p.updated_at
+            "#,
+            filter
         )
         .fetch_all(pool)
         .await?;
@@ -41,11 +64,16 @@ impl Product {
         Ok(product)
     }
 
+    #[instrument(name = "Product::find_by_id", skip_all, err)]
     pub async fn find_by_id(id: i32, pool: &PostgresPool) -> Result<Product> {
         let product = sqlx::query_as!(
             Product,
             r#"
-              SELECT * FROM products WHERE id = $1
+              SELECT p.id, p.name, p.price, p.origin, p.cultivar,
+                     COALESCE((SELECT json_agg(i ORDER BY i.position, i.id) FROM image_listing i WHERE i.product_id = p.id), '[]') AS "images!: Json<Vec<Image>>",
+                     p.currency, p.attributes, p.updated_at, p.created_at
+                  FROM products p
+               WHERE p.id = $1
             "#,
             id
         )
@@ -55,54 +83,220 @@ impl Product {
         Ok(product)
     }
 
+    /// Lists products linked to the category `slug` or any of its descendants.
+    #[instrument(name = "Product::find_by_category", skip_all, err)]
+    pub async fn find_by_category(slug: &str, pool: &PostgresPool) -> Result<Vec<Product>> {
+        let product = sqlx::query_as!(
+            Product,
+            r#"
+              WITH RECURSIVE tree AS (
+                  SELECT id FROM categories WHERE slug = $1
+                UNION ALL
+                  SELECT c.id FROM categories c JOIN tree t ON c.parent_id = t.id
+              )
+              SELECT p.id, p.name, p.price, p.origin, p.cultivar,
+                     COALESCE((SELECT json_agg(i ORDER BY i.position, i.id) FROM image_listing i WHERE i.product_id = p.id), '[]') AS "images!: Json<Vec<Image>>",
+                     p.currency, p.attributes, p.updated_at, p.created_at
+                  FROM products p
+               WHERE EXISTS (
+                  SELECT 1 FROM product_categories pc
+                      JOIN tree t ON t.id = pc.category_id
+                   WHERE pc.product_id = p.id
+               )
+              ORDER BY p.updated_at
+            "#,
+            slug
+        )
+        .fetch_all(pool)
+        .await?;
+
+        Ok(product)
+    }
+
+    /// Lists products priced in `currency`, preferring an explicit price list entry and
+    /// otherwise converting through `exchange_rates`. Products that can't be priced are skipped.
+    #[instrument(name = "Product::find_all_in", skip_all, err)]
+    pub async fn find_all_in(
+        currency: &str,
+        filter: Option<&JsonValue>,
+        pool: &PostgresPool,
+    ) -> Result<Vec<Product>> {
+        let product = sqlx::query_as!(
+            Product,
+            r#"
+              SELECT p.id, p.name,
+                     CASE WHEN pp.amount IS NOT NULL THEN pp.amount
+                          WHEN p.currency = $1 THEN p.price
+                          ELSE ROUND(p.price * tr.rate / br.rate)::bigint
+                     END AS "price!",
+                     p.origin, p.cultivar,
+                     COALESCE((SELECT json_agg(i ORDER BY i.position, i.id) FROM image_listing i WHERE i.product_id = p.id), '[]') AS "images!: Json<Vec<Image>>",
+                     $1::text AS "currency!", p.attributes, p.updated_at, p.created_at
+                  FROM products p
+                  LEFT JOIN product_prices pp ON pp.product_id = p.id AND pp.currency = $1
+                  LEFT JOIN exchange_rates br ON br.currency = p.currency
+                  LEFT JOIN exchange_rates tr ON tr.currency = $1
+               WHERE (pp.amount IS NOT NULL OR p.currency = $1 OR (br.rate IS NOT NULL AND tr.rate IS NOT NULL))
+                 AND ($2::jsonb IS NULL OR EXISTS (SELECT 1 FROM jsonb_array_elements($2) f WHERE p.attributes @> f))
+              ORDER BY p.updated_at
+            "#,
+            currency,
+            filter
+        )
+        .fetch_all(pool)
+        .await?;
+
+        Ok(product)
+    }
+
+    /// The product priced in `currency`. Fails with `sqlx::Error::RowNotFound` when
+    /// there is no such product, and with another error when it has no price in
+    /// `currency` and no exchange rate to convert its own.
+    #[instrument(name = "Product::find_by_id_in", skip_all, err)]
+    pub async fn find_by_id_in(id: i32, currency: &str, pool: &PostgresPool) -> Result<Product> {
+        let product = sqlx::query_as!(
+            Product,
+            r#"
+              SELECT p.id, p.name,
+                     CASE WHEN pp.amount IS NOT NULL THEN pp.amount
+                          WHEN p.currency = $2 THEN p.price
+                          ELSE ROUND(p.price * tr.rate / br.rate)::bigint
+                     END AS "price!",
+                     p.origin, p.cultivar,
+                     COALESCE((SELECT json_agg(i ORDER BY i.position, i.id) FROM image_listing i WHERE i.product_id = p.id), '[]') AS "images!: Json<Vec<Image>>",
+                     $2::text AS "currency!", p.attributes, p.updated_at, p.created_at
+                  FROM products p
+                  LEFT JOIN product_prices pp ON pp.product_id = p.id AND pp.currency = $2
+                  LEFT JOIN exchange_rates br ON br.currency = p.currency
+                  LEFT JOIN exchange_rates tr ON tr.currency = $2
+               WHERE p.id = $1
+                 AND (pp.amount IS NOT NULL OR p.currency = $2 OR (br.rate IS NOT NULL AND tr.rate IS NOT NULL))
+            "#,
+            id,
+            currency
+        )
+        .fetch_optional(&*pool)
+        .await?;
+
+        match product {
+            Some(product) => Ok(product),
+            None => {
+                let exists = sqlx::query_scalar!(
+                    r#"SELECT EXISTS (SELECT 1 FROM products WHERE id = $1) AS "exists!""#,
+                    id
+                )
+                .fetch_one(pool)
+                .await?;
+                if exists {
+                    Err(anyhow!("product {} has no price in {}", id, currency))
+                } else {
+                    Err(sqlx::Error::RowNotFound.into())
+                }
+            }
+        }
+    }
+
+    #[instrument(name = "Product::create", skip_all, err)]
     pub async fn create(input: ProductInput, pool: &PostgresPool) -> Result<Product> {
+        let currency = match input.currency {
+            Some(ref code) => currencies::normalize(code)?,
+            None => BASE_CURRENCY.to_string(),
+        };
+        let attributes = input
+            .attributes
+            .unwrap_or_else(|| JsonValue::Object(Default::default()));
+
         let mut tx = pool.begin().await?;
         let product = sqlx::query_as!(
             Product,
             r#"
-              INSERT INTO products (name, price, origin, cultivar, images) VALUES ($1, $2, $3, $4, $5)
-               RETURNING id, name, price, origin, cultivar, images, updated_at, created_at
+              INSERT INTO products (name, price, origin, cultivar, currency, attributes) VALUES ($1, $2, $3, $4, $5, $6)
+               RETURNING id, name, price, origin, cultivar, '[]'::json AS "images!: Json<Vec<Image>>",
+                         currency, attributes, updated_at, created_at
             "#,
             input.name,
             input.price,
             input.origin,
             input.cultivar,
-            input.images,
+            currency,
+            attributes,
         )
         .fetch_one(&mut tx)
         .await?;
+        if let Some(category_ids) = input.category_ids {
+            sqlx::query!(
+                r#"
+                  INSERT INTO product_categories (product_id, category_id)
+                      SELECT $1, UNNEST($2::int[])
+                  ON CONFLICT DO NOTHING
+                "#,
+                product.id,
+                &category_ids
+            )
+            .execute(&mut tx)
+            .await?;
+        }
+        let definitions = AttributeDefinition::find_for_product(product.id, &mut tx).await?;
+        attributes::validate(&product.attributes, &definitions)?;
+        let event = DomainEvent::ProductCreated {
+            product_id: product.id,
+        };
+        Outbox::publish(&event, &mut tx).await?;
         tx.commit().await?;
 
         Ok(product)
     }
 
+    #[instrument(name = "Product::update", skip_all, err)]
     pub async fn update(id: i32, input: ProductInput, pool: &PostgresPool) -> Result<Product> {
+        let currency = input
+            .currency
+            .as_deref()
+            .map(currencies::normalize)
+            .transpose()?;
+
         let mut tx = pool.begin().await.unwrap();
         let product = sqlx::query_as!(
             Product,
             r#"
-              UPDATE products SET name = $1, price = $2, origin = $3, cultivar = $4, images = $5 WHERE id = $6
-               RETURNING id, name, price, origin, cultivar, images, updated_at, created_at
+              UPDATE products SET name = $1, price = $2, origin = $3, cultivar = $4, currency = COALESCE($5, currency), attributes = COALESCE($6, attributes) WHERE id = $7
+               RETURNING id, name, price, origin, cultivar,
+                         COALESCE((SELECT json_agg(i ORDER BY i.position, i.id) FROM image_listing i WHERE i.product_id = products.id), '[]') AS "images!: Json<Vec<Image>>",
+                         currency, attributes, updated_at, created_at
             "#,
             input.name,
             input.price,
             input.origin,
             input.cultivar,
-            input.images,
+            currency,
+            input.attributes,
             id
         )
         .fetch_one(&mut tx)
         .await?;
+        if input.attributes.is_some() {
+            let definitions = AttributeDefinition::find_for_product(id, &mut tx).await?;
+            attributes::validate(&product.attributes, &definitions)?;
+        }
+        let event = DomainEvent::ProductUpdated {
+            product_id: product.id,
+        };
+        Outbox::publish(&event, &mut tx).await?;
         tx.commit().await.unwrap();
 
         Ok(product)
     }
 
+    #[instrument(name = "Product::delete", skip_all, err)]
     pub async fn delete(id: i32, pool: &PostgresPool) -> Result<u64> {
         let mut tx = pool.begin().await?;
         let result = sqlx::query_as!(Product, "DELETE FROM products WHERE id = $1", id)
             .execute(&mut tx)
             .await?;
+        if result.rows_affected() > 0 {
+            let event = DomainEvent::ProductDeleted { product_id: id };
+            Outbox::publish(&event, &mut tx).await?;
+        }
 
         tx.commit().await?;
         Ok(result.rows_affected())
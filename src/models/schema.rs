@@ -0,0 +1,78 @@
+use crate::types::PostgresPool;
+use anyhow::{anyhow, Result};
+use sqlx::{
+    migrate::{Migration, Migrator},
+    Executor,
+};
+use tracing::instrument;
+
+/// The migrations in `./migrations`, embedded at compile time so a deploy is just
+/// the binary.
+pub static MIGRATOR: Migrator = sqlx::migrate!();
+
+/// The embedded migrations, without their down scripts, oldest first.
+pub fn migrations() -> impl Iterator<Item = &'static Migration> {
+    MIGRATOR
+        .iter()
+        .filter(|migration| !migration.migration_type.is_down_migration())
+}
+
+/// Versions the database has successfully applied, oldest first.
+#[instrument(name = "schema::applied_migrations", skip_all, err)]
+pub async fn applied_migrations(pool: &PostgresPool) -> Result<Vec<i64>> {
+    if !table_exists("_sqlx_migrations", pool).await? {
+        return Ok(Vec::new());
+    }
+    let applied =
+        sqlx::query_scalar("SELECT version FROM _sqlx_migrations WHERE success ORDER BY version")
+            .fetch_all(pool)
+            .await?;
+
+    Ok(applied)
+}
+
+/// Versions of embedded migrations the database hasn't applied yet.
+#[instrument(name = "schema::pending_migrations", skip_all, err)]
+pub async fn pending_migrations(pool: &PostgresPool) -> Result<Vec<i64>> {
+    let applied = applied_migrations(pool).await?;
+
+    Ok(migrations()
+        .map(|migration| migration.version)
+        .filter(|version| !applied.contains(version))
+        .collect())
+}
+
+/// Runs the down scripts of every applied migration newer than `target`, newest
+/// first, and forgets them, all in one transaction. Returns how many were reverted.
+#[instrument(name = "schema::revert", skip_all, err)]
+pub async fn revert(target: i64, pool: &PostgresPool) -> Result<usize> {
+    let applied = applied_migrations(pool).await?;
+    let mut tx = pool.begin().await?;
+    let mut reverted = 0;
+    for version in applied.into_iter().rev().filter(|version| *version > target) {
+        let down = MIGRATOR
+            .iter()
+            .find(|migration| {
+                migration.version == version && migration.migration_type.is_down_migration()
+            })
+            .ok_or_else(|| anyhow!("migration {} has no down script", version))?;
+        tx.execute(&*down.sql).await?;
+        sqlx::query("DELETE FROM _sqlx_migrations WHERE version = $1")
+            .bind(version)
+            .execute(&mut tx)
+            .await?;
+        reverted += 1;
+    }
+    tx.commit().await?;
+
+    Ok(reverted)
+}
+
+async fn table_exists(name: &str, pool: &PostgresPool) -> Result<bool> {
+    let exists: bool = sqlx::query_scalar("SELECT to_regclass($1) IS NOT NULL")
+        .bind(name)
+        .fetch_one(pool)
+        .await?;
+
+    Ok(exists)
+}
@@ -1,35 +1,373 @@
-
-use serde::{Serialize, Deserialize};
-use meilisearch_sdk::{document::*, client::*, search::SearchResult};
+use crate::{
+    search::{query::Sort, SearchHit, SearchRequest},
+    types::PostgresPool,
+};
 use anyhow::Result;
+use meilisearch_sdk::{document::*, settings::Settings};
+use serde::{Deserialize, Serialize};
+use serde_json::Value as JsonValue;
+use sqlx::FromRow;
+use std::collections::HashMap;
+use tracing::instrument;
 
-#[derive(Debug, Serialize, Deserialize)]
-pub struct Licenceholder {
-    id: f64,
-    holder: String,
-    website: String,
+/// What the search engine stores for a product. `category_slugs` includes the
+/// ancestors of every assigned category so filtering by a parent finds its children.
+#[derive(Clone, Debug, Serialize, Deserialize, FromRow)]
+pub struct ProductDocument {
+    pub id: i32,
+    pub name: String,
+    pub price: i64,
+    pub currency: String,
+    pub origin: String,
+    pub cultivar: String,
+    pub categories: Vec<String>,
+    pub category_slugs: Vec<String>,
+    pub attributes: JsonValue,
+    pub image: Option<String>,
+    pub updated_at: i64,
+    pub created_at: i64,
 }
 
 // That trait is required to make a struct usable by an index
-impl Document for Licenceholder {
-    type UIDType = f64;
+impl Document for ProductDocument {
+    type UIDType = i32;
 
     fn get_uid(&self) -> &Self::UIDType {
         &self.id
     }
 }
 
-pub async fn meili_search(query: &str) -> Result<Vec<Licenceholder>> {
-    let mut fresh_formatted_results = Vec::new();
-    let client = Client::new("http://10.13.100.16:7700", "secret");
-    let result = client.index("candata")
-        .search()
-        .with_query(query)
-        .execute::<Licenceholder>()
+impl ProductDocument {
+    #[instrument(name = "ProductDocument::find_by_id", skip_all, err)]
+    pub async fn find_by_id(id: i32, pool: &PostgresPool) -> Result<Option<ProductDocument>> {
+        let document = sqlx::query_as!(
+            ProductDocument,
+            r#"
+              SELECT id AS "id!", name AS "name!", price AS "price!", currency AS "currency!",
+                     origin AS "origin!", cultivar AS "cultivar!", categories AS "categories!",
+                     category_slugs AS "category_slugs!", attributes AS "attributes!", image,
+                     updated_at AS "updated_at!", created_at AS "created_at!"
+                  FROM product_documents
+               WHERE id = $1
+            "#,
+            id
+        )
+        .fetch_optional(pool)
+        .await?;
+
+        Ok(document)
+    }
+
+    #[instrument(name = "ProductDocument::find_ids", skip_all, err)]
+    pub async fn find_ids(pool: &PostgresPool) -> Result<Vec<i32>> {
+        let ids = sqlx::query_scalar!("SELECT id FROM products ORDER BY id")
+            .fetch_all(pool)
+            .await?;
+
+        Ok(ids)
+    }
+
+    /// Full-text search over name, cultivar and origin for the postgres backend. `text`
+    /// is the query as passed to `websearch_to_tsquery`, after synonym expansion. Names
+    /// that are merely similar to the query (`pg_trgm`) match too, so small typos still
+    /// find products. Filters on the same field must all match, like in Meilisearch.
+    #[instrument(name = "ProductDocument::search", skip_all, err)]
+    pub async fn search(
+        request: &SearchRequest,
+        text: &str,
+        pool: &PostgresPool,
+    ) -> Result<Vec<SearchHit>> {
+        let filters = PostgresFilters::from(request);
+        let sort = request.sort.map(Sort::to_param);
+        let rows = sqlx::query!(
+            r#"
+              SELECT d.id AS "id!", d.name AS "name!", d.price AS "price!", d.currency AS "currency!",
+                     d.origin AS "origin!", d.cultivar AS "cultivar!", d.categories AS "categories!",
+                     d.category_slugs AS "category_slugs!", d.attributes AS "attributes!", d.image,
+                     d.updated_at AS "updated_at!", d.created_at AS "created_at!",
+                     CASE WHEN $9 THEN ts_headline('simple', d.name, q, 'StartSel=<em>, StopSel=</em>, HighlightAll=true') END AS name_highlight,
+                     CASE WHEN $9 THEN ts_headline('simple', d.cultivar, q, 'StartSel=<em>, StopSel=</em>, HighlightAll=true') END AS cultivar_highlight,
+                     CASE WHEN $9 THEN ts_headline('simple', d.origin, q, 'StartSel=<em>, StopSel=</em>, HighlightAll=true') END AS origin_highlight
+                  FROM product_documents d
+                  JOIN products p ON p.id = d.id,
+                       websearch_to_tsquery('simple', $10) q
+               WHERE ($10 = '' OR p.search_vector @@ q OR $1 <% p.name)
+                 AND d.category_slugs @> $2::text[]
+                 AND (cardinality($3::text[]) = 0 OR d.origin = ALL($3))
+                 AND (cardinality($4::text[]) = 0 OR d.cultivar = ALL($4))
+                 AND (cardinality($5::text[]) = 0 OR d.currency = ALL($5))
+                 AND NOT (d.id = ANY($11::int[]))
+                 AND ($12::int[] IS NULL OR d.id = ANY($12))
+               ORDER BY CASE WHEN $6 = 'price:asc' THEN d.price END ASC,
+                        CASE WHEN $6 = 'price:desc' THEN d.price END DESC,
+                        CASE WHEN $6 = 'name:asc' THEN d.name END ASC,
+                        CASE WHEN $6 = 'name:desc' THEN d.name END DESC,
+                        CASE WHEN $6 = 'updated_at:asc' THEN d.updated_at END ASC,
+                        CASE WHEN $6 = 'updated_at:desc' THEN d.updated_at END DESC,
+                        CASE WHEN $6 = 'created_at:asc' THEN d.created_at END ASC,
+                        CASE WHEN $6 = 'created_at:desc' THEN d.created_at END DESC,
+                        ts_rank(p.search_vector, q) DESC, word_similarity($1, p.name) DESC, d.id
+               LIMIT $7 OFFSET $8
+            "#,
+            request.query,
+            &filters.categories,
+            &filters.origins,
+            &filters.cultivars,
+            &filters.currencies,
+            sort,
+            request.limit as i64,
+            request.offset as i64,
+            request.highlight,
+            text,
+            &request.excluded,
+            request.only.as_deref()
+        )
+        .fetch_all(pool)
+        .await?;
+
+        let hits = rows
+            .into_iter()
+            .map(|row| {
+                let highlight = request.highlight.then(|| {
+                    [
+                        ("name", row.name_highlight),
+                        ("cultivar", row.cultivar_highlight),
+                        ("origin", row.origin_highlight),
+                    ]
+                    .into_iter()
+                    .filter_map(|(field, value)| Some((field.to_string(), value?)))
+                    .collect()
+                });
+                SearchHit {
+                    document: ProductDocument {
+                        id: row.id,
+                        name: row.name,
+                        price: row.price,
+                        currency: row.currency,
+                        origin: row.origin,
+                        cultivar: row.cultivar,
+                        categories: row.categories,
+                        category_slugs: row.category_slugs,
+                        attributes: row.attributes,
+                        image: row.image,
+                        updated_at: row.updated_at,
+                        created_at: row.created_at,
+                    },
+                    highlight,
+                }
+            })
+            .collect();
+
+        Ok(hits)
+    }
+
+    /// Counts the products matching `request`, ignoring paging, and the values of
+    /// every facet field among them. The total is returned under the empty field name.
+    #[instrument(name = "ProductDocument::facet_counts", skip_all, err)]
+    pub async fn facet_counts(
+        request: &SearchRequest,
+        text: &str,
+        pool: &PostgresPool,
+    ) -> Result<HashMap<String, HashMap<String, u64>>> {
+        let filters = PostgresFilters::from(request);
+        let rows = sqlx::query!(
+            r#"
+              WITH matches AS (
+                  SELECT d.* FROM product_documents d
+                      JOIN products p ON p.id = d.id,
+                           websearch_to_tsquery('simple', $6) q
+                   WHERE ($6 = '' OR p.search_vector @@ q OR $1 <% p.name)
+                     AND d.category_slugs @> $2::text[]
+                     AND (cardinality($3::text[]) = 0 OR d.origin = ALL($3))
+                     AND (cardinality($4::text[]) = 0 OR d.cultivar = ALL($4))
+                     AND (cardinality($5::text[]) = 0 OR d.currency = ALL($5))
+                     AND NOT (d.id = ANY($7::int[]))
+                     AND ($8::int[] IS NULL OR d.id = ANY($8))
+              )
+              SELECT 'origin' AS "field!", origin AS "value!", COUNT(*) AS "count!" FROM matches GROUP BY origin
+              UNION ALL
+              SELECT 'cultivar', cultivar, COUNT(*) FROM matches GROUP BY cultivar
+              UNION ALL
+              SELECT 'currency', currency, COUNT(*) FROM matches GROUP BY currency
+              UNION ALL
+              SELECT 'category', slug, COUNT(*) FROM matches, UNNEST(category_slugs) slug GROUP BY slug
+              UNION ALL
+              SELECT '', '', COUNT(*) FROM matches
+            "#,
+            request.query,
+            &filters.categories,
+            &filters.origins,
+            &filters.cultivars,
+            &filters.currencies,
+            text,
+            &request.excluded,
+            request.only.as_deref()
+        )
+        .fetch_all(pool)
+        .await?;
+
+        let mut facets: HashMap<String, HashMap<String, u64>> = HashMap::new();
+        for row in rows {
+            facets
+                .entry(row.field)
+                .or_default()
+                .insert(row.value, row.count as u64);
+        }
+
+        Ok(facets)
+    }
+}
+
+/// Filter values per field, bound as arrays so empty ones disable the condition.
+struct PostgresFilters {
+    categories: Vec<String>,
+    origins: Vec<String>,
+    cultivars: Vec<String>,
+    currencies: Vec<String>,
+}
+
+impl From<&SearchRequest> for PostgresFilters {
+    fn from(request: &SearchRequest) -> PostgresFilters {
+        let values = |field: &str| {
+            request
+                .filters
+                .iter()
+                .filter(|filter| filter.field == field)
+                .map(|filter| filter.value.clone())
+                .collect()
+        };
+        PostgresFilters {
+            categories: values("category"),
+            origins: values("origin"),
+            cultivars: values("cultivar"),
+            currencies: values("currency"),
+        }
+    }
+}
+
+#[derive(Debug, FromRow)]
+struct SuggestionRow {
+    kind: String,
+    id: Option<i32>,
+    slug: Option<String>,
+    text: String,
+    popularity: i64,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct Suggestion {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id: Option<i32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub slug: Option<String>,
+    pub text: String,
+    pub popularity: i64,
+}
+
+/// Completions for a search box, each list ranked by popularity: products by how
+/// often their name was searched, categories by product count, queries by use.
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct Suggestions {
+    pub products: Vec<Suggestion>,
+    pub categories: Vec<Suggestion>,
+    pub queries: Vec<Suggestion>,
+}
+
+impl Suggestions {
+    #[instrument(name = "Suggestions::find", skip_all, err)]
+    pub async fn find(prefix: &str, limit: i64, pool: &PostgresPool) -> Result<Suggestions> {
+        let pattern = prefix
+            .replace('\\', "\\\\")
+            .replace('%', "\\%")
+            .replace('_', "\\_");
+        let rows = sqlx::query_as!(
+            SuggestionRow,
+            r#"
+              (SELECT 'product' AS "kind!", p.id AS "id?", NULL::text AS "slug?", p.name AS "text!",
+                      COALESCE(sq.count, 0) AS "popularity!"
+                  FROM products p
+                  LEFT JOIN search_queries sq ON sq.query = LOWER(p.name)
+               WHERE p.name ILIKE $1 || '%' OR p.name ILIKE '% ' || $1 || '%'
+               ORDER BY 5 DESC, p.name
+               LIMIT $2)
+              UNION ALL
+              (SELECT 'category', c.id, c.slug, c.name, COUNT(pc.product_id)
+                  FROM categories c
+                  LEFT JOIN product_categories pc ON pc.category_id = c.id
+               WHERE c.name ILIKE $1 || '%'
+               GROUP BY c.id
+               ORDER BY 5 DESC, c.name
+               LIMIT $2)
+              UNION ALL
+              (SELECT 'query', NULL, NULL, sq.query, sq.count
+                  FROM search_queries sq
+               WHERE sq.query LIKE LOWER($1) || '%'
+               ORDER BY sq.count DESC, sq.query
+               LIMIT $2)
+            "#,
+            pattern,
+            limit
+        )
+        .fetch_all(pool)
+        .await?;
+
+        let mut suggestions = Suggestions::default();
+        for row in rows {
+            let list = match row.kind.as_str() {
+                "product" => &mut suggestions.products,
+                "category" => &mut suggestions.categories,
+                _ => &mut suggestions.queries,
+            };
+            list.push(Suggestion {
+                id: row.id,
+                slug: row.slug,
+                text: row.text,
+                popularity: row.popularity,
+            });
+        }
+
+        Ok(suggestions)
+    }
+}
+
+/// Counts of the searches users ran, see `workers::query_log`.
+pub struct SearchQueries;
+
+impl SearchQueries {
+    /// Adds `counts` to the stored totals in a single statement.
+    #[instrument(name = "SearchQueries::record", skip_all, err)]
+    pub async fn record(counts: Vec<(String, i64)>, pool: &PostgresPool) -> Result<()> {
+        let (queries, counts): (Vec<String>, Vec<i64>) = counts.into_iter().unzip();
+        let mut tx = pool.begin().await?;
+        sqlx::query!(
+            r#"
+              INSERT INTO search_queries (query, count)
+                  SELECT * FROM UNNEST($1::text[], $2::bigint[])
+              ON CONFLICT (query) DO UPDATE
+                 SET count = search_queries.count + EXCLUDED.count, last_searched_at = NOW()
+            "#,
+            &queries,
+            &counts
+        )
+        .execute(&mut tx)
         .await?;
+        tx.commit().await?;
 
-    for res in result.hits {
-        fresh_formatted_results.push(res.result);
+        Ok(())
     }
-    Ok(fresh_formatted_results)
-}
\ No newline at end of file
+}
+
+/// Attribute configuration of the Meilisearch products index, pushed on startup.
+pub fn product_index_settings() -> Settings {
+    Settings::new()
+        .with_searchable_attributes(["name", "cultivar", "origin", "categories"])
+        .with_filterable_attributes([
+            "id",
+            "category_slugs",
+            "origin",
+            "cultivar",
+            "currency",
+            "price",
+        ])
+        .with_sortable_attributes(["price", "name", "updated_at", "created_at"])
+}
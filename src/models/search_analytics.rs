@@ -0,0 +1,164 @@
+use crate::types::PostgresPool;
+use anyhow::Result;
+use chrono::NaiveDateTime;
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+use tracing::instrument;
+
+/// A logged `/search` call. `filters` holds `field:value` pairs as requested.
+#[derive(Clone, Debug)]
+pub struct SearchEvent {
+    pub id: String,
+    pub query: String,
+    pub filters: Vec<String>,
+    pub sort: Option<String>,
+    pub page: i32,
+    pub result_count: i64,
+    pub latency_ms: i32,
+    pub session_id: Option<String>,
+    /// `ok`, `disabled`, `timeout` or `error`; failed searches have no results
+    pub outcome: String,
+}
+
+#[derive(Deserialize)]
+pub struct SearchClickInput {
+    pub search_id: String,
+    pub product_id: i32,
+    pub position: Option<i32>,
+}
+
+/// Per query totals over a date range. `clicked` counts searches with at least one click.
+#[derive(Serialize, FromRow, Debug)]
+pub struct QueryReport {
+    pub query: String,
+    pub searches: i64,
+    pub clicked: i64,
+    pub click_through_rate: f64,
+    pub avg_results: f64,
+}
+
+#[derive(Serialize, FromRow, Debug)]
+pub struct ClickThroughReport {
+    pub searches: i64,
+    pub clicked: i64,
+    pub clicks: i64,
+    pub click_through_rate: f64,
+}
+
+impl SearchEvent {
+    #[instrument(name = "SearchEvent::insert_all", skip_all, err)]
+    pub async fn insert_all(events: Vec<SearchEvent>, pool: &PostgresPool) -> Result<()> {
+        let mut tx = pool.begin().await?;
+        for event in events {
+            sqlx::query!(
+                r#"
+                  INSERT INTO search_events (id, query, filters, sort, page, result_count, latency_ms, session_id, outcome)
+                      VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)
+                  ON CONFLICT (id) DO NOTHING
+                "#,
+                event.id,
+                event.query,
+                &event.filters,
+                event.sort,
+                event.page,
+                event.result_count,
+                event.latency_ms,
+                event.session_id,
+                event.outcome
+            )
+            .execute(&mut tx)
+            .await?;
+        }
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    #[instrument(name = "SearchEvent::record_click", skip_all, err)]
+    pub async fn record_click(
+        input: SearchClickInput,
+        session_id: Option<String>,
+        pool: &PostgresPool,
+    ) -> Result<()> {
+        let mut tx = pool.begin().await?;
+        sqlx::query!(
+            "INSERT INTO search_clicks (search_id, product_id, position, session_id) VALUES ($1, $2, $3, $4)",
+            input.search_id,
+            input.product_id,
+            input.position,
+            session_id
+        )
+        .execute(&mut tx)
+        .await?;
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    /// The most frequent queries between `from` and `to`. With `zero_results` only
+    /// searches that found nothing are counted. Failed searches are left out.
+    #[instrument(name = "SearchEvent::top_queries", skip_all, err)]
+    pub async fn top_queries(
+        from: NaiveDateTime,
+        to: NaiveDateTime,
+        zero_results: bool,
+        limit: i64,
+        pool: &PostgresPool,
+    ) -> Result<Vec<QueryReport>> {
+        let reports = sqlx::query_as!(
+            QueryReport,
+            r#"
+              SELECT LOWER(e.query) AS "query!",
+                     COUNT(*) AS "searches!",
+                     COUNT(*) FILTER (WHERE EXISTS (SELECT 1 FROM search_clicks c WHERE c.search_id = e.id)) AS "clicked!",
+                     COALESCE(COUNT(*) FILTER (WHERE EXISTS (SELECT 1 FROM search_clicks c WHERE c.search_id = e.id))::float8
+                              / NULLIF(COUNT(*), 0), 0) AS "click_through_rate!",
+                     AVG(e.result_count)::float8 AS "avg_results!"
+                  FROM search_events e
+               WHERE e.created_at >= $1 AND e.created_at < $2
+                 AND e.outcome = 'ok'
+                 AND e.query <> ''
+                 AND (NOT $3 OR e.result_count = 0)
+               GROUP BY LOWER(e.query)
+               ORDER BY 2 DESC, 1
+               LIMIT $4
+            "#,
+            from,
+            to,
+            zero_results,
+            limit
+        )
+        .fetch_all(pool)
+        .await?;
+
+        Ok(reports)
+    }
+
+    #[instrument(name = "SearchEvent::click_through", skip_all, err)]
+    pub async fn click_through(
+        from: NaiveDateTime,
+        to: NaiveDateTime,
+        pool: &PostgresPool,
+    ) -> Result<ClickThroughReport> {
+        let report = sqlx::query_as!(
+            ClickThroughReport,
+            r#"
+              SELECT COUNT(*) AS "searches!",
+                     COUNT(*) FILTER (WHERE clicks > 0) AS "clicked!",
+                     COALESCE(SUM(clicks), 0)::bigint AS "clicks!",
+                     COALESCE(COUNT(*) FILTER (WHERE clicks > 0)::float8 / NULLIF(COUNT(*), 0), 0) AS "click_through_rate!"
+                  FROM (
+                    SELECT e.id, (SELECT COUNT(*) FROM search_clicks c WHERE c.search_id = e.id) AS clicks
+                        FROM search_events e
+                     WHERE e.created_at >= $1 AND e.created_at < $2 AND e.outcome = 'ok'
+                  ) searches
+            "#,
+            from,
+            to
+        )
+        .fetch_one(pool)
+        .await?;
+
+        Ok(report)
+    }
+}
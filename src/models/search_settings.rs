@@ -0,0 +1,250 @@
+use crate::types::PostgresPool;
+use anyhow::{anyhow, Result};
+use chrono::NaiveDateTime;
+use serde::{Deserialize, Serialize};
+use sqlx::{types::Json, Postgres, Transaction};
+use tracing::instrument;
+
+/// The Postgres channel a new current version is announced on, with its number.
+pub const CHANNEL: &str = "search_settings";
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RuleAction {
+    /// show the product first
+    Pin,
+    /// show the product last
+    Bury,
+}
+
+/// Places `product_id` at the top or bottom of the results for `query`.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct MerchandisingRule {
+    pub query: String,
+    pub product_id: i32,
+    pub action: RuleAction,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct SearchSettingsInput {
+    /// groups of interchangeable terms, e.g. `[["coffee", "kaffee"]]`
+    #[serde(default)]
+    pub synonyms: Vec<Vec<String>>,
+    #[serde(default)]
+    pub stop_words: Vec<String>,
+    #[serde(default)]
+    pub rules: Vec<MerchandisingRule>,
+}
+
+/// One version of the synonyms, stop words and merchandising rules. Version 0 is
+/// the empty default used before anything was configured.
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct SearchSettings {
+    pub version: i32,
+    pub synonyms: Vec<Vec<String>>,
+    pub stop_words: Vec<String>,
+    pub rules: Vec<MerchandisingRule>,
+    pub created_by: Option<i32>,
+    pub created_at: Option<NaiveDateTime>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct SearchSettingsVersion {
+    pub version: i32,
+    pub created_by: Option<i32>,
+    pub created_at: NaiveDateTime,
+}
+
+struct SearchSettingsRow {
+    version: i32,
+    synonyms: Json<Vec<Vec<String>>>,
+    stop_words: Vec<String>,
+    rules: Json<Vec<MerchandisingRule>>,
+    created_by: Option<i32>,
+    created_at: NaiveDateTime,
+}
+
+impl From<SearchSettingsRow> for SearchSettings {
+    fn from(row: SearchSettingsRow) -> SearchSettings {
+        SearchSettings {
+            version: row.version,
+            synonyms: row.synonyms.0,
+            stop_words: row.stop_words,
+            rules: row.rules.0,
+            created_by: row.created_by,
+            created_at: Some(row.created_at),
+        }
+    }
+}
+
+impl SearchSettings {
+    #[instrument(name = "SearchSettings::current", skip_all, err)]
+    pub async fn current(pool: &PostgresPool) -> Result<SearchSettings> {
+        let row = sqlx::query_as!(
+            SearchSettingsRow,
+            r#"
+              SELECT version, synonyms AS "synonyms: Json<Vec<Vec<String>>>", stop_words,
+                     rules AS "rules: Json<Vec<MerchandisingRule>>", created_by, created_at
+                  FROM search_settings
+               ORDER BY version DESC
+               LIMIT 1
+            "#
+        )
+        .fetch_optional(pool)
+        .await?;
+
+        Ok(row.map(SearchSettings::from).unwrap_or_default())
+    }
+
+    #[instrument(name = "SearchSettings::find_by_version", skip_all, err)]
+    pub async fn find_by_version(version: i32, pool: &PostgresPool) -> Result<SearchSettings> {
+        let row = sqlx::query_as!(
+            SearchSettingsRow,
+            r#"
+              SELECT version, synonyms AS "synonyms: Json<Vec<Vec<String>>>", stop_words,
+                     rules AS "rules: Json<Vec<MerchandisingRule>>", created_by, created_at
+                  FROM search_settings
+               WHERE version = $1
+            "#,
+            version
+        )
+        .fetch_one(pool)
+        .await?;
+
+        Ok(row.into())
+    }
+
+    #[instrument(name = "SearchSettings::find_versions", skip_all, err)]
+    pub async fn find_versions(pool: &PostgresPool) -> Result<Vec<SearchSettingsVersion>> {
+        let versions = sqlx::query_as!(
+            SearchSettingsVersion,
+            "SELECT version, created_by, created_at FROM search_settings ORDER BY version DESC"
+        )
+        .fetch_all(pool)
+        .await?;
+
+        Ok(versions)
+    }
+
+    /// Stores `input` as the new current version. Terms are lowercased and
+    /// trimmed; synonym groups need at least two distinct terms.
+    #[instrument(name = "SearchSettings::create", skip_all, err)]
+    pub async fn create(
+        input: SearchSettingsInput,
+        created_by: Option<i32>,
+        pool: &PostgresPool,
+    ) -> Result<SearchSettings> {
+        let normalize = |term: &str| term.trim().to_lowercase();
+
+        let mut synonyms = Vec::with_capacity(input.synonyms.len());
+        for group in input.synonyms {
+            let mut terms: Vec<String> = Vec::with_capacity(group.len());
+            for term in group.iter().map(|term| normalize(term)) {
+                if !term.is_empty() && !terms.contains(&term) {
+                    terms.push(term);
+                }
+            }
+            if terms.len() < 2 {
+                return Err(anyhow!(
+                    "synonym group {:?} needs at least two terms",
+                    group
+                ));
+            }
+            synonyms.push(terms);
+        }
+
+        let mut stop_words: Vec<String> = input
+            .stop_words
+            .iter()
+            .map(|word| normalize(word))
+            .filter(|word| !word.is_empty())
+            .collect();
+        stop_words.sort();
+        stop_words.dedup();
+
+        let mut rules = Vec::with_capacity(input.rules.len());
+        for rule in input.rules {
+            let query = normalize(&rule.query);
+            if query.is_empty() {
+                return Err(anyhow!("rule for product {} has no query", rule.product_id));
+            }
+            rules.push(MerchandisingRule { query, ..rule });
+        }
+
+        let mut tx = pool.begin().await?;
+        let row = sqlx::query_as!(
+            SearchSettingsRow,
+            r#"
+              INSERT INTO search_settings (synonyms, stop_words, rules, created_by) VALUES ($1, $2, $3, $4)
+               RETURNING version, synonyms AS "synonyms: Json<Vec<Vec<String>>>", stop_words,
+                         rules AS "rules: Json<Vec<MerchandisingRule>>", created_by, created_at
+            "#,
+            Json(&synonyms) as _,
+            &stop_words,
+            Json(&rules) as _,
+            created_by
+        )
+        .fetch_one(&mut tx)
+        .await?;
+        announce(row.version, &mut tx).await?;
+        tx.commit().await?;
+
+        Ok(row.into())
+    }
+
+    /// Makes an older version current again by copying it into a new version.
+    #[instrument(name = "SearchSettings::restore", skip_all, err)]
+    pub async fn restore(
+        version: i32,
+        created_by: Option<i32>,
+        pool: &PostgresPool,
+    ) -> Result<SearchSettings> {
+        let mut tx = pool.begin().await?;
+        let row = sqlx::query_as!(
+            SearchSettingsRow,
+            r#"
+              INSERT INTO search_settings (synonyms, stop_words, rules, created_by)
+                  SELECT synonyms, stop_words, rules, $2 FROM search_settings WHERE version = $1
+               RETURNING version, synonyms AS "synonyms: Json<Vec<Vec<String>>>", stop_words,
+                         rules AS "rules: Json<Vec<MerchandisingRule>>", created_by, created_at
+            "#,
+            version,
+            created_by
+        )
+        .fetch_one(&mut tx)
+        .await?;
+        announce(row.version, &mut tx).await?;
+        tx.commit().await?;
+
+        Ok(row.into())
+    }
+
+    /// Other terms of every synonym group containing `term`.
+    pub fn synonyms_of(&self, term: &str) -> Vec<&str> {
+        self.synonyms
+            .iter()
+            .filter(|group| group.iter().any(|candidate| candidate == term))
+            .flat_map(|group| group.iter().map(String::as_str))
+            .filter(|candidate| *candidate != term)
+            .collect()
+    }
+
+    pub fn is_stop_word(&self, word: &str) -> bool {
+        self.stop_words
+            .binary_search_by(|stop| stop.as_str().cmp(word))
+            .is_ok()
+    }
+
+    pub fn rules_for<'a>(&'a self, query: &str) -> impl Iterator<Item = &'a MerchandisingRule> {
+        let query = query.trim().to_lowercase();
+        self.rules.iter().filter(move |rule| rule.query == query)
+    }
+}
+
+/// Tells every process to switch to `version`; Postgres sends it on commit.
+async fn announce(version: i32, tx: &mut Transaction<'_, Postgres>) -> Result<()> {
+    sqlx::query!("SELECT pg_notify($1, $2)", CHANNEL, version.to_string())
+        .execute(&mut *tx)
+        .await?;
+    Ok(())
+}
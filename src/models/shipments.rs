@@ -0,0 +1,123 @@
+use crate::{events::DomainEvent, models::outbox::Outbox, types::PostgresPool};
+use anyhow::{anyhow, Result};
+use chrono::NaiveDateTime;
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+use tracing::instrument;
+
+const STATUSES: &[&str] = &["preparing", "in_transit", "delivered", "returned"];
+
+#[derive(Serialize, Deserialize)]
+pub struct ShipmentInput {
+    pub carrier: String,
+    pub tracking_number: Option<String>,
+    /// defaults to `preparing` for new shipments
+    pub status: Option<String>,
+}
+
+#[derive(Serialize, FromRow, Debug)]
+pub struct Shipment {
+    pub id: i32,
+    pub order_id: i32,
+    pub carrier: String,
+    pub tracking_number: Option<String>,
+    /// `preparing`, `in_transit`, `delivered` or `returned`
+    pub status: String,
+    pub updated_at: NaiveDateTime,
+    pub created_at: NaiveDateTime,
+}
+
+fn validate(input: &ShipmentInput) -> Result<()> {
+    match input.status {
+        Some(ref status) if !STATUSES.contains(&status.as_str()) => {
+            Err(anyhow!("unknown shipment status: {}", status))
+        }
+        _ => Ok(()),
+    }
+}
+
+impl Shipment {
+    #[instrument(name = "Shipment::find_by_order", skip_all, err)]
+    pub async fn find_by_order(order_id: i32, pool: &PostgresPool) -> Result<Vec<Shipment>> {
+        let shipments = sqlx::query_as!(
+            Shipment,
+            r#"
+              SELECT id, order_id, carrier, tracking_number, status, updated_at, created_at
+                  FROM shipments
+               WHERE order_id = $1
+              ORDER BY id
+            "#,
+            order_id
+        )
+        .fetch_all(pool)
+        .await?;
+
+        Ok(shipments)
+    }
+
+    #[instrument(name = "Shipment::create", skip_all, err)]
+    pub async fn create(
+        order_id: i32,
+        input: ShipmentInput,
+        pool: &PostgresPool,
+    ) -> Result<Shipment> {
+        validate(&input)?;
+
+        let mut tx = pool.begin().await?;
+        let shipment = sqlx::query_as!(
+            Shipment,
+            r#"
+              INSERT INTO shipments (order_id, carrier, tracking_number, status)
+                  VALUES ($1, $2, $3, $4)
+               RETURNING id, order_id, carrier, tracking_number, status, updated_at, created_at
+            "#,
+            order_id,
+            input.carrier,
+            input.tracking_number,
+            input.status.unwrap_or_else(|| "preparing".to_string())
+        )
+        .fetch_one(&mut tx)
+        .await?;
+        let event = DomainEvent::ShipmentCreated {
+            order_id,
+            shipment_id: shipment.id,
+        };
+        Outbox::publish(&event, &mut tx).await?;
+        tx.commit().await?;
+
+        Ok(shipment)
+    }
+
+    /// Updates a shipment; the status is kept when none is given.
+    #[instrument(name = "Shipment::update", skip_all, err)]
+    pub async fn update(id: i32, input: ShipmentInput, pool: &PostgresPool) -> Result<Shipment> {
+        validate(&input)?;
+
+        let mut tx = pool.begin().await?;
+        let shipment = sqlx::query_as!(
+            Shipment,
+            r#"
+              UPDATE shipments
+                 SET carrier = $1, tracking_number = $2, status = COALESCE($3, status),
+                     updated_at = NOW()
+               WHERE id = $4
+               RETURNING id, order_id, carrier, tracking_number, status, updated_at, created_at
+            "#,
+            input.carrier,
+            input.tracking_number,
+            input.status,
+            id
+        )
+        .fetch_one(&mut tx)
+        .await?;
+        let event = DomainEvent::ShipmentUpdated {
+            order_id: shipment.order_id,
+            shipment_id: shipment.id,
+            status: shipment.status.clone(),
+        };
+        Outbox::publish(&event, &mut tx).await?;
+        tx.commit().await?;
+
+        Ok(shipment)
+    }
+}
@@ -1,8 +1,9 @@
 use crate::errors::ServiceError;
-use crate::{auth, types::PostgresPool};
+use crate::{auth, events::DomainEvent, models::outbox::Outbox, types::PostgresPool};
 use anyhow::Result;
 use chrono::NaiveDateTime;
 use serde::{Deserialize, Serialize};
+use tracing::instrument;
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct UserInput {
@@ -21,6 +22,7 @@ pub struct User {
     pub username: String,
     pub password: String,
     pub email: String,
+    pub is_admin: bool,
     pub created_at: NaiveDateTime,
     pub updated_at: NaiveDateTime,
 }
@@ -38,11 +40,12 @@ pub struct Credentials {
 }
 
 impl User {
+    #[instrument(name = "User::find_all", skip_all, err)]
     pub async fn find_all(pool: &PostgresPool) -> Result<Vec<User>> {
         let users = sqlx::query_as!(
             User,
             r#"
-                SELECT id, first_name, last_name, email, username, password, created_at, updated_at
+                SELECT id, first_name, last_name, email, username, password, is_admin, created_at, updated_at
                     FROM users
                 ORDER BY created_at
             "#
@@ -53,6 +56,7 @@ impl User {
         Ok(users)
     }
 
+    #[instrument(name = "User::find_by_id", skip_all, err)]
     pub async fn find_by_id(id: i32, pool: &PostgresPool) -> Result<User> {
         let user = sqlx::query_as!(
             User,
@@ -67,6 +71,7 @@ impl User {
         Ok(user)
     }
 
+    #[instrument(name = "User::find_by_username", skip_all, err)]
     pub async fn find_by_username(username: &str, pool: &PostgresPool) -> Result<User> {
         let user = sqlx::query_as!(
             User,
@@ -81,41 +86,55 @@ impl User {
         Ok(user)
     }
 
-    pub async fn create(input: UserInput, pool: &PostgresPool) -> Result<User> {
+    #[instrument(name = "User::create", skip_all, err)]
+    pub async fn create(input: UserInput, salt: &str, pool: &PostgresPool) -> Result<User> {
         let result = User::find_by_username(&input.username, pool).await;
         match result {
             Ok(user) => Ok(user),
-            _ => {
-                let mut tx = pool.begin().await?;
-                let mut user = sqlx::query_as!(
-                    User,
-                    r#"
-                        INSERT INTO users (first_name, last_name, email, username, password) VALUES ($1, $2, $3, $4, $5)
-                         RETURNING id, first_name, last_name, email, username, password, created_at, updated_at
-                    "#,
-                    input.first_name,
-                    input.last_name,
-                    input.email,
-                    input.username,
-                    auth::hash(&input.password),
-                )
-                .fetch_one(&mut tx)
-                .await?;
-                tx.commit().await?;
-
-                user.password = "".to_string();
-                Ok(user)
-            }
+            _ => User::insert(input, salt, false, pool).await,
         }
     }
 
+    /// Creates a user that may use the admin endpoints; the user and the flag are
+    /// written in the same transaction.
+    #[instrument(name = "User::create_admin", skip_all, err)]
+    pub async fn create_admin(input: UserInput, salt: &str, pool: &PostgresPool) -> Result<User> {
+        User::insert(input, salt, true, pool).await
+    }
+
+    async fn insert(input: UserInput, salt: &str, is_admin: bool, pool: &PostgresPool) -> Result<User> {
+        let mut tx = pool.begin().await?;
+        let mut user = sqlx::query_as!(
+            User,
+            r#"
+                INSERT INTO users (first_name, last_name, email, username, password, is_admin) VALUES ($1, $2, $3, $4, $5, $6)
+                 RETURNING id, first_name, last_name, email, username, password, is_admin, created_at, updated_at
+            "#,
+            input.first_name,
+            input.last_name,
+            input.email,
+            input.username,
+            auth::hash(&input.password, salt),
+            is_admin,
+        )
+        .fetch_one(&mut tx)
+        .await?;
+        let event = DomainEvent::UserRegistered { user_id: user.id };
+        Outbox::publish(&event, &mut tx).await?;
+        tx.commit().await?;
+
+        user.password = "".to_string();
+        Ok(user)
+    }
+
+    #[instrument(name = "User::update", skip_all, err)]
     pub async fn update(id: i32, input: UserInput, pool: &PostgresPool) -> Result<User> {
         let mut tx = pool.begin().await.unwrap();
         let mut user = sqlx::query_as!(
             User,
             r#"
                 UPDATE users SET first_name = $1, last_name = $2, email = $3 WHERE id = $4
-                 RETURNING id, first_name, last_name, email, username, password, created_at, updated_at
+                 RETURNING id, first_name, last_name, email, username, password, is_admin, created_at, updated_at
             "#,
             input.first_name,
             input.last_name,
@@ -130,6 +149,7 @@ impl User {
         Ok(user)
     }
 
+    #[instrument(name = "User::delete", skip_all, err)]
     pub async fn delete(id: i32, pool: &PostgresPool) -> Result<u64> {
         let mut tx = pool.begin().await?;
         let result = sqlx::query_as!(User, "DELETE FROM users WHERE id = $1", id)
@@ -140,20 +160,48 @@ impl User {
         Ok(result.rows_affected())
     }
 
+    #[instrument(name = "User::set_admin", skip_all, err)]
+    pub async fn set_admin(id: i32, is_admin: bool, pool: &PostgresPool) -> Result<u64> {
+        let mut tx = pool.begin().await?;
+        let result = sqlx::query!("UPDATE users SET is_admin = $1, updated_at = NOW() WHERE id = $2", is_admin, id)
+            .execute(&mut tx)
+            .await?;
+
+        tx.commit().await?;
+        Ok(result.rows_affected())
+    }
+
+    #[instrument(name = "User::set_password", skip_all, err)]
+    pub async fn set_password(id: i32, password: &str, salt: &str, pool: &PostgresPool) -> Result<u64> {
+        let mut tx = pool.begin().await?;
+        let result = sqlx::query!(
+            "UPDATE users SET password = $1, updated_at = NOW() WHERE id = $2",
+            auth::hash(password, salt),
+            id
+        )
+        .execute(&mut tx)
+        .await?;
+
+        tx.commit().await?;
+        Ok(result.rows_affected())
+    }
+
+    #[instrument(name = "User::authenticate", skip_all)]
     pub async fn authenticate(
         credentials: Credentials,
+        salt: &str,
         pool: &PostgresPool,
     ) -> Result<User, ServiceError> {
         let result = User::find_by_username(&credentials.username, pool).await;
         match result {
             Ok(user) => {
                 // TODO: figure out why I keep getting hacked
-                if auth::hash(&credentials.password) == user.password {
+                if auth::hash(&credentials.password, salt) == user.password {
                     return Ok(user);
                 }
-                return Err(ServiceError::Unauthorized);
+                Err(ServiceError::Unauthorized)
             }
-            _ => return Err(ServiceError::Unauthorized),
+            _ => Err(ServiceError::Unauthorized),
         }
     }
 }
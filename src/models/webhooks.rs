@@ -0,0 +1,363 @@
+use crate::{events::EVENT_TYPES, types::PostgresPool};
+use anyhow::{anyhow, Result};
+use chrono::NaiveDateTime;
+use serde::{Deserialize, Serialize};
+use serde_json::Value as JsonValue;
+use sqlx::{FromRow, Postgres, Transaction};
+use tracing::instrument;
+use uuid::Uuid;
+
+#[derive(Serialize, Deserialize)]
+pub struct WebhookSubscriptionInput {
+    pub url: String,
+    /// empty subscribes to every event type
+    #[serde(default)]
+    pub event_types: Vec<String>,
+    /// generated when missing
+    pub secret: Option<String>,
+    pub active: Option<bool>,
+    pub description: Option<String>,
+}
+
+#[derive(Serialize, FromRow, Debug)]
+pub struct WebhookSubscription {
+    pub id: i32,
+    pub url: String,
+    pub event_types: Vec<String>,
+    /// only returned when the subscription is created
+    #[serde(skip_serializing)]
+    pub secret: String,
+    pub active: bool,
+    pub description: Option<String>,
+    pub updated_at: NaiveDateTime,
+    pub created_at: NaiveDateTime,
+}
+
+#[derive(Serialize, FromRow, Debug)]
+pub struct WebhookDelivery {
+    pub id: i64,
+    pub subscription_id: i32,
+    pub event_id: i64,
+    pub event_type: String,
+    pub body: JsonValue,
+    /// `pending`, `succeeded`, or `failed` while retries are left or after they ran out
+    pub status: String,
+    pub attempts: i32,
+    pub response_status: Option<i32>,
+    pub delivered_at: Option<NaiveDateTime>,
+    pub updated_at: NaiveDateTime,
+    pub created_at: NaiveDateTime,
+}
+
+#[derive(Serialize, FromRow, Debug)]
+pub struct WebhookAttempt {
+    pub id: i64,
+    pub delivery_id: i64,
+    pub response_status: Option<i32>,
+    pub response_body: Option<String>,
+    pub error: Option<String>,
+    pub duration_ms: i32,
+    pub created_at: NaiveDateTime,
+}
+
+/// The outcome of one request, see `WebhookDelivery::record_attempt`.
+pub struct AttemptOutcome {
+    pub response_status: Option<i32>,
+    pub response_body: Option<String>,
+    pub error: Option<String>,
+    pub duration_ms: i32,
+}
+
+#[derive(Deserialize)]
+pub struct DeliveryFilter {
+    pub status: Option<String>,
+    pub limit: Option<i64>,
+    pub offset: Option<i64>,
+}
+
+fn validate(input: &WebhookSubscriptionInput) -> Result<()> {
+    if !input.url.starts_with("http://") && !input.url.starts_with("https://") {
+        return Err(anyhow!("webhook url must be http or https: {}", input.url));
+    }
+    if let Some(event_type) = input
+        .event_types
+        .iter()
+        .find(|event_type| !EVENT_TYPES.contains(&event_type.as_str()))
+    {
+        return Err(anyhow!("unknown event type: {}", event_type));
+    }
+    if matches!(input.secret, Some(ref secret) if secret.len() < 16) {
+        return Err(anyhow!("webhook secret must be at least 16 characters"));
+    }
+    Ok(())
+}
+
+impl WebhookSubscription {
+    #[instrument(name = "WebhookSubscription::find_all", skip_all, err)]
+    pub async fn find_all(pool: &PostgresPool) -> Result<Vec<WebhookSubscription>> {
+        let subscriptions = sqlx::query_as!(
+            WebhookSubscription,
+            r#"
+              SELECT id, url, event_types, secret, active, description, updated_at, created_at
+                  FROM webhook_subscriptions
+              ORDER BY id
+            "#
+        )
+        .fetch_all(pool)
+        .await?;
+
+        Ok(subscriptions)
+    }
+
+    #[instrument(name = "WebhookSubscription::find_by_id", skip_all, err)]
+    pub async fn find_by_id(id: i32, pool: &PostgresPool) -> Result<WebhookSubscription> {
+        let subscription = sqlx::query_as!(
+            WebhookSubscription,
+            r#"
+              SELECT * FROM webhook_subscriptions WHERE id = $1
+            "#,
+            id
+        )
+        .fetch_one(pool)
+        .await?;
+
+        Ok(subscription)
+    }
+
+    /// Active subscriptions that want `event_type`.
+    #[instrument(name = "WebhookSubscription::find_for_event", skip_all, err)]
+    pub async fn find_for_event(
+        event_type: &str,
+        tx: &mut Transaction<'_, Postgres>,
+    ) -> Result<Vec<WebhookSubscription>> {
+        let subscriptions = sqlx::query_as!(
+            WebhookSubscription,
+            r#"
+              SELECT id, url, event_types, secret, active, description, updated_at, created_at
+                  FROM webhook_subscriptions
+               WHERE active AND (event_types = '{}' OR $1 = ANY(event_types))
+              ORDER BY id
+            "#,
+            event_type
+        )
+        .fetch_all(&mut *tx)
+        .await?;
+
+        Ok(subscriptions)
+    }
+
+    #[instrument(name = "WebhookSubscription::create", skip_all, err)]
+    pub async fn create(
+        input: WebhookSubscriptionInput,
+        pool: &PostgresPool,
+    ) -> Result<WebhookSubscription> {
+        validate(&input)?;
+        let secret = input
+            .secret
+            .unwrap_or_else(|| format!("{}{}", Uuid::new_v4().simple(), Uuid::new_v4().simple()));
+
+        let mut tx = pool.begin().await?;
+        let subscription = sqlx::query_as!(
+            WebhookSubscription,
+            r#"
+              INSERT INTO webhook_subscriptions (url, event_types, secret, active, description)
+                  VALUES ($1, $2, $3, $4, $5)
+               RETURNING id, url, event_types, secret, active, description, updated_at, created_at
+            "#,
+            input.url,
+            &input.event_types,
+            secret,
+            input.active.unwrap_or(true),
+            input.description
+        )
+        .fetch_one(&mut tx)
+        .await?;
+        tx.commit().await?;
+
+        Ok(subscription)
+    }
+
+    /// Updates a subscription; the secret is only replaced when one is given.
+    #[instrument(name = "WebhookSubscription::update", skip_all, err)]
+    pub async fn update(
+        id: i32,
+        input: WebhookSubscriptionInput,
+        pool: &PostgresPool,
+    ) -> Result<WebhookSubscription> {
+        validate(&input)?;
+
+        let mut tx = pool.begin().await?;
+        let subscription = sqlx::query_as!(
+            WebhookSubscription,
+            r#"
+              UPDATE webhook_subscriptions
+                 SET url = $1, event_types = $2, secret = COALESCE($3, secret),
+                     active = COALESCE($4, active), description = $5, updated_at = NOW()
+               WHERE id = $6
+               RETURNING id, url, event_types, secret, active, description, updated_at, created_at
+            "#,
+            input.url,
+            &input.event_types,
+            input.secret,
+            input.active,
+            input.description,
+            id
+        )
+        .fetch_one(&mut tx)
+        .await?;
+        tx.commit().await?;
+
+        Ok(subscription)
+    }
+
+    #[instrument(name = "WebhookSubscription::delete", skip_all, err)]
+    pub async fn delete(id: i32, pool: &PostgresPool) -> Result<u64> {
+        let mut tx = pool.begin().await?;
+        let result = sqlx::query!("DELETE FROM webhook_subscriptions WHERE id = $1", id)
+            .execute(&mut tx)
+            .await?;
+
+        tx.commit().await?;
+        Ok(result.rows_affected())
+    }
+}
+
+impl WebhookDelivery {
+    /// Records a delivery of event `event_id` to a subscription. Returns `None` when
+    /// it already exists, i.e. the event was dispatched before.
+    #[instrument(name = "WebhookDelivery::create", skip_all, err)]
+    pub async fn create(
+        subscription_id: i32,
+        event_id: i64,
+        event_type: &str,
+        body: &JsonValue,
+        tx: &mut Transaction<'_, Postgres>,
+    ) -> Result<Option<i64>> {
+        let id = sqlx::query_scalar!(
+            r#"
+              INSERT INTO webhook_deliveries (subscription_id, event_id, event_type, body)
+                  VALUES ($1, $2, $3, $4)
+              ON CONFLICT (subscription_id, event_id) DO NOTHING
+               RETURNING id
+            "#,
+            subscription_id,
+            event_id,
+            event_type,
+            body
+        )
+        .fetch_optional(&mut *tx)
+        .await?;
+
+        Ok(id)
+    }
+
+    #[instrument(name = "WebhookDelivery::find_by_id", skip_all, err)]
+    pub async fn find_by_id(id: i64, pool: &PostgresPool) -> Result<WebhookDelivery> {
+        let delivery = sqlx::query_as!(
+            WebhookDelivery,
+            r#"
+              SELECT * FROM webhook_deliveries WHERE id = $1
+            "#,
+            id
+        )
+        .fetch_one(pool)
+        .await?;
+
+        Ok(delivery)
+    }
+
+    /// Lists a subscription's deliveries, newest first.
+    #[instrument(name = "WebhookDelivery::find_by_subscription", skip_all, err)]
+    pub async fn find_by_subscription(
+        subscription_id: i32,
+        filter: &DeliveryFilter,
+        pool: &PostgresPool,
+    ) -> Result<Vec<WebhookDelivery>> {
+        let deliveries = sqlx::query_as!(
+            WebhookDelivery,
+            r#"
+              SELECT id, subscription_id, event_id, event_type, body, status, attempts,
+                     response_status, delivered_at, updated_at, created_at
+                  FROM webhook_deliveries
+               WHERE subscription_id = $1
+                 AND ($2::text IS NULL OR status = $2)
+              ORDER BY id DESC
+              LIMIT $3 OFFSET $4
+            "#,
+            subscription_id,
+            filter.status,
+            filter.limit.unwrap_or(50),
+            filter.offset.unwrap_or(0)
+        )
+        .fetch_all(pool)
+        .await?;
+
+        Ok(deliveries)
+    }
+
+    /// Logs a request and updates the delivery's status with its result.
+    #[instrument(name = "WebhookDelivery::record_attempt", skip_all, err)]
+    pub async fn record_attempt(
+        id: i64,
+        succeeded: bool,
+        outcome: AttemptOutcome,
+        pool: &PostgresPool,
+    ) -> Result<()> {
+        let mut tx = pool.begin().await?;
+        sqlx::query!(
+            r#"
+              INSERT INTO webhook_attempts (delivery_id, response_status, response_body, error, duration_ms)
+                  VALUES ($1, $2, $3, $4, $5)
+            "#,
+            id,
+            outcome.response_status,
+            outcome.response_body,
+            outcome.error,
+            outcome.duration_ms
+        )
+        .execute(&mut tx)
+        .await?;
+
+        sqlx::query!(
+            r#"
+              UPDATE webhook_deliveries
+                 SET status = CASE WHEN $2 THEN 'succeeded' ELSE 'failed' END,
+                     attempts = attempts + 1, response_status = $3,
+                     delivered_at = CASE WHEN $2 THEN NOW() ELSE delivered_at END,
+                     updated_at = NOW()
+               WHERE id = $1
+            "#,
+            id,
+            succeeded,
+            outcome.response_status
+        )
+        .execute(&mut tx)
+        .await?;
+        tx.commit().await?;
+
+        Ok(())
+    }
+}
+
+impl WebhookAttempt {
+    #[instrument(name = "WebhookAttempt::find_by_delivery", skip_all, err)]
+    pub async fn find_by_delivery(
+        delivery_id: i64,
+        pool: &PostgresPool,
+    ) -> Result<Vec<WebhookAttempt>> {
+        let attempts = sqlx::query_as!(
+            WebhookAttempt,
+            r#"
+              SELECT id, delivery_id, response_status, response_body, error, duration_ms, created_at
+                  FROM webhook_attempts
+               WHERE delivery_id = $1
+              ORDER BY id
+            "#,
+            delivery_id
+        )
+        .fetch_all(pool)
+        .await?;
+
+        Ok(attempts)
+    }
+}
@@ -0,0 +1,295 @@
+use super::{
+    query::{Sort, HIGHLIGHT_FIELDS},
+    SearchBackend, SearchConfig, SearchError, SearchHit, SearchRequest, SearchResults,
+};
+use crate::models::{
+    search::{product_index_settings, ProductDocument},
+    search_settings::SearchSettings,
+};
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use meilisearch_sdk::{client::Client, search::Selectors, settings::Settings};
+use std::{collections::HashMap, future::Future, time::Duration};
+use tracing::Instrument;
+
+/// Searches a Meilisearch index. `client` is `None` when search is switched off,
+/// in which case every call fails with `SearchError::Disabled`.
+#[derive(Clone)]
+pub struct MeiliSearch {
+    client: Option<Client>,
+    timeout: Duration,
+    products_index: String,
+}
+
+impl MeiliSearch {
+    /// Creates the client and checks that the engine answers. An unreachable engine
+    /// is an error only when `required` is set; otherwise the server starts and
+    /// search requests fail until the engine comes back.
+    pub async fn connect(config: SearchConfig) -> Result<MeiliSearch> {
+        if !config.enabled {
+            log::info!("search is disabled");
+            return Ok(MeiliSearch {
+                client: None,
+                timeout: config.timeout(),
+                products_index: config.products_index,
+            });
+        }
+
+        let engine = MeiliSearch {
+            client: Some(Client::new(config.url.as_str(), config.api_key.as_str())),
+            timeout: config.timeout(),
+            products_index: config.products_index,
+        };
+        match engine
+            .run("health", |client| async move { client.health().await })
+            .await
+        {
+            Ok(_) => log::info!("connected to search engine at {}", config.url),
+            Err(err) if config.required => {
+                return Err(anyhow!(
+                    "search engine at {} is unreachable: {}",
+                    config.url,
+                    err
+                ))
+            }
+            Err(err) => log::warn!(
+                "search engine at {} is unreachable, search will be unavailable: {}",
+                config.url,
+                err
+            ),
+        }
+
+        Ok(engine)
+    }
+
+    /// Runs a request against the engine, bounded by the configured timeout, in a
+    /// client span named after `operation`.
+    async fn run<T, E, F, Fut>(&self, operation: &'static str, request: F) -> Result<T, SearchError>
+    where
+        F: FnOnce(Client) -> Fut,
+        Fut: Future<Output = Result<T, E>>,
+        E: Into<anyhow::Error>,
+    {
+        let client = self.client.clone().ok_or(SearchError::Disabled)?;
+        let span = tracing::info_span!(
+            "meilisearch",
+            otel.name = operation,
+            otel.kind = "client",
+            index = %self.products_index,
+        );
+        let result = match tokio::time::timeout(self.timeout, request(client))
+            .instrument(span.clone())
+            .await
+        {
+            Ok(Ok(value)) => Ok(value),
+            Ok(Err(err)) => Err(SearchError::Engine(err.into())),
+            Err(_) => Err(SearchError::Timeout),
+        };
+        if let Err(ref err) = result {
+            tracing::error!(parent: &span, error = %err, "search request failed");
+        }
+        result
+    }
+}
+
+#[async_trait]
+impl SearchBackend for MeiliSearch {
+    fn name(&self) -> &'static str {
+        "meilisearch"
+    }
+
+    fn is_enabled(&self) -> bool {
+        self.client.is_some()
+    }
+
+    async fn health(&self) -> Result<(), SearchError> {
+        self.run("health", |client| async move { client.health().await })
+            .await?;
+        Ok(())
+    }
+
+    async fn configure(&self) -> Result<(), SearchError> {
+        let index = self.products_index.clone();
+        self.run("configure", |client| async move {
+            client
+                .index(index)
+                .set_settings(&product_index_settings())
+                .await
+        })
+        .await?;
+
+        Ok(())
+    }
+
+    async fn apply_settings(&self, settings: &SearchSettings) -> Result<(), SearchError> {
+        // Meilisearch wants each term mapped to its synonyms
+        let mut synonyms: HashMap<String, Vec<String>> = HashMap::new();
+        for group in &settings.synonyms {
+            for term in group {
+                synonyms
+                    .entry(term.clone())
+                    .or_default()
+                    .extend(group.iter().filter(|other| *other != term).cloned());
+            }
+        }
+        let update = Settings::new()
+            .with_synonyms(synonyms)
+            .with_stop_words(settings.stop_words.clone());
+
+        let index = self.products_index.clone();
+        self.run("apply_settings", |client| async move {
+            client.index(index).set_settings(&update).await
+        })
+        .await?;
+
+        Ok(())
+    }
+
+    async fn index_product(&self, document: ProductDocument) -> Result<(), SearchError> {
+        let index = self.products_index.clone();
+        self.run("index_product", |client| async move {
+            client
+                .index(index)
+                .add_or_replace(&[document], Some("id"))
+                .await
+        })
+        .await?;
+
+        Ok(())
+    }
+
+    async fn remove_product(&self, id: i32) -> Result<(), SearchError> {
+        let index = self.products_index.clone();
+        self.run("remove_product", |client| async move {
+            client.index(index).delete_document(id).await
+        })
+        .await?;
+
+        Ok(())
+    }
+
+    async fn search(&self, request: &SearchRequest) -> Result<SearchResults, SearchError> {
+        let index = self.products_index.clone();
+        let query = request.query.clone();
+        let offset = request.offset as usize;
+        let limit = request.limit as usize;
+        let highlight = request.highlight;
+        let filter = filter_expression(request).map_err(SearchError::Engine)?;
+        let sort = request.sort.map(Sort::to_param);
+        let facets = request
+            .facets
+            .iter()
+            .map(|field| document_field(field))
+            .collect::<Result<Vec<_>>>()
+            .map_err(SearchError::Engine)?;
+        let fields = facets.clone();
+
+        let result = self
+            .run("search", |client| async move {
+                let sort = sort.as_deref().map(|sort| [sort]);
+                let index = client.index(index);
+                let mut search = index.search();
+                search
+                    .with_query(&query)
+                    .with_offset(offset)
+                    .with_limit(limit);
+                if let Some(ref filter) = filter {
+                    search.with_filter(filter);
+                }
+                if let Some(ref sort) = sort {
+                    search.with_sort(sort);
+                }
+                if !facets.is_empty() {
+                    search.with_facets_distribution(Selectors::Some(&facets));
+                }
+                if highlight {
+                    search.with_attributes_to_highlight(Selectors::Some(&HIGHLIGHT_FIELDS));
+                }
+                search.execute::<ProductDocument>().await
+            })
+            .await?;
+
+        let mut distribution = result.facets_distribution.unwrap_or_default();
+        let facets = request
+            .facets
+            .iter()
+            .zip(fields)
+            .map(|(field, document_field)| {
+                let counts = distribution
+                    .remove(document_field)
+                    .unwrap_or_default()
+                    .into_iter()
+                    .map(|(value, count)| (value, count as u64))
+                    .collect();
+                (field.to_string(), counts)
+            })
+            .collect();
+        let hits = result
+            .hits
+            .into_iter()
+            .map(|hit| {
+                let highlight = hit.formatted_result.filter(|_| highlight).map(|formatted| {
+                    HIGHLIGHT_FIELDS
+                        .iter()
+                        .filter_map(|field| {
+                            let value = formatted.get(*field)?.as_str()?;
+                            Some((field.to_string(), value.to_string()))
+                        })
+                        .collect()
+                });
+                SearchHit {
+                    document: hit.result,
+                    highlight,
+                }
+            })
+            .collect();
+
+        Ok(SearchResults {
+            hits,
+            total: result.nb_hits as u64,
+            page: request.page,
+            per_page: request.per_page,
+            facets,
+            processing_time_ms: result.processing_time_ms as u64,
+        })
+    }
+}
+
+/// Name of a public filter/facet field in the index.
+fn document_field(field: &str) -> Result<&'static str> {
+    match field {
+        "category" => Ok("category_slugs"),
+        "origin" => Ok("origin"),
+        "cultivar" => Ok("cultivar"),
+        "currency" => Ok("currency"),
+        _ => Err(anyhow!("{} is not a filter field", field)),
+    }
+}
+
+/// Turns filters and product restrictions into a Meilisearch filter expression,
+/// e.g. `origin = "Colombia" AND id != 7 AND ...`.
+fn filter_expression(request: &SearchRequest) -> Result<Option<String>> {
+    let mut conditions = request
+        .filters
+        .iter()
+        .map(|filter| {
+            let value = filter.value.replace('\\', "\\\\").replace('"', "\\\"");
+            Ok(format!("{} = \"{}\"", document_field(filter.field)?, value))
+        })
+        .collect::<Result<Vec<_>>>()?;
+    conditions.extend(request.excluded.iter().map(|id| format!("id != {}", id)));
+    if let Some(ref only) = request.only {
+        let ids: Vec<String> = only.iter().map(|id| format!("id = {}", id)).collect();
+        if ids.is_empty() {
+            // ids are positive, so this matches nothing
+            conditions.push("id < 0".to_string());
+        } else {
+            conditions.push(format!("({})", ids.join(" OR ")));
+        }
+    }
+
+    if conditions.is_empty() {
+        return Ok(None);
+    }
+    Ok(Some(conditions.join(" AND ")))
+}
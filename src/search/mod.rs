@@ -0,0 +1,427 @@
+use crate::{
+    models::search::ProductDocument,
+    models::search_settings::{RuleAction, SearchSettings},
+    types::PostgresPool,
+};
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use serde::Deserialize;
+use std::{sync::Arc, time::Duration};
+
+pub mod meilisearch;
+pub mod postgres;
+pub mod query;
+pub mod suggest;
+
+pub use self::meilisearch::MeiliSearch;
+pub use self::postgres::PostgresSearch;
+pub use self::query::{Filter, SearchHit, SearchParams, SearchRequest, SearchResults};
+pub use self::suggest::SuggestCache;
+
+/// Search engine settings, the `[search]` section of the settings.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct SearchConfig {
+    /// `meilisearch` or `postgres`
+    pub backend: String,
+    pub enabled: bool,
+    pub url: String,
+    pub api_key: String,
+    /// fail startup instead of running without search when the engine is unreachable
+    pub required: bool,
+    pub timeout_ms: u64,
+    pub products_index: String,
+}
+
+impl Default for SearchConfig {
+    fn default() -> SearchConfig {
+        SearchConfig {
+            backend: "meilisearch".to_string(),
+            enabled: true,
+            url: "http://localhost:7700".to_string(),
+            api_key: String::new(),
+            required: false,
+            timeout_ms: 2000,
+            products_index: "products".to_string(),
+        }
+    }
+}
+
+impl SearchConfig {
+    pub fn timeout(&self) -> Duration {
+        Duration::from_millis(self.timeout_ms)
+    }
+
+    pub fn validate(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+        if !["meilisearch", "postgres"].contains(&self.backend.as_str()) {
+            problems.push(format!(
+                "search.backend must be meilisearch or postgres, got {}",
+                self.backend
+            ));
+        }
+        if self.timeout_ms == 0 {
+            problems.push("search.timeout_ms must be at least 1".to_string());
+        }
+        if self.products_index.is_empty() {
+            problems.push("search.products_index must not be empty".to_string());
+        }
+        problems
+    }
+}
+
+#[derive(Debug)]
+pub enum SearchError {
+    Disabled,
+    Timeout,
+    Engine(anyhow::Error),
+}
+
+impl std::fmt::Display for SearchError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SearchError::Disabled => write!(f, "search is disabled"),
+            SearchError::Timeout => write!(f, "search engine did not answer in time"),
+            SearchError::Engine(err) => write!(f, "search engine error: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for SearchError {}
+
+/// A product search implementation. The search outbox worker feeds it every product
+/// change; backends that read straight from the database can ignore those calls.
+#[async_trait]
+pub trait SearchBackend: Send + Sync {
+    fn name(&self) -> &'static str;
+    /// Whether the outbox worker should run for this backend.
+    fn is_enabled(&self) -> bool;
+    /// Checks that the engine answers, for the readiness probe.
+    async fn health(&self) -> Result<(), SearchError>;
+    /// Pushes index settings; called once at startup.
+    async fn configure(&self) -> Result<(), SearchError>;
+    /// Takes over synonyms and stop words; called at startup and after every change.
+    async fn apply_settings(&self, settings: &SearchSettings) -> Result<(), SearchError>;
+    async fn index_product(&self, document: ProductDocument) -> Result<(), SearchError>;
+    async fn remove_product(&self, id: i32) -> Result<(), SearchError>;
+    async fn search(&self, request: &SearchRequest) -> Result<SearchResults, SearchError>;
+}
+
+/// Builds the backend selected by `search.backend`.
+pub async fn from_config(
+    config: SearchConfig,
+    pool: PostgresPool,
+) -> Result<Arc<dyn SearchBackend>> {
+    match config.backend.as_str() {
+        "meilisearch" => Ok(Arc::new(MeiliSearch::connect(config).await?)),
+        "postgres" => Ok(Arc::new(PostgresSearch::new(pool))),
+        other => Err(anyhow!("unknown search backend: {}", other)),
+    }
+}
+
+/// The pin and bury rules for a search's query. Pinned products that pass the
+/// filters lead the results in rule order, buried ones that match follow all other
+/// results. They are placed before paging, so every product is on exactly one page
+/// and counts once in the total and the facets.
+#[derive(Default)]
+pub struct Rules {
+    pinned: Vec<ProductDocument>,
+    buried: Vec<i32>,
+}
+
+impl Rules {
+    pub async fn resolve(
+        request: &SearchRequest,
+        settings: &SearchSettings,
+        pool: &PostgresPool,
+    ) -> Result<Rules> {
+        let mut rules = Rules::default();
+        for rule in settings.rules_for(&request.query) {
+            let id = rule.product_id;
+            if rules.pinned.iter().any(|document| document.id == id) || rules.buried.contains(&id) {
+                continue;
+            }
+            match rule.action {
+                RuleAction::Pin => match ProductDocument::find_by_id(id, pool).await? {
+                    Some(document) if matches_filters(&document, &request.filters)? => {
+                        rules.pinned.push(document)
+                    }
+                    _ => {}
+                },
+                RuleAction::Bury => rules.buried.push(id),
+            }
+        }
+        Ok(rules)
+    }
+
+    /// Runs `request` on `backend` with the rules applied: the other products are
+    /// searched without the pinned and buried ones, and the page is cut from pinned,
+    /// other and buried products in that order.
+    pub async fn search(
+        &self,
+        backend: &dyn SearchBackend,
+        request: &SearchRequest,
+    ) -> Result<SearchResults, SearchError> {
+        if self.pinned.is_empty() && self.buried.is_empty() {
+            return backend.search(request).await;
+        }
+        let start = request.offset as u64;
+        let end = start + request.limit as u64;
+        let pinned = self.pinned.len() as u64;
+
+        let mut others = request.clone();
+        others
+            .excluded
+            .extend(self.pinned.iter().map(|document| document.id));
+        others.excluded.extend(&self.buried);
+        others.offset = start.saturating_sub(pinned) as u32;
+        others.limit = (end.saturating_sub(pinned) - start.saturating_sub(pinned)) as u32;
+        let others = backend.search(&others).await?;
+
+        let buried = if self.buried.is_empty() {
+            None
+        } else {
+            let mut buried = request.clone();
+            buried.only = Some(self.buried.clone());
+            buried.offset = 0;
+            buried.limit = self.buried.len() as u32;
+            Some(backend.search(&buried).await?)
+        };
+
+        let mut results = SearchResults {
+            hits: Vec::with_capacity(request.limit as usize),
+            total: pinned + others.total,
+            page: request.page,
+            per_page: request.per_page,
+            facets: others.facets,
+            processing_time_ms: others.processing_time_ms,
+        };
+        for document in &self.pinned {
+            for field in &request.facets {
+                let counts = results.facets.entry(field.to_string()).or_default();
+                for value in field_values(document, field).map_err(SearchError::Engine)? {
+                    *counts.entry(value.to_string()).or_insert(0) += 1;
+                }
+            }
+        }
+        results.hits.extend(
+            self.pinned
+                .iter()
+                .skip(start as usize)
+                .take(request.limit as usize)
+                .map(|document| SearchHit {
+                    document: document.clone(),
+                    highlight: None,
+                }),
+        );
+        results.hits.extend(others.hits);
+        if let Some(buried) = buried {
+            let first = results.total;
+            results.total += buried.total;
+            results.processing_time_ms += buried.processing_time_ms;
+            for (field, counts) in buried.facets {
+                let merged = results.facets.entry(field).or_default();
+                for (value, count) in counts {
+                    *merged.entry(value).or_insert(0) += count;
+                }
+            }
+            results.hits.extend(
+                buried
+                    .hits
+                    .into_iter()
+                    .skip(start.saturating_sub(first) as usize)
+                    .take(end.saturating_sub(first.max(start)) as usize),
+            );
+        }
+
+        Ok(results)
+    }
+}
+
+/// The values of a public filter field in `document`.
+fn field_values<'a>(document: &'a ProductDocument, field: &str) -> Result<Vec<&'a str>> {
+    match field {
+        "category" => Ok(document.category_slugs.iter().map(String::as_str).collect()),
+        "origin" => Ok(vec![&document.origin]),
+        "cultivar" => Ok(vec![&document.cultivar]),
+        "currency" => Ok(vec![&document.currency]),
+        other => Err(anyhow!("unknown filter field: {}", other)),
+    }
+}
+
+fn matches_filters(document: &ProductDocument, filters: &[Filter]) -> Result<bool> {
+    for filter in filters {
+        if !field_values(document, filter.field)?.contains(&filter.value.as_str()) {
+            return Ok(false);
+        }
+    }
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use std::collections::HashMap;
+
+    /// Answers searches from a list, in list order.
+    struct ListSearch(Vec<ProductDocument>);
+
+    #[async_trait]
+    impl SearchBackend for ListSearch {
+        fn name(&self) -> &'static str {
+            "list"
+        }
+
+        fn is_enabled(&self) -> bool {
+            true
+        }
+
+        async fn health(&self) -> Result<(), SearchError> {
+            Ok(())
+        }
+
+        async fn configure(&self) -> Result<(), SearchError> {
+            Ok(())
+        }
+
+        async fn apply_settings(&self, _settings: &SearchSettings) -> Result<(), SearchError> {
+            Ok(())
+        }
+
+        async fn index_product(&self, _document: ProductDocument) -> Result<(), SearchError> {
+            Ok(())
+        }
+
+        async fn remove_product(&self, _id: i32) -> Result<(), SearchError> {
+            Ok(())
+        }
+
+        async fn search(&self, request: &SearchRequest) -> Result<SearchResults, SearchError> {
+            let matches: Vec<&ProductDocument> = self
+                .0
+                .iter()
+                .filter(|document| !request.excluded.contains(&document.id))
+                .filter(|document| {
+                    request
+                        .only
+                        .as_ref()
+                        .is_none_or(|only| only.contains(&document.id))
+                })
+                .collect();
+            let mut facets: HashMap<String, HashMap<String, u64>> = HashMap::new();
+            for field in &request.facets {
+                let counts = facets.entry(field.to_string()).or_default();
+                for document in &matches {
+                    for value in field_values(document, field).unwrap() {
+                        *counts.entry(value.to_string()).or_insert(0) += 1;
+                    }
+                }
+            }
+
+            Ok(SearchResults {
+                total: matches.len() as u64,
+                hits: matches
+                    .into_iter()
+                    .skip(request.offset as usize)
+                    .take(request.limit as usize)
+                    .map(|document| SearchHit {
+                        document: document.clone(),
+                        highlight: None,
+                    })
+                    .collect(),
+                page: request.page,
+                per_page: request.per_page,
+                facets,
+                processing_time_ms: 0,
+            })
+        }
+    }
+
+    fn document(id: i32) -> ProductDocument {
+        ProductDocument {
+            id,
+            name: format!("product {}", id),
+            price: 100,
+            currency: "EUR".to_string(),
+            origin: if id % 2 == 0 { "Kenya" } else { "Colombia" }.to_string(),
+            cultivar: "Gesha".to_string(),
+            categories: Vec::new(),
+            category_slugs: Vec::new(),
+            attributes: json!({}),
+            image: None,
+            updated_at: 0,
+            created_at: 0,
+        }
+    }
+
+    fn request(page: u32, per_page: u32) -> SearchRequest {
+        SearchRequest::try_from(SearchParams {
+            q: Some("gesha".to_string()),
+            filter: None,
+            facets: Some("origin".to_string()),
+            sort: None,
+            page: Some(page),
+            per_page: Some(per_page),
+            highlight: false,
+        })
+        .unwrap()
+    }
+
+    async fn page(rules: &Rules, backend: &ListSearch, page: u32) -> (Vec<i32>, u64) {
+        let results = rules.search(backend, &request(page, 4)).await.unwrap();
+        let ids = results.hits.iter().map(|hit| hit.document.id).collect();
+        (ids, results.total)
+    }
+
+    #[actix_web::test]
+    async fn pinned_products_lead_and_shift_every_page() {
+        let backend = ListSearch((1..=10).map(document).collect());
+        let rules = Rules {
+            pinned: vec![document(7), document(3)],
+            buried: Vec::new(),
+        };
+
+        assert_eq!(page(&rules, &backend, 1).await, (vec![7, 3, 1, 2], 10));
+        assert_eq!(page(&rules, &backend, 2).await, (vec![4, 5, 6, 8], 10));
+        assert_eq!(page(&rules, &backend, 3).await, (vec![9, 10], 10));
+    }
+
+    #[actix_web::test]
+    async fn buried_products_follow_all_other_results() {
+        let backend = ListSearch((1..=10).map(document).collect());
+        let rules = Rules {
+            pinned: Vec::new(),
+            buried: vec![2, 42],
+        };
+
+        assert_eq!(page(&rules, &backend, 1).await, (vec![1, 3, 4, 5], 10));
+        assert_eq!(page(&rules, &backend, 2).await, (vec![6, 7, 8, 9], 10));
+        assert_eq!(page(&rules, &backend, 3).await, (vec![10, 2], 10));
+        assert_eq!(page(&rules, &backend, 4).await, (vec![], 10));
+    }
+
+    #[actix_web::test]
+    async fn counts_pinned_products_the_search_did_not_find() {
+        let backend = ListSearch((1..=5).map(document).collect());
+        let rules = Rules {
+            pinned: vec![document(8)],
+            buried: vec![1],
+        };
+
+        let results = rules.search(&backend, &request(1, 4)).await.unwrap();
+        let ids: Vec<i32> = results.hits.iter().map(|hit| hit.document.id).collect();
+        assert_eq!(ids, vec![8, 2, 3, 4]);
+        assert_eq!(results.total, 6);
+        assert_eq!(results.facets["origin"]["Kenya"], 3);
+        assert_eq!(results.facets["origin"]["Colombia"], 3);
+
+        assert_eq!(page(&rules, &backend, 2).await, (vec![5, 1], 6));
+    }
+
+    #[actix_web::test]
+    async fn without_rules_the_backend_pages_alone() {
+        let backend = ListSearch((1..=5).map(document).collect());
+        assert_eq!(page(&Rules::default(), &backend, 2).await, (vec![5], 5));
+    }
+}
@@ -0,0 +1,224 @@
+use super::{SearchBackend, SearchError, SearchRequest, SearchResults};
+use crate::{
+    models::{search::ProductDocument, search_settings::SearchSettings},
+    types::PostgresPool,
+};
+use async_trait::async_trait;
+use std::{sync::RwLock, time::Instant};
+
+/// Upper bound on the synonym combinations a query is expanded to.
+const MAX_VARIANTS: usize = 16;
+
+/// Searches the `products.search_vector` column with `websearch_to_tsquery`, and
+/// falls back to `pg_trgm` similarity on the name to tolerate typos. Documents are
+/// read straight from the database, so there is no index to keep in sync.
+pub struct PostgresSearch {
+    pool: PostgresPool,
+    settings: RwLock<SearchSettings>,
+}
+
+impl PostgresSearch {
+    pub fn new(pool: PostgresPool) -> PostgresSearch {
+        PostgresSearch {
+            pool,
+            settings: RwLock::new(SearchSettings::default()),
+        }
+    }
+
+    /// `query` rewritten with the settings last applied to this backend.
+    fn expand(&self, query: &str) -> String {
+        expand_query(
+            query,
+            &self.settings.read().unwrap_or_else(|e| e.into_inner()),
+        )
+    }
+}
+
+#[async_trait]
+impl SearchBackend for PostgresSearch {
+    fn name(&self) -> &'static str {
+        "postgres"
+    }
+
+    fn is_enabled(&self) -> bool {
+        true
+    }
+
+    async fn health(&self) -> Result<(), SearchError> {
+        sqlx::query("SELECT 1")
+            .execute(&self.pool)
+            .await
+            .map_err(|err| SearchError::Engine(err.into()))?;
+        Ok(())
+    }
+
+    async fn configure(&self) -> Result<(), SearchError> {
+        Ok(())
+    }
+
+    async fn apply_settings(&self, settings: &SearchSettings) -> Result<(), SearchError> {
+        // settings are replaced whole, so a panicked writer can't leave them half done
+        *self.settings.write().unwrap_or_else(|e| e.into_inner()) = settings.clone();
+        Ok(())
+    }
+
+    async fn index_product(&self, _document: ProductDocument) -> Result<(), SearchError> {
+        Ok(())
+    }
+
+    async fn remove_product(&self, _id: i32) -> Result<(), SearchError> {
+        Ok(())
+    }
+
+    async fn search(&self, request: &SearchRequest) -> Result<SearchResults, SearchError> {
+        let started = Instant::now();
+        let text = self.expand(&request.query);
+        let hits = ProductDocument::search(request, &text, &self.pool)
+            .await
+            .map_err(SearchError::Engine)?;
+        let mut counts = ProductDocument::facet_counts(request, &text, &self.pool)
+            .await
+            .map_err(SearchError::Engine)?;
+
+        let total = counts
+            .remove("")
+            .and_then(|total| total.values().next().copied())
+            .unwrap_or(0);
+        let facets = request
+            .facets
+            .iter()
+            .map(|field| (field.to_string(), counts.remove(*field).unwrap_or_default()))
+            .collect();
+
+        Ok(SearchResults {
+            hits,
+            total,
+            page: request.page,
+            per_page: request.per_page,
+            facets,
+            processing_time_ms: started.elapsed().as_millis() as u64,
+        })
+    }
+}
+
+/// Rewrites a query for `websearch_to_tsquery`: stop words are dropped, and every
+/// combination of synonym substitutions is OR-ed in (up to `MAX_VARIANTS`), so
+/// `kaffee gesha` also matches `coffee gesha`.
+fn expand_query(query: &str, settings: &SearchSettings) -> String {
+    let mut variants: Vec<Vec<String>> = vec![Vec::new()];
+    for word in query.split_whitespace().map(str::to_lowercase) {
+        if settings.is_stop_word(&word) {
+            continue;
+        }
+        let mut alternatives = vec![word.clone()];
+        alternatives.extend(settings.synonyms_of(&word).into_iter().map(str::to_string));
+
+        let mut expanded = Vec::with_capacity(variants.len() * alternatives.len());
+        for variant in &variants {
+            for alternative in &alternatives {
+                if expanded.len() == MAX_VARIANTS {
+                    break;
+                }
+                let mut words = variant.clone();
+                words.push(alternative.clone());
+                expanded.push(words);
+            }
+        }
+        variants = expanded;
+    }
+
+    variants
+        .iter()
+        .map(|words| words.join(" "))
+        .filter(|variant| !variant.is_empty())
+        .collect::<Vec<_>>()
+        .join(" or ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support;
+
+    fn settings(synonyms: &[&[&str]], stop_words: &[&str]) -> SearchSettings {
+        let mut stop_words: Vec<String> = stop_words.iter().map(|word| word.to_string()).collect();
+        stop_words.sort();
+        SearchSettings {
+            synonyms: synonyms
+                .iter()
+                .map(|group| group.iter().map(|term| term.to_string()).collect())
+                .collect(),
+            stop_words,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn leaves_plain_queries_alone() {
+        assert_eq!(
+            expand_query("  Gesha   Natural ", &settings(&[], &[])),
+            "gesha natural"
+        );
+        assert_eq!(expand_query("", &settings(&[], &[])), "");
+    }
+
+    #[test]
+    fn drops_stop_words() {
+        let settings = settings(&[], &["the", "a"]);
+        assert_eq!(
+            expand_query("The Gesha of a farm", &settings),
+            "gesha of farm"
+        );
+        assert_eq!(expand_query("a the", &settings), "");
+    }
+
+    #[test]
+    fn ors_in_every_synonym_combination() {
+        let settings = settings(&[&["coffee", "kaffee"], &["gesha", "geisha"]], &[]);
+        assert_eq!(
+            expand_query("kaffee gesha", &settings),
+            "kaffee gesha or kaffee geisha or coffee gesha or coffee geisha"
+        );
+    }
+
+    #[test]
+    fn stop_words_are_not_expanded() {
+        let settings = settings(&[&["the", "das"]], &["the"]);
+        assert_eq!(expand_query("the coffee", &settings), "coffee");
+    }
+
+    #[test]
+    fn caps_the_number_of_variants() {
+        let settings = settings(
+            &[
+                &["a1", "a2"],
+                &["b1", "b2"],
+                &["c1", "c2"],
+                &["d1", "d2"],
+                &["e1", "e2"],
+            ],
+            &[],
+        );
+        let expanded = expand_query("a1 b1 c1 d1 e1", &settings);
+        let variants: Vec<&str> = expanded.split(" or ").collect();
+
+        assert_eq!(variants.len(), MAX_VARIANTS);
+        assert_eq!(variants[0], "a1 b1 c1 d1 e1");
+        assert!(variants
+            .iter()
+            .all(|variant| variant.split(' ').count() == 5));
+    }
+
+    #[actix_web::test]
+    async fn expands_with_the_applied_settings() {
+        let search = PostgresSearch::new(test_support::unused_pool());
+        assert_eq!(search.expand("the kaffee"), "the kaffee");
+
+        let applied = settings(&[&["coffee", "kaffee"]], &["the"]);
+        search.apply_settings(&applied).await.unwrap();
+        assert_eq!(search.expand("the kaffee"), "kaffee or coffee");
+
+        search.apply_settings(&settings(&[], &[])).await.unwrap();
+        assert_eq!(search.expand("the kaffee"), "the kaffee");
+    }
+}
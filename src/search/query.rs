@@ -0,0 +1,175 @@
+use crate::models::search::ProductDocument;
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// Fields that can be filtered on and faceted, by their public name.
+pub const FACET_FIELDS: [&str; 4] = ["category", "origin", "cultivar", "currency"];
+pub const SORT_FIELDS: [&str; 4] = ["price", "name", "updated_at", "created_at"];
+/// Fields that get highlighted snippets.
+pub const HIGHLIGHT_FIELDS: [&str; 3] = ["name", "cultivar", "origin"];
+
+const DEFAULT_PER_PAGE: u32 = 20;
+const MAX_PER_PAGE: u32 = 100;
+
+/// Query string of `GET /search`, e.g.
+/// `?q=gesha&filter=origin:Colombia,category:coffee&facets=cultivar,origin&sort=price:asc&page=2&per_page=20&highlight=true`.
+#[derive(Deserialize)]
+pub struct SearchParams {
+    pub q: Option<String>,
+    pub filter: Option<String>,
+    pub facets: Option<String>,
+    pub sort: Option<String>,
+    pub page: Option<u32>,
+    pub per_page: Option<u32>,
+    #[serde(default)]
+    pub highlight: bool,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Filter {
+    pub field: &'static str,
+    pub value: String,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SortOrder {
+    Asc,
+    Desc,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Sort {
+    pub field: &'static str,
+    pub order: SortOrder,
+}
+
+impl Sort {
+    /// The `field:order` form used in query strings and by Meilisearch.
+    pub fn to_param(self) -> String {
+        let order = match self.order {
+            SortOrder::Asc => "asc",
+            SortOrder::Desc => "desc",
+        };
+        format!("{}:{}", self.field, order)
+    }
+}
+
+/// A validated search, independent of the backend answering it.
+#[derive(Clone, Debug)]
+pub struct SearchRequest {
+    pub query: String,
+    pub filters: Vec<Filter>,
+    pub facets: Vec<&'static str>,
+    pub sort: Option<Sort>,
+    pub page: u32,
+    pub per_page: u32,
+    pub highlight: bool,
+    /// Products left out, e.g. those placed by merchandising rules.
+    pub excluded: Vec<i32>,
+    /// Restricts the search to these products when set.
+    pub only: Option<Vec<i32>>,
+    /// The slice of the results returned; `page` of `per_page` results unless the
+    /// merchandising rules shift it.
+    pub offset: u32,
+    pub limit: u32,
+}
+
+impl TryFrom<SearchParams> for SearchRequest {
+    type Error = anyhow::Error;
+
+    fn try_from(params: SearchParams) -> Result<SearchRequest> {
+        let field = |name: &str, allowed: &[&'static str]| {
+            allowed
+                .iter()
+                .find(|field| **field == name)
+                .copied()
+                .ok_or_else(|| anyhow!("unknown field: {}", name))
+        };
+        let list = |value: &Option<String>| -> Vec<String> {
+            value
+                .as_deref()
+                .unwrap_or("")
+                .split(',')
+                .map(str::trim)
+                .filter(|part| !part.is_empty())
+                .map(str::to_string)
+                .collect()
+        };
+
+        let filters = list(&params.filter)
+            .into_iter()
+            .map(|filter| {
+                let (name, value) = filter
+                    .split_once(':')
+                    .ok_or_else(|| anyhow!("filter must look like field:value, got {}", filter))?;
+                Ok(Filter {
+                    field: field(name.trim(), &FACET_FIELDS)?,
+                    value: value.trim().to_string(),
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let mut facets = Vec::new();
+        for name in list(&params.facets) {
+            let facet = field(&name, &FACET_FIELDS)?;
+            if !facets.contains(&facet) {
+                facets.push(facet);
+            }
+        }
+        let sort = match params.sort.as_deref().map(str::trim) {
+            None | Some("") => None,
+            Some(sort) => {
+                let (name, order) = sort.split_once(':').unwrap_or((sort, "asc"));
+                let order = match order {
+                    "asc" => SortOrder::Asc,
+                    "desc" => SortOrder::Desc,
+                    other => return Err(anyhow!("sort order must be asc or desc, got {}", other)),
+                };
+                Some(Sort {
+                    field: field(name, &SORT_FIELDS)?,
+                    order,
+                })
+            }
+        };
+        let per_page = params.per_page.unwrap_or(DEFAULT_PER_PAGE);
+        if per_page == 0 || per_page > MAX_PER_PAGE {
+            return Err(anyhow!("per_page must be between 1 and {}", MAX_PER_PAGE));
+        }
+
+        let page = params.page.unwrap_or(1).max(1);
+
+        Ok(SearchRequest {
+            query: params.q.unwrap_or_default().trim().to_string(),
+            filters,
+            facets,
+            sort,
+            page,
+            per_page,
+            highlight: params.highlight,
+            excluded: Vec::new(),
+            only: None,
+            offset: (page - 1).saturating_mul(per_page),
+            limit: per_page,
+        })
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct SearchHit {
+    #[serde(flatten)]
+    pub document: ProductDocument,
+    /// Highlighted `HIGHLIGHT_FIELDS`, matches wrapped in `<em>`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub highlight: Option<HashMap<String, String>>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SearchResults {
+    pub hits: Vec<SearchHit>,
+    pub total: u64,
+    pub page: u32,
+    pub per_page: u32,
+    /// Value counts per requested facet, by public field name.
+    pub facets: HashMap<String, HashMap<String, u64>>,
+    pub processing_time_ms: u64,
+}
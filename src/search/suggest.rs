@@ -0,0 +1,41 @@
+use crate::models::search::Suggestions;
+use std::{
+    collections::HashMap,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+/// Keeps recent suggestion lists in memory, since a search box asks for the same
+/// short prefixes over and over. Entries expire after `ttl`; when `capacity` is
+/// reached the whole cache is dropped rather than tracking recency.
+pub struct SuggestCache {
+    entries: Mutex<HashMap<String, (Instant, Suggestions)>>,
+    ttl: Duration,
+    capacity: usize,
+}
+
+impl SuggestCache {
+    pub fn new(ttl: Duration, capacity: usize) -> SuggestCache {
+        SuggestCache {
+            entries: Mutex::new(HashMap::new()),
+            ttl,
+            capacity,
+        }
+    }
+
+    pub fn get(&self, prefix: &str) -> Option<Suggestions> {
+        let entries = self.entries.lock().unwrap_or_else(|e| e.into_inner());
+        entries
+            .get(prefix)
+            .filter(|(cached_at, _)| cached_at.elapsed() < self.ttl)
+            .map(|(_, suggestions)| suggestions.clone())
+    }
+
+    pub fn insert(&self, prefix: String, suggestions: Suggestions) {
+        let mut entries = self.entries.lock().unwrap_or_else(|e| e.into_inner());
+        if entries.len() >= self.capacity {
+            entries.clear();
+        }
+        entries.insert(prefix, (Instant::now(), suggestions));
+    }
+}
@@ -0,0 +1,252 @@
+use crate::{
+    events::Subscriber,
+    handlers,
+    metrics::{Metrics, RequestMetrics},
+    models, routes, search,
+    settings::Settings,
+    storage,
+    telemetry::RequestTracing,
+    types::PostgresPool,
+    workers::{
+        self,
+        jobs::JobContext,
+        order_feed::{OrderFeed, OrderNotifier},
+        search_index::SearchIndexer,
+        supervisor::Supervisor,
+        webhooks::WebhookDispatcher,
+    },
+};
+use actix_files::Files;
+use actix_session::{storage::CookieSessionStore, SessionMiddleware};
+use actix_web::{middleware::Condition, rt, web, App, HttpServer};
+use anyhow::{Context, Result};
+use futures::future::join_all;
+use std::{
+    sync::{Arc, RwLock},
+    time::Duration,
+};
+
+/// Starts the background workers and serves the API until SIGTERM or SIGINT.
+/// Shutting down stops accepting connections, gives in-flight requests and then
+/// the workers `server.shutdown_timeout_secs` each to finish.
+pub async fn run(settings: Settings, pool: PostgresPool) -> Result<()> {
+    let signing_key = settings.cookie.signing_key();
+    let mut supervisor = Supervisor::new();
+
+    let store = storage::from_config(&settings.storage).context("Failed to configure storage")?;
+
+    let search = search::from_config(settings.search.clone(), pool.clone())
+        .await
+        .context("Failed to configure search")?;
+    let search_settings = models::search_settings::SearchSettings::current(&pool)
+        .await
+        .context("Failed to load search settings")?;
+    let mut subscribers: Vec<Box<dyn Subscriber>> = vec![
+        Box::new(WebhookDispatcher { pool: pool.clone() }),
+        Box::new(OrderNotifier { pool: pool.clone() }),
+    ];
+    if search.is_enabled() {
+        if let Err(err) = search.configure().await {
+            log::error!(
+                "could not configure the {} search backend: {}",
+                search.name(),
+                err
+            );
+        }
+        if let Err(err) = search.apply_settings(&search_settings).await {
+            log::warn!("could not apply search settings: {}", err);
+        }
+        subscribers.push(Box::new(SearchIndexer {
+            pool: pool.clone(),
+            backend: search.clone(),
+        }));
+    }
+    let search_settings = Arc::new(RwLock::new(Arc::new(search_settings)));
+    workers::search_settings::start(
+        &mut supervisor,
+        pool.clone(),
+        search_settings.clone(),
+        search.clone(),
+    );
+    let search_settings = web::Data::from(search_settings);
+
+    workers::outbox::start(&mut supervisor, pool.clone(), subscribers);
+    if settings.jobs.in_process {
+        let context = JobContext {
+            pool: pool.clone(),
+            store: store.clone(),
+            search: search.clone(),
+        };
+        workers::jobs::start(&mut supervisor, settings.jobs.clone(), context);
+    }
+    let query_log = workers::query_log::start(&mut supervisor, pool.clone());
+    let order_feed = Arc::new(OrderFeed::new());
+    workers::order_feed::start(&mut supervisor, pool.clone(), order_feed.clone());
+    let suggestions = web::Data::new(search::SuggestCache::new(Duration::from_secs(60), 1000));
+
+    let metrics = web::Data::new(
+        Metrics::new(settings.database.max_connections).context("Failed to register metrics")?,
+    );
+
+    let addr = settings.server.address();
+    let server_settings = settings.server.clone();
+    let metrics_settings = settings.metrics.clone();
+    let settings = web::Data::new(settings);
+    let api_metrics = metrics.clone();
+    let api_pool = pool.clone();
+    let api_settings = settings.clone();
+    let api_order_feed = order_feed.clone();
+
+    let mut server = HttpServer::new(move || {
+        let settings = api_settings.clone();
+        App::new()
+            .app_data(settings.clone())
+            .app_data(web::Data::new(settings.images.clone()))
+            .app_data(web::Data::new(api_pool.clone()))
+            .app_data(web::Data::from(store.clone()))
+            .app_data(web::Data::from(search.clone()))
+            .app_data(web::Data::new(query_log.clone()))
+            .app_data(suggestions.clone())
+            .app_data(search_settings.clone())
+            .app_data(web::Data::from(api_order_feed.clone()))
+            .app_data(api_metrics.clone())
+            // .wrap(HttpAuthentication::bearer(validator))
+            .wrap(settings.cors.cors())
+            .wrap(
+                SessionMiddleware::builder(CookieSessionStore::default(), signing_key.clone())
+                    .cookie_name(settings.cookie.name.clone())
+                    .cookie_secure(settings.cookie.secure)
+                    .cookie_http_only(settings.cookie.http_only)
+                    .cookie_same_site(settings.cookie.same_site())
+                    .build(),
+            )
+            .wrap(Condition::new(
+                settings.metrics.enabled,
+                RequestMetrics(api_metrics.get_ref().clone()),
+            ))
+            .wrap(RequestTracing)
+            .route("/", web::get().to(routes::index))
+            .route("/login", web::post().to(handlers::users::login))
+            .service(
+                web::scope("/api").service(
+                    web::scope("/v1")
+                        .configure(handlers::users::config)
+                        .configure(handlers::products::config)
+                        .configure(handlers::order_events::config)
+                        .configure(handlers::orders::config)
+                        .configure(handlers::shipments::config)
+                        .configure(handlers::currencies::config)
+                        .configure(handlers::categories::config)
+                        .configure(handlers::attributes::config)
+                        .configure(handlers::images::config)
+                        .configure(handlers::search::config)
+                        .configure(handlers::search_settings::config)
+                        .configure(handlers::jobs::config)
+                        .configure(handlers::webhooks::config),
+                ),
+            )
+            .configure(handlers::auth::config)
+            .configure(handlers::health::config)
+            .configure(|cfg| {
+                if settings.metrics.enabled && settings.metrics.bind.is_none() {
+                    handlers::metrics::config(cfg);
+                }
+            })
+            .service(
+                web::scope("/static").default_service(
+                    Files::new("", "./static")
+                        .index_file("index.html")
+                        .use_last_modified(true),
+                ),
+            )
+    })
+    .keep_alive(Duration::from_secs(server_settings.keep_alive_secs))
+    .client_request_timeout(Duration::from_millis(server_settings.request_timeout_ms))
+    .shutdown_timeout(server_settings.shutdown_timeout_secs);
+    if let Some(workers) = server_settings.workers {
+        server = server.workers(workers);
+    }
+
+    let server = server.disable_signals().bind(addr)?.run();
+    let mut handles = vec![server.handle()];
+
+    let metrics_server = match metrics_settings.bind {
+        Some(ref metrics_addr) if metrics_settings.enabled => {
+            let metrics_server = HttpServer::new(move || {
+                App::new()
+                    .app_data(settings.clone())
+                    .app_data(web::Data::new(pool.clone()))
+                    .app_data(metrics.clone())
+                    .configure(handlers::metrics::config)
+            })
+            .workers(1)
+            .disable_signals()
+            .bind(metrics_addr)?
+            .run();
+            handles.push(metrics_server.handle());
+            Some(metrics_server)
+        }
+        _ => None,
+    };
+
+    rt::spawn(async move {
+        shutdown_signal().await;
+        log::info!("shutdown requested, draining in-flight requests");
+        order_feed.close();
+        join_all(handles.iter().map(|handle| handle.stop(true))).await;
+    });
+
+    let result = match metrics_server {
+        Some(metrics_server) => futures::try_join!(server, metrics_server).map(|_| ()),
+        None => server.await,
+    };
+
+    log::info!("server stopped, stopping background workers");
+    supervisor
+        .shutdown(Duration::from_secs(server_settings.shutdown_timeout_secs))
+        .await;
+    Ok(result?)
+}
+
+/// Runs only the job worker, for `shopapi worker`, until SIGTERM or SIGINT.
+pub async fn run_worker(settings: Settings, pool: PostgresPool) -> Result<()> {
+    let mut supervisor = Supervisor::new();
+    let context = JobContext {
+        store: storage::from_config(&settings.storage)?,
+        search: search::from_config(settings.search.clone(), pool.clone()).await?,
+        pool,
+    };
+    // indexing jobs use the same synonyms and stop words as the servers
+    workers::search_settings::start(
+        &mut supervisor,
+        context.pool.clone(),
+        workers::search_settings::initial(),
+        context.search.clone(),
+    );
+    workers::jobs::start(&mut supervisor, settings.jobs.clone(), context);
+    log::info!("job worker started");
+
+    shutdown_signal().await;
+    log::info!("shutdown requested, finishing running jobs");
+    supervisor
+        .shutdown(Duration::from_secs(settings.server.shutdown_timeout_secs))
+        .await;
+    Ok(())
+}
+
+/// Resolves on SIGTERM or SIGINT (Ctrl-C).
+async fn shutdown_signal() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        let mut terminate = signal(SignalKind::terminate()).expect("Failed to listen for SIGTERM");
+        tokio::select! {
+            _ = terminate.recv() => {}
+            _ = tokio::signal::ctrl_c() => {}
+        }
+    }
+
+    #[cfg(not(unix))]
+    let _ = tokio::signal::ctrl_c().await;
+}
@@ -0,0 +1,475 @@
+use crate::{search::SearchConfig, storage::StorageConfig, types::PostgresPool};
+use actix_cors::Cors;
+use actix_web::cookie::{Key, SameSite};
+use anyhow::{anyhow, Result};
+use config::{Config, Environment, File};
+use serde::Deserialize;
+use sqlx::postgres::PgPoolOptions;
+use std::{env, time::Duration};
+use tracing_subscriber::EnvFilter;
+
+/// Prefix of the environment variables overriding settings; nested keys are
+/// separated by `__`, e.g. `SHOPAPI_DATABASE__MAX_CONNECTIONS=20`.
+const ENV_PREFIX: &str = "SHOPAPI";
+/// Settings file read when `SHOPAPI_CONFIG` doesn't name another one.
+const DEFAULT_FILE: &str = "shopapi.toml";
+
+/// Everything the server can be configured with. Values are layered: built-in
+/// defaults, then the TOML settings file, then environment variables.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct Settings {
+    pub server: ServerConfig,
+    pub database: DatabaseConfig,
+    pub auth: AuthConfig,
+    pub cors: CorsConfig,
+    pub cookie: CookieConfig,
+    pub images: ImageConfig,
+    pub storage: StorageConfig,
+    pub search: SearchConfig,
+    pub jobs: JobsConfig,
+    pub mail: MailConfig,
+    pub metrics: MetricsConfig,
+    pub telemetry: TelemetryConfig,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct ServerConfig {
+    pub host: String,
+    pub port: u16,
+    /// defaults to the number of CPUs
+    pub workers: Option<usize>,
+    pub keep_alive_secs: u64,
+    pub request_timeout_ms: u64,
+    /// on SIGTERM/SIGINT, how long in-flight requests and then the background
+    /// workers get to finish
+    pub shutdown_timeout_secs: u64,
+}
+
+impl Default for ServerConfig {
+    fn default() -> ServerConfig {
+        ServerConfig {
+            host: "127.0.0.1".to_string(),
+            port: 8080,
+            workers: None,
+            keep_alive_secs: 5,
+            request_timeout_ms: 5000,
+            shutdown_timeout_secs: 30,
+        }
+    }
+}
+
+impl ServerConfig {
+    pub fn address(&self) -> String {
+        format!("{}:{}", self.host, self.port)
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct DatabaseConfig {
+    pub url: String,
+    pub max_connections: u32,
+    pub min_connections: u32,
+    pub connect_timeout_secs: u64,
+    pub idle_timeout_secs: Option<u64>,
+}
+
+impl Default for DatabaseConfig {
+    fn default() -> DatabaseConfig {
+        DatabaseConfig {
+            url: String::new(),
+            max_connections: 5,
+            min_connections: 0,
+            connect_timeout_secs: 30,
+            idle_timeout_secs: Some(600),
+        }
+    }
+}
+
+impl DatabaseConfig {
+    pub fn connect_timeout(&self) -> Duration {
+        Duration::from_secs(self.connect_timeout_secs)
+    }
+
+    pub fn idle_timeout(&self) -> Option<Duration> {
+        self.idle_timeout_secs.map(Duration::from_secs)
+    }
+
+    pub async fn connect(&self) -> Result<PostgresPool> {
+        let pool = PgPoolOptions::new()
+            .max_connections(self.max_connections)
+            .min_connections(self.min_connections)
+            .connect_timeout(self.connect_timeout())
+            .idle_timeout(self.idle_timeout())
+            .connect(&self.url)
+            .await?;
+
+        Ok(pool)
+    }
+}
+
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct AuthConfig {
+    /// salt of the password hashes; changing it invalidates every password
+    pub salt: String,
+}
+
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct CorsConfig {
+    /// origins allowed to call the API; empty allows any origin
+    pub allowed_origins: Vec<String>,
+    pub max_age_secs: Option<usize>,
+}
+
+impl CorsConfig {
+    pub fn cors(&self) -> Cors {
+        if self.allowed_origins.is_empty() {
+            return Cors::permissive();
+        }
+        self.allowed_origins
+            .iter()
+            .fold(Cors::default(), |cors, origin| cors.allowed_origin(origin))
+            .allow_any_method()
+            .allow_any_header()
+            .supports_credentials()
+            .max_age(self.max_age_secs)
+    }
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum SameSitePolicy {
+    Strict,
+    Lax,
+    None,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct CookieConfig {
+    pub name: String,
+    pub secure: bool,
+    pub http_only: bool,
+    pub same_site: SameSitePolicy,
+    /// secret of at least 32 bytes signing the session cookie; without it a random
+    /// key is used and sessions don't survive a restart
+    pub key: Option<String>,
+}
+
+impl Default for CookieConfig {
+    fn default() -> CookieConfig {
+        CookieConfig {
+            name: "id".to_string(),
+            secure: false,
+            http_only: false,
+            same_site: SameSitePolicy::Strict,
+            key: None,
+        }
+    }
+}
+
+impl CookieConfig {
+    pub fn signing_key(&self) -> Key {
+        match self.key {
+            Some(ref key) => Key::derive_from(key.as_bytes()),
+            None => Key::generate(),
+        }
+    }
+
+    pub fn same_site(&self) -> SameSite {
+        match self.same_site {
+            SameSitePolicy::Strict => SameSite::Strict,
+            SameSitePolicy::Lax => SameSite::Lax,
+            SameSitePolicy::None => SameSite::None,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct ImageConfig {
+    pub upload_max_bytes: usize,
+    /// signs render URLs, see `handlers::images::render_url`
+    pub render_signing_key: String,
+}
+
+impl Default for ImageConfig {
+    fn default() -> ImageConfig {
+        ImageConfig {
+            upload_max_bytes: 10 * 1024 * 1024,
+            render_signing_key: String::new(),
+        }
+    }
+}
+
+// nothing sends mail yet; the settings are validated so deployments can prepare them
+#[allow(dead_code)]
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct MailConfig {
+    pub enabled: bool,
+    pub smtp_host: String,
+    pub smtp_port: u16,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    pub from: String,
+}
+
+impl Default for MailConfig {
+    fn default() -> MailConfig {
+        MailConfig {
+            enabled: false,
+            smtp_host: String::new(),
+            smtp_port: 587,
+            username: None,
+            password: None,
+            from: String::new(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct JobsConfig {
+    /// run the job worker inside `shopapi serve`; turn off when `shopapi worker`
+    /// processes run separately
+    pub in_process: bool,
+    /// jobs run at the same time per worker process
+    pub concurrency: usize,
+    pub poll_interval_ms: u64,
+    /// a running job whose worker stopped renewing its lease for this long is
+    /// assumed lost and run again; workers renew it every third of this
+    pub lease_secs: u64,
+}
+
+impl Default for JobsConfig {
+    fn default() -> JobsConfig {
+        JobsConfig {
+            in_process: true,
+            concurrency: 4,
+            poll_interval_ms: 1000,
+            lease_secs: 600,
+        }
+    }
+}
+
+/// `/metrics` has to be protected: either served on its own `bind` address that
+/// only the scraper can reach, or behind a bearer `token`, or both.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct MetricsConfig {
+    pub enabled: bool,
+    /// e.g. `127.0.0.1:9090`; when set `/metrics` isn't served on the API address
+    pub bind: Option<String>,
+    pub token: Option<String>,
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    Text,
+    Json,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct TelemetryConfig {
+    pub format: LogFormat,
+    /// `RUST_LOG` style directives, used when `RUST_LOG` isn't set
+    pub filter: String,
+    /// OTLP/gRPC collector, e.g. `http://localhost:4317`; spans aren't exported without it
+    pub otlp_endpoint: Option<String>,
+    pub service_name: String,
+}
+
+impl Default for TelemetryConfig {
+    fn default() -> TelemetryConfig {
+        TelemetryConfig {
+            format: LogFormat::Text,
+            filter: "info".to_string(),
+            otlp_endpoint: None,
+            service_name: "shopapi".to_string(),
+        }
+    }
+}
+
+impl Settings {
+    /// Loads and validates the settings. `DATABASE_URL` is honoured as well, since
+    /// sqlx's macros and CLI read it too.
+    pub fn load() -> Result<Settings> {
+        let file = env::var(format!("{}_CONFIG", ENV_PREFIX)).ok();
+        let source = match file {
+            Some(ref path) => File::with_name(path).required(true),
+            None => File::with_name(DEFAULT_FILE).required(false),
+        };
+
+        // the prefixed variable wins over the plain one
+        let database_url = env::var(format!("{}_DATABASE__URL", ENV_PREFIX))
+            .or_else(|_| env::var("DATABASE_URL"))
+            .ok();
+
+        let settings: Settings = Config::builder()
+            .add_source(source)
+            .add_source(
+                Environment::with_prefix(ENV_PREFIX)
+                    .prefix_separator("_")
+                    .separator("__")
+                    .try_parsing(true)
+                    .list_separator(",")
+                    .with_list_parse_key("cors.allowed_origins"),
+            )
+            .set_override_option("database.url", database_url)?
+            .build()?
+            .try_deserialize()
+            .map_err(|err| anyhow!("invalid settings: {}", err))?;
+
+        settings.validate()?;
+        Ok(settings)
+    }
+
+    /// Checks values serde can't, reporting every problem at once.
+    pub fn validate(&self) -> Result<()> {
+        let mut problems = Vec::new();
+
+        if self.server.port == 0 {
+            problems.push("server.port must not be 0".to_string());
+        }
+        if self.server.workers == Some(0) {
+            problems.push("server.workers must be at least 1".to_string());
+        }
+        if self.database.url.is_empty() {
+            problems.push("database.url (or DATABASE_URL) is required".to_string());
+        }
+        if self.database.max_connections == 0 {
+            problems.push("database.max_connections must be at least 1".to_string());
+        }
+        if self.database.min_connections > self.database.max_connections {
+            problems.push(format!(
+                "database.min_connections ({}) exceeds database.max_connections ({})",
+                self.database.min_connections, self.database.max_connections
+            ));
+        }
+        // argon2 rejects salts shorter than 8 bytes
+        if self.auth.salt.len() < 8 {
+            problems.push("auth.salt must be at least 8 characters".to_string());
+        }
+        for origin in &self.cors.allowed_origins {
+            if !origin.starts_with("http://") && !origin.starts_with("https://") {
+                problems.push(format!("cors.allowed_origins: {} is not an origin", origin));
+            }
+        }
+        if matches!(self.cookie.key, Some(ref key) if key.len() < 32) {
+            problems.push("cookie.key must be at least 32 characters".to_string());
+        }
+        if self.cookie.same_site == SameSitePolicy::None && !self.cookie.secure {
+            problems.push("cookie.same_site = \"none\" requires cookie.secure".to_string());
+        }
+        if self.images.upload_max_bytes == 0 {
+            problems.push("images.upload_max_bytes must be at least 1".to_string());
+        }
+        if self.images.render_signing_key.len() < 16 {
+            problems.push("images.render_signing_key must be at least 16 characters".to_string());
+        }
+        problems.extend(self.storage.validate());
+        problems.extend(self.search.validate());
+        if self.jobs.concurrency == 0 {
+            problems.push("jobs.concurrency must be at least 1".to_string());
+        }
+        if self.jobs.lease_secs == 0 {
+            problems.push("jobs.lease_secs must be at least 1".to_string());
+        }
+        if self.mail.enabled && (self.mail.smtp_host.is_empty() || self.mail.from.is_empty()) {
+            problems
+                .push("mail.smtp_host and mail.from are required when mail is enabled".to_string());
+        }
+
+        if self.metrics.enabled && self.metrics.bind.is_none() && self.metrics.token.is_none() {
+            problems.push("metrics needs metrics.bind or metrics.token when enabled".to_string());
+        }
+
+        if EnvFilter::try_new(&self.telemetry.filter).is_err() {
+            problems.push(format!(
+                "telemetry.filter is not a valid filter: {}",
+                self.telemetry.filter
+            ));
+        }
+
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            Err(anyhow!(
+                "invalid settings:\n  - {}",
+                problems.join("\n  - ")
+            ))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn valid() -> Settings {
+        let mut settings = Settings::default();
+        settings.database.url = "postgres://localhost/shop".to_string();
+        settings.auth.salt = "PEPPERPEPPER".to_string();
+        settings.images.render_signing_key = "0123456789abcdef".to_string();
+        settings
+    }
+
+    fn problems(settings: &Settings) -> String {
+        settings.validate().unwrap_err().to_string()
+    }
+
+    #[test]
+    fn accepts_the_defaults_with_the_required_values() {
+        assert!(valid().validate().is_ok());
+    }
+
+    #[test]
+    fn reports_missing_values() {
+        let problems = problems(&Settings::default());
+        assert!(problems.contains("database.url (or DATABASE_URL) is required"));
+        assert!(problems.contains("auth.salt must be at least 8 characters"));
+        assert!(problems.contains("images.render_signing_key must be at least 16 characters"));
+    }
+
+    #[test]
+    fn reports_values_that_are_too_short() {
+        let mut settings = valid();
+        settings.auth.salt = "pepper".to_string();
+        settings.cookie.key = Some("short".to_string());
+        settings.images.render_signing_key = "short".to_string();
+        settings.server.port = 0;
+        settings.jobs.concurrency = 0;
+
+        let problems = problems(&settings);
+        assert!(problems.contains("auth.salt must be at least 8 characters"));
+        assert!(problems.contains("cookie.key must be at least 32 characters"));
+        assert!(problems.contains("images.render_signing_key must be at least 16 characters"));
+        assert!(problems.contains("server.port must not be 0"));
+        assert!(problems.contains("jobs.concurrency must be at least 1"));
+    }
+
+    #[test]
+    fn reports_inconsistent_values() {
+        let mut settings = valid();
+        settings.database.min_connections = settings.database.max_connections + 1;
+        settings.cookie.same_site = SameSitePolicy::None;
+        settings.cookie.secure = false;
+        settings.mail.enabled = true;
+        settings.metrics.enabled = true;
+        settings.cors.allowed_origins = vec!["example.com".to_string()];
+
+        let problems = problems(&settings);
+        assert!(problems.contains("exceeds database.max_connections"));
+        assert!(problems.contains("cookie.same_site = \"none\" requires cookie.secure"));
+        assert!(problems.contains("mail.smtp_host and mail.from are required"));
+        assert!(problems.contains("metrics needs metrics.bind or metrics.token"));
+        assert!(problems.contains("cors.allowed_origins: example.com is not an origin"));
+    }
+}
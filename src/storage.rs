@@ -0,0 +1,339 @@
+use actix_web::web;
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use s3::{creds::Credentials, Bucket, Region};
+use serde::Deserialize;
+use std::{path::PathBuf, sync::Arc};
+
+/// Where uploaded files live. Keys are relative, `/` separated paths such as
+/// `products/12/5f0c….jpg`; `url` turns a key into something a browser can fetch.
+#[async_trait]
+pub trait BlobStore: Send + Sync {
+    async fn put(&self, key: &str, bytes: Vec<u8>, content_type: &str) -> Result<()>;
+    async fn get(&self, key: &str) -> Result<Vec<u8>>;
+    async fn delete(&self, key: &str) -> Result<()>;
+    fn url(&self, key: &str) -> String;
+    /// Checks that the store can be reached, for the readiness probe.
+    async fn check(&self) -> Result<()>;
+}
+
+/// Stores files below a directory on disk, by default inside `./static` so the
+/// `/static` `Files` service serves them.
+pub struct LocalStore {
+    root: PathBuf,
+    public_url: String,
+}
+
+impl LocalStore {
+    pub fn new(root: impl Into<PathBuf>, public_url: &str) -> LocalStore {
+        LocalStore {
+            root: root.into(),
+            public_url: public_url.trim_end_matches('/').to_string(),
+        }
+    }
+
+    fn path_for(&self, key: &str) -> Result<PathBuf> {
+        if key.is_empty() || key.starts_with('/') || key.split('/').any(|part| part == "..") {
+            return Err(anyhow!("invalid storage key: {}", key));
+        }
+        Ok(self.root.join(key))
+    }
+}
+
+#[async_trait]
+impl BlobStore for LocalStore {
+    async fn put(&self, key: &str, bytes: Vec<u8>, _content_type: &str) -> Result<()> {
+        let path = self.path_for(key)?;
+        web::block(move || -> std::io::Result<()> {
+            if let Some(dir) = path.parent() {
+                std::fs::create_dir_all(dir)?;
+            }
+            std::fs::write(path, bytes)
+        })
+        .await??;
+        Ok(())
+    }
+
+    async fn get(&self, key: &str) -> Result<Vec<u8>> {
+        let path = self.path_for(key)?;
+        Ok(web::block(move || std::fs::read(path)).await??)
+    }
+
+    async fn delete(&self, key: &str) -> Result<()> {
+        let path = self.path_for(key)?;
+        web::block(move || match std::fs::remove_file(path) {
+            Err(err) if err.kind() != std::io::ErrorKind::NotFound => Err(err),
+            _ => Ok(()),
+        })
+        .await??;
+        Ok(())
+    }
+
+    fn url(&self, key: &str) -> String {
+        format!("{}/{}", self.public_url, key)
+    }
+
+    async fn check(&self) -> Result<()> {
+        let root = self.root.clone();
+        let metadata = web::block(move || std::fs::metadata(root)).await??;
+        if !metadata.is_dir() {
+            return Err(anyhow!("{} is not a directory", self.root.display()));
+        }
+        Ok(())
+    }
+}
+
+/// Stores files in an S3 compatible bucket (AWS, MinIO, ...), addressed path style
+/// so custom endpoints work without wildcard DNS.
+pub struct S3Store {
+    bucket: Bucket,
+    public_url: String,
+}
+
+impl S3Store {
+    pub fn new(
+        bucket: &str,
+        endpoint: &str,
+        region: &str,
+        access_key: &str,
+        secret_key: &str,
+        public_url: Option<String>,
+    ) -> Result<S3Store> {
+        let region = Region::Custom {
+            region: region.to_string(),
+            endpoint: endpoint.trim_end_matches('/').to_string(),
+        };
+        let credentials = Credentials::new(Some(access_key), Some(secret_key), None, None, None)?;
+        let bucket = Bucket::new(bucket, region, credentials)?.with_path_style();
+        let public_url = public_url
+            .unwrap_or_else(|| format!("{}/{}", endpoint.trim_end_matches('/'), bucket.name()));
+
+        Ok(S3Store {
+            bucket,
+            public_url: public_url.trim_end_matches('/').to_string(),
+        })
+    }
+}
+
+#[async_trait]
+impl BlobStore for S3Store {
+    async fn put(&self, key: &str, bytes: Vec<u8>, content_type: &str) -> Result<()> {
+        let response = self
+            .bucket
+            .put_object_with_content_type(key, &bytes, content_type)
+            .await?;
+        match response.status_code() {
+            200..=299 => Ok(()),
+            code => Err(anyhow!("storing {} failed with status {}", key, code)),
+        }
+    }
+
+    async fn get(&self, key: &str) -> Result<Vec<u8>> {
+        let response = self.bucket.get_object(key).await?;
+        match response.status_code() {
+            200..=299 => Ok(response.bytes().to_vec()),
+            code => Err(anyhow!("reading {} failed with status {}", key, code)),
+        }
+    }
+
+    async fn delete(&self, key: &str) -> Result<()> {
+        let response = self.bucket.delete_object(key).await?;
+        match response.status_code() {
+            200..=299 | 404 => Ok(()),
+            code => Err(anyhow!("deleting {} failed with status {}", key, code)),
+        }
+    }
+
+    fn url(&self, key: &str) -> String {
+        format!("{}/{}", self.public_url, key)
+    }
+
+    async fn check(&self) -> Result<()> {
+        self.bucket.location().await?;
+        Ok(())
+    }
+}
+
+/// Storage settings, the `[storage]` section of the settings.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct StorageConfig {
+    /// `local` or `s3`
+    pub backend: String,
+    pub local_root: String,
+    /// base URL of stored files; for S3 it defaults to `<endpoint>/<bucket>`
+    pub public_url: Option<String>,
+    pub s3_bucket: Option<String>,
+    pub s3_endpoint: Option<String>,
+    pub s3_region: String,
+    pub s3_access_key: Option<String>,
+    pub s3_secret_key: Option<String>,
+}
+
+impl Default for StorageConfig {
+    fn default() -> StorageConfig {
+        StorageConfig {
+            backend: "local".to_string(),
+            local_root: "./static/uploads".to_string(),
+            public_url: None,
+            s3_bucket: None,
+            s3_endpoint: None,
+            s3_region: "us-east-1".to_string(),
+            s3_access_key: None,
+            s3_secret_key: None,
+        }
+    }
+}
+
+impl StorageConfig {
+    pub fn validate(&self) -> Vec<String> {
+        match self.backend.as_str() {
+            "local" => Vec::new(),
+            "s3" => [
+                ("s3_bucket", &self.s3_bucket),
+                ("s3_endpoint", &self.s3_endpoint),
+                ("s3_access_key", &self.s3_access_key),
+                ("s3_secret_key", &self.s3_secret_key),
+            ]
+            .iter()
+            .filter(|(_, value)| value.is_none())
+            .map(|(name, _)| format!("storage.{} is required for the s3 backend", name))
+            .collect(),
+            other => vec![format!(
+                "storage.backend must be local or s3, got {}",
+                other
+            )],
+        }
+    }
+}
+
+/// Builds the store selected by `storage.backend`.
+pub fn from_config(config: &StorageConfig) -> Result<Arc<dyn BlobStore>> {
+    match config.backend.as_str() {
+        "local" => Ok(Arc::new(LocalStore::new(
+            config.local_root.as_str(),
+            config.public_url.as_deref().unwrap_or("/static/uploads"),
+        ))),
+        "s3" => {
+            let required = |name: &str, value: &Option<String>| {
+                value
+                    .clone()
+                    .ok_or_else(|| anyhow!("storage.{} is required for the s3 backend", name))
+            };
+            Ok(Arc::new(S3Store::new(
+                &required("s3_bucket", &config.s3_bucket)?,
+                &required("s3_endpoint", &config.s3_endpoint)?,
+                &config.s3_region,
+                &required("s3_access_key", &config.s3_access_key)?,
+                &required("s3_secret_key", &config.s3_secret_key)?,
+                config.public_url.clone(),
+            )?))
+        }
+        other => Err(anyhow!("unknown storage backend: {}", other)),
+    }
+}
+
+/// Identifies an image by its leading bytes rather than trusting the client's
+/// content type. Returns the mime type and file extension.
+pub fn detect_image_type(bytes: &[u8]) -> Option<(&'static str, &'static str)> {
+    match bytes {
+        [0xFF, 0xD8, 0xFF, ..] => Some(("image/jpeg", "jpg")),
+        [0x89, b'P', b'N', b'G', 0x0D, 0x0A, 0x1A, 0x0A, ..] => Some(("image/png", "png")),
+        [b'G', b'I', b'F', b'8', b'7' | b'9', b'a', ..] => Some(("image/gif", "gif")),
+        [b'R', b'I', b'F', b'F', _, _, _, _, b'W', b'E', b'B', b'P', ..] => {
+            Some(("image/webp", "webp"))
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use uuid::Uuid;
+
+    async fn round_trip(store: &dyn BlobStore) {
+        let key = format!("tests/{}/image.jpg", Uuid::new_v4());
+        let bytes = vec![0xFF, 0xD8, 0xFF, 0x00, 0x01, 0x02];
+
+        store.put(&key, bytes.clone(), "image/jpeg").await.unwrap();
+        assert_eq!(store.get(&key).await.unwrap(), bytes);
+        assert!(store.url(&key).ends_with(&key));
+
+        store.delete(&key).await.unwrap();
+        assert!(store.get(&key).await.is_err());
+        // deleting what's gone isn't an error
+        store.delete(&key).await.unwrap();
+    }
+
+    #[actix_web::test]
+    async fn local_store_round_trip() {
+        let root = std::env::temp_dir().join(format!("shopapi-storage-{}", Uuid::new_v4()));
+        std::fs::create_dir_all(&root).unwrap();
+        let store = LocalStore::new(&root, "/static/uploads/");
+
+        store.check().await.unwrap();
+        round_trip(&store).await;
+
+        std::fs::remove_dir_all(root).unwrap();
+    }
+
+    /// Runs against the MinIO from docker-compose.yml unless `TEST_S3_*` say
+    /// otherwise; the bucket has to exist.
+    #[actix_web::test]
+    #[ignore = "needs an S3 compatible server, e.g. MinIO at TEST_S3_ENDPOINT"]
+    async fn s3_store_round_trip() {
+        let env =
+            |name: &str, default: &str| std::env::var(name).unwrap_or_else(|_| default.to_string());
+        let store = S3Store::new(
+            &env("TEST_S3_BUCKET", "shopapi-test"),
+            &env("TEST_S3_ENDPOINT", "http://localhost:9000"),
+            "us-east-1",
+            &env("TEST_S3_ACCESS_KEY", "minio"),
+            &env("TEST_S3_SECRET_KEY", "minio-secret"),
+            None,
+        )
+        .unwrap();
+
+        store.check().await.unwrap();
+        round_trip(&store).await;
+    }
+
+    #[test]
+    fn local_paths_stay_below_the_root() {
+        let store = LocalStore::new("/srv/uploads", "/static/uploads");
+
+        assert_eq!(
+            store.path_for("products/1/image.jpg").unwrap(),
+            PathBuf::from("/srv/uploads/products/1/image.jpg")
+        );
+        for key in [
+            "",
+            "..",
+            "../secrets",
+            "products/../../etc/passwd",
+            "/etc/passwd",
+        ] {
+            assert!(store.path_for(key).is_err(), "{} was accepted", key);
+        }
+    }
+
+    #[test]
+    fn detects_images_by_magic_bytes() {
+        assert_eq!(
+            detect_image_type(&[0xFF, 0xD8, 0xFF, 0xE0]),
+            Some(("image/jpeg", "jpg"))
+        );
+        assert_eq!(
+            detect_image_type(b"\x89PNG\r\n\x1a\n...."),
+            Some(("image/png", "png"))
+        );
+        assert_eq!(detect_image_type(b"GIF89a.."), Some(("image/gif", "gif")));
+        assert_eq!(
+            detect_image_type(b"RIFF\0\0\0\0WEBPVP8 "),
+            Some(("image/webp", "webp"))
+        );
+        assert_eq!(detect_image_type(b"<svg xmlns=\"\"></svg>"), None);
+        assert_eq!(detect_image_type(&[0xFF, 0xD8]), None);
+    }
+}
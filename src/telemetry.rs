@@ -0,0 +1,229 @@
+use crate::settings::{LogFormat, TelemetryConfig};
+use actix_web::{
+    body::MessageBody,
+    dev::{Service, ServiceRequest, ServiceResponse, Transform},
+    http::header::{HeaderMap, HeaderName, HeaderValue},
+    Error,
+};
+use anyhow::Result;
+use futures::future::{ready, LocalBoxFuture, Ready};
+use opentelemetry::{
+    global,
+    propagation::{Extractor, Injector},
+    sdk::{propagation::TraceContextPropagator, trace as sdktrace, Resource},
+    trace::{TraceContextExt, TracerProvider},
+    KeyValue,
+};
+use opentelemetry_otlp::WithExportConfig;
+use std::{rc::Rc, time::Instant};
+use tracing::{field, Instrument};
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+use tracing_subscriber::{fmt, layer::SubscriberExt, util::SubscriberInitExt, EnvFilter};
+use uuid::Uuid;
+
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+
+/// Installs the global subscriber: `RUST_LOG` (or `telemetry.filter`) filtering,
+/// text or JSON output, and OpenTelemetry spans exported over OTLP when
+/// `telemetry.otlp_endpoint` is set. `log` records from dependencies are
+/// forwarded too.
+pub fn init(config: &TelemetryConfig) -> Result<()> {
+    global::set_text_map_propagator(TraceContextPropagator::new());
+
+    let trace_config = sdktrace::config().with_resource(Resource::new(vec![KeyValue::new(
+        "service.name",
+        config.service_name.clone(),
+    )]));
+    let tracer = match config.otlp_endpoint {
+        Some(ref endpoint) => opentelemetry_otlp::new_pipeline()
+            .tracing()
+            .with_exporter(
+                opentelemetry_otlp::new_exporter()
+                    .tonic()
+                    .with_endpoint(endpoint),
+            )
+            .with_trace_config(trace_config)
+            .install_batch(opentelemetry::runtime::Tokio)?,
+        None => {
+            // without an exporter trace ids are still generated, for the logs and
+            // the `traceparent` response header
+            let provider = sdktrace::TracerProvider::builder()
+                .with_config(trace_config)
+                .build();
+            let tracer = provider.tracer("shopapi");
+            global::set_tracer_provider(provider);
+            tracer
+        }
+    };
+
+    let filter =
+        EnvFilter::try_from_default_env().or_else(|_| EnvFilter::try_new(&config.filter))?;
+    let (json, text) = match config.format {
+        LogFormat::Json => (
+            Some(
+                fmt::layer()
+                    .json()
+                    .with_current_span(false)
+                    .with_span_list(true),
+            ),
+            None,
+        ),
+        LogFormat::Text => (None, Some(fmt::layer())),
+    };
+
+    tracing_subscriber::registry()
+        .with(filter)
+        .with(tracing_opentelemetry::layer().with_tracer(tracer))
+        .with(json)
+        .with(text)
+        .try_init()?;
+
+    Ok(())
+}
+
+/// Flushes spans that haven't been exported yet.
+pub fn shutdown() {
+    global::shutdown_tracer_provider();
+}
+
+struct HeaderExtractor<'a>(&'a HeaderMap);
+
+impl Extractor for HeaderExtractor<'_> {
+    fn get(&self, key: &str) -> Option<&str> {
+        self.0.get(key).and_then(|value| value.to_str().ok())
+    }
+
+    fn keys(&self) -> Vec<&str> {
+        self.0.keys().map(HeaderName::as_str).collect()
+    }
+}
+
+struct HeaderInjector<'a>(&'a mut HeaderMap);
+
+impl Injector for HeaderInjector<'_> {
+    fn set(&mut self, key: &str, value: String) {
+        if let (Ok(name), Ok(value)) = (
+            HeaderName::from_bytes(key.as_bytes()),
+            HeaderValue::from_str(&value),
+        ) {
+            self.0.insert(name, value);
+        }
+    }
+}
+
+/// Accepts client supplied request ids only when they are short printable ASCII,
+/// so they can't forge log lines.
+fn valid_request_id(id: &str) -> bool {
+    !id.is_empty() && id.len() <= 128 && id.bytes().all(|byte| byte.is_ascii_graphic())
+}
+
+/// Middleware running each request in an `http_request` span. The request id is
+/// taken from `X-Request-Id` or generated, the trace continues a W3C `traceparent`
+/// if the client sent one, and both are echoed in the response headers. Logs one
+/// line per finished request, replacing the access log.
+pub struct RequestTracing;
+
+impl<S, B> Transform<S, ServiceRequest> for RequestTracing
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Transform = RequestTracingMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(RequestTracingMiddleware {
+            service: Rc::new(service),
+        }))
+    }
+}
+
+pub struct RequestTracingMiddleware<S> {
+    service: Rc<S>,
+}
+
+impl<S, B> Service<ServiceRequest> for RequestTracingMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    actix_web::dev::forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let started = Instant::now();
+        let method = req.method().to_string();
+        let request_id = req
+            .headers()
+            .get(REQUEST_ID_HEADER)
+            .and_then(|value| value.to_str().ok())
+            .filter(|id| valid_request_id(id))
+            .map(str::to_string)
+            .unwrap_or_else(|| Uuid::new_v4().to_string());
+
+        let span = tracing::info_span!(
+            "http_request",
+            otel.name = field::Empty,
+            otel.kind = "server",
+            otel.status_code = field::Empty,
+            http.method = %method,
+            http.target = %req.path(),
+            http.route = field::Empty,
+            http.status_code = field::Empty,
+            request_id = %request_id,
+            trace_id = field::Empty,
+        );
+        let parent = global::get_text_map_propagator(|propagator| {
+            propagator.extract(&HeaderExtractor(req.headers()))
+        });
+        span.set_parent(parent);
+        let trace_id = span.context().span().span_context().trace_id();
+        span.record("trace_id", &field::display(trace_id));
+
+        let service = self.service.clone();
+        Box::pin(async move {
+            let mut response = match service.call(req).instrument(span.clone()).await {
+                Ok(response) => response,
+                Err(err) => {
+                    span.record("otel.status_code", &"ERROR");
+                    tracing::error!(parent: &span, error = %err, "request failed");
+                    return Err(err);
+                }
+            };
+
+            let route = response
+                .request()
+                .match_pattern()
+                .unwrap_or_else(|| "unmatched".to_string());
+            let status = response.status();
+            span.record("http.route", &route.as_str());
+            span.record("otel.name", &format!("{} {}", method, route).as_str());
+            span.record("http.status_code", &status.as_u16());
+            if status.is_server_error() {
+                span.record("otel.status_code", &"ERROR");
+            }
+            tracing::info!(
+                parent: &span,
+                status = status.as_u16(),
+                latency_ms = started.elapsed().as_millis() as u64,
+                "request finished"
+            );
+
+            let headers = response.headers_mut();
+            if let Ok(value) = HeaderValue::from_str(&request_id) {
+                headers.insert(HeaderName::from_static(REQUEST_ID_HEADER), value);
+            }
+            global::get_text_map_propagator(|propagator| {
+                propagator.inject_context(&span.context(), &mut HeaderInjector(headers))
+            });
+
+            Ok(response)
+        })
+    }
+}
@@ -0,0 +1,67 @@
+// Setup shared by the tests. Tests needing Postgres are `#[ignore]`d; run them
+// with `TEST_DATABASE_URL` pointing at a disposable database and
+// `cargo test -- --include-ignored`.
+use crate::{models::schema::MIGRATOR, types::PostgresPool};
+use actix_session::{storage::CookieSessionStore, Session, SessionMiddleware};
+use actix_web::{
+    cookie::{Cookie, Key},
+    dev::ServiceResponse,
+    test, web, HttpResponse,
+};
+use sqlx::postgres::PgPoolOptions;
+
+/// A pool on the test database, migrated to the latest version.
+pub async fn pool() -> PostgresPool {
+    let url = std::env::var("TEST_DATABASE_URL")
+        .expect("TEST_DATABASE_URL is required for database tests");
+    let pool = PgPoolOptions::new()
+        .max_connections(5)
+        .connect(&url)
+        .await
+        .expect("Failed to connect to the test database");
+    MIGRATOR
+        .run(&pool)
+        .await
+        .expect("Failed to migrate the test database");
+    pool
+}
+
+/// A pool that never connects, for requests rejected before they reach the database.
+pub fn unused_pool() -> PostgresPool {
+    PgPoolOptions::new()
+        .connect_lazy("postgres://localhost/unused")
+        .expect("Failed to create the unused pool")
+}
+
+/// Cookie sessions like the server's, over plain HTTP.
+pub fn session() -> SessionMiddleware<CookieSessionStore> {
+    SessionMiddleware::builder(CookieSessionStore::default(), Key::from(&[7u8; 64]))
+        .cookie_secure(false)
+        .build()
+}
+
+async fn login(session: Session, user_id: web::Path<i64>) -> HttpResponse {
+    session
+        .insert("user_id", user_id.into_inner())
+        .expect("Failed to store the user id");
+    HttpResponse::Ok().finish()
+}
+
+/// Registers `POST /test/login/{user_id}`, logging a session in without a password.
+pub fn config(cfg: &mut web::ServiceConfig) {
+    cfg.route("/test/login/{user_id}", web::post().to(login));
+}
+
+pub fn login_request(user_id: i64) -> test::TestRequest {
+    test::TestRequest::post().uri(&format!("/test/login/{}", user_id))
+}
+
+/// The session cookie set by `response`.
+pub fn session_cookie<B>(response: &ServiceResponse<B>) -> Cookie<'static> {
+    response
+        .response()
+        .cookies()
+        .next()
+        .expect("no session cookie was set")
+        .into_owned()
+}
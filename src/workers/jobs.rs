@@ -0,0 +1,249 @@
+use crate::{
+    models::jobs::{JobRecord, NewJob},
+    search::SearchBackend,
+    settings::JobsConfig,
+    storage::BlobStore,
+    types::PostgresPool,
+    workers::{
+        search_index::ReindexProducts, supervisor::Supervisor, variants::GenerateVariants,
+        webhooks::DeliverWebhook,
+    },
+};
+use actix_web::rt;
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use chrono::NaiveDateTime;
+use futures::future::{join_all, FutureExt, LocalBoxFuture};
+use serde::{de::DeserializeOwned, Serialize};
+use serde_json::Value as JsonValue;
+use sqlx::{Postgres, Transaction};
+use std::{
+    collections::HashMap, future::Future, panic::AssertUnwindSafe, rc::Rc, sync::Arc,
+    time::Duration,
+};
+
+/// Work that runs outside of request handlers. The job itself is the payload,
+/// stored as JSON in the `jobs` table until a worker picks it up.
+#[async_trait(?Send)]
+pub trait Job: Serialize + DeserializeOwned + 'static {
+    /// Identifies the job type in the `jobs.kind` column; must not change once
+    /// jobs of this type were queued.
+    const KIND: &'static str;
+    const MAX_ATTEMPTS: i32 = 10;
+
+    /// While a job with this key is pending or running, queuing another one of
+    /// the same kind and key does nothing.
+    fn unique_key(&self) -> Option<String> {
+        None
+    }
+
+    async fn run(self, context: &JobContext) -> Result<()>;
+}
+
+/// What jobs get to do their work with.
+pub struct JobContext {
+    pub pool: PostgresPool,
+    pub store: Arc<dyn BlobStore>,
+    pub search: Arc<dyn SearchBackend>,
+}
+
+/// Queues `job` to run at `run_at`, or as soon as possible. Returns the job id,
+/// `None` if an equal unique job is already queued.
+pub async fn enqueue<J: Job>(
+    job: &J,
+    run_at: Option<NaiveDateTime>,
+    pool: &PostgresPool,
+) -> Result<Option<i64>> {
+    let mut tx = pool.begin().await?;
+    let id = enqueue_in(job, run_at, &mut tx).await?;
+    tx.commit().await?;
+
+    Ok(id)
+}
+
+/// Like `enqueue`, as part of the caller's transaction.
+pub async fn enqueue_in<J: Job>(
+    job: &J,
+    run_at: Option<NaiveDateTime>,
+    tx: &mut Transaction<'_, Postgres>,
+) -> Result<Option<i64>> {
+    let job = NewJob {
+        kind: J::KIND,
+        payload: serde_json::to_value(job)?,
+        unique_key: job.unique_key(),
+        max_attempts: J::MAX_ATTEMPTS,
+        run_at,
+    };
+
+    JobRecord::insert(job, tx).await
+}
+
+/// Delay before attempt `attempts + 1`: 5s, 10s, 20s, … capped at an hour.
+fn backoff(attempts: i32) -> Duration {
+    let exponent = attempts.clamp(1, 16) as u32 - 1;
+    Duration::from_secs(5 * 2u64.pow(exponent)).min(Duration::from_secs(3600))
+}
+
+type Handler = Box<dyn Fn(JsonValue, Rc<JobContext>) -> LocalBoxFuture<'static, Result<()>>>;
+
+/// Maps job kinds to the code running them.
+pub struct JobRegistry {
+    handlers: HashMap<&'static str, Handler>,
+}
+
+impl JobRegistry {
+    /// Every job type the workers know about.
+    pub fn new() -> JobRegistry {
+        JobRegistry {
+            handlers: HashMap::new(),
+        }
+        .register::<GenerateVariants>()
+        .register::<ReindexProducts>()
+        .register::<DeliverWebhook>()
+    }
+
+    fn register<J: Job>(mut self) -> JobRegistry {
+        let handler: Handler = Box::new(|payload, context| {
+            Box::pin(async move {
+                let job: J = serde_json::from_value(payload)?;
+                job.run(&context).await
+            })
+        });
+        self.handlers.insert(J::KIND, handler);
+        self
+    }
+
+    async fn run(&self, job: &JobRecord, context: Rc<JobContext>) -> Result<()> {
+        let handler = self
+            .handlers
+            .get(job.kind.as_str())
+            .ok_or_else(|| anyhow!("no handler for job kind {}", job.kind))?;
+        match AssertUnwindSafe(handler(job.payload.clone(), context))
+            .catch_unwind()
+            .await
+        {
+            Ok(result) => result,
+            Err(_) => Err(anyhow!("job panicked")),
+        }
+    }
+}
+
+impl Default for JobRegistry {
+    fn default() -> JobRegistry {
+        JobRegistry::new()
+    }
+}
+
+/// Supervises the worker claiming due jobs, up to `jobs.concurrency` at a time.
+/// On shutdown the claimed batch is finished; jobs cut off by the shutdown
+/// timeout are claimed again once their lease expires.
+pub fn start(supervisor: &mut Supervisor, config: JobsConfig, context: JobContext) {
+    let registry = Rc::new(JobRegistry::new());
+    let context = Rc::new(context);
+
+    supervisor.spawn("jobs", move |mut shutdown| {
+        let (config, registry, context) = (config.clone(), registry.clone(), context.clone());
+        async move {
+            let poll_interval = Duration::from_millis(config.poll_interval_ms);
+            // renewed well before it runs out, so a slow query doesn't cost the lease
+            let heartbeat_interval = Duration::from_secs((config.lease_secs / 3).max(1));
+            while !shutdown.is_triggered() {
+                let claimed = match JobRecord::claim(
+                    config.concurrency as i64,
+                    config.lease_secs as f64,
+                    &context.pool,
+                )
+                .await
+                {
+                    Ok(claimed) => claimed,
+                    Err(err) => {
+                        log::error!("claiming jobs failed: {}", err);
+                        Vec::new()
+                    }
+                };
+
+                if claimed.is_empty() {
+                    tokio::select! {
+                        _ = rt::time::sleep(poll_interval) => {}
+                        _ = shutdown.wait() => {}
+                    }
+                    continue;
+                }
+
+                join_all(
+                    claimed
+                        .iter()
+                        .map(|job| process(job, &registry, context.clone(), heartbeat_interval)),
+                )
+                .await;
+            }
+
+            Ok(())
+        }
+    });
+}
+
+/// Runs `future`, renewing job `id`'s lease every `interval` until it finishes.
+async fn with_heartbeat<T>(
+    id: i64,
+    interval: Duration,
+    pool: &PostgresPool,
+    future: impl Future<Output = T>,
+) -> T {
+    futures::pin_mut!(future);
+    let mut ticks = rt::time::interval_at(rt::time::Instant::now() + interval, interval);
+    loop {
+        tokio::select! {
+            output = &mut future => return output,
+            _ = ticks.tick() => {
+                if let Err(err) = JobRecord::heartbeat(id, pool).await {
+                    log::warn!("renewing the lease of job {} failed: {}", id, err);
+                }
+            }
+        }
+    }
+}
+
+async fn process(
+    job: &JobRecord,
+    registry: &JobRegistry,
+    context: Rc<JobContext>,
+    heartbeat_interval: Duration,
+) {
+    let pool = context.pool.clone();
+    let run = registry.run(job, context);
+    let recorded = match with_heartbeat(job.id, heartbeat_interval, &pool, run).await {
+        Ok(()) => JobRecord::complete(job.id, &pool).await,
+        Err(err) => {
+            let delay = backoff(job.attempts);
+            match JobRecord::fail(job.id, &err.to_string(), delay.as_secs_f64(), &pool).await {
+                Ok(status) if status == "dead" => {
+                    log::error!(
+                        "{} job {} failed for good after {} attempt(s): {}",
+                        job.kind,
+                        job.id,
+                        job.attempts,
+                        err
+                    );
+                    Ok(())
+                }
+                Ok(_) => {
+                    log::warn!(
+                        "{} job {} failed (attempt {}), retrying in {:?}: {}",
+                        job.kind,
+                        job.id,
+                        job.attempts,
+                        delay,
+                        err
+                    );
+                    Ok(())
+                }
+                Err(err) => Err(err),
+            }
+        }
+    };
+
+    if let Err(err) = recorded {
+        log::error!("recording the result of job {} failed: {}", job.id, err);
+    }
+}
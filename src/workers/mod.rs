@@ -0,0 +1,9 @@
+pub mod jobs;
+pub mod outbox;
+pub mod query_log;
+pub mod search_index;
+pub mod search_settings;
+pub mod supervisor;
+pub mod variants;
+pub mod webhooks;
+pub mod order_feed;
@@ -0,0 +1,229 @@
+use crate::{
+    events::{DomainEvent, Event, Subscriber},
+    types::PostgresPool,
+    workers::supervisor::Supervisor,
+};
+use anyhow::Result;
+use async_trait::async_trait;
+use serde::Deserialize;
+use serde_json::json;
+use sqlx::postgres::PgListener;
+use std::sync::Arc;
+use tokio::sync::{broadcast, watch};
+
+/// The Postgres channel order events are fanned out on.
+const CHANNEL: &str = "order_events";
+/// Events buffered per client; a client falling further behind is disconnected.
+const CAPACITY: usize = 256;
+
+/// An order event as sent to feed clients: `json` holds the event's `id`, `type`,
+/// `order_id`, `created_at` and `data`, the same body webhooks get plus the order.
+#[derive(Clone, Debug)]
+pub struct FeedEvent {
+    pub id: i64,
+    pub order_id: i32,
+    pub event_type: String,
+    pub json: String,
+}
+
+/// What a notification carries; the body is read from `order_events`.
+#[derive(Deserialize)]
+struct FeedNotification {
+    id: i64,
+    order_id: i32,
+}
+
+/// Hands the order events this instance receives to its SSE and WebSocket clients.
+pub struct OrderFeed {
+    sender: broadcast::Sender<FeedEvent>,
+    closed: watch::Sender<bool>,
+}
+
+impl Default for OrderFeed {
+    fn default() -> OrderFeed {
+        OrderFeed::new()
+    }
+}
+
+impl OrderFeed {
+    pub fn new() -> OrderFeed {
+        let (sender, _) = broadcast::channel(CAPACITY);
+        let (closed, _) = watch::channel(false);
+        OrderFeed { sender, closed }
+    }
+
+    /// Events of order `order_id` from now on, or of every order when `None`.
+    pub fn subscribe(&self, order_id: Option<i32>) -> FeedSubscription {
+        FeedSubscription {
+            receiver: self.sender.subscribe(),
+            closed: self.closed.subscribe(),
+            order_id,
+        }
+    }
+
+    fn publish(&self, event: FeedEvent) {
+        // fails only when nobody is listening
+        let _ = self.sender.send(event);
+    }
+
+    /// Ends every subscription, so that open streams don't hold up shutdown.
+    pub fn close(&self) {
+        let _ = self.closed.send(true);
+    }
+}
+
+pub struct FeedSubscription {
+    receiver: broadcast::Receiver<FeedEvent>,
+    closed: watch::Receiver<bool>,
+    order_id: Option<i32>,
+}
+
+impl FeedSubscription {
+    /// The next matching event, `None` once the feed closed or the client fell
+    /// too far behind; clients then reconnect and start over from the order's
+    /// current state.
+    pub async fn next(&mut self) -> Option<FeedEvent> {
+        loop {
+            tokio::select! {
+                received = self.receiver.recv() => match received {
+                    Ok(event) if self.order_id.is_none_or(|id| id == event.order_id) => {
+                        return Some(event)
+                    }
+                    Ok(_) => {}
+                    Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                        log::warn!("order feed client missed {} event(s), disconnecting", skipped);
+                        return None;
+                    }
+                    Err(broadcast::error::RecvError::Closed) => return None,
+                },
+                _ = wait_closed(&mut self.closed) => return None,
+            }
+        }
+    }
+}
+
+async fn wait_closed(closed: &mut watch::Receiver<bool>) {
+    while !*closed.borrow() {
+        if closed.changed().await.is_err() {
+            return;
+        }
+    }
+}
+
+/// Notifies every instance's listener of order and shipment events, keeping their
+/// bodies in `order_events` for the listeners to read.
+pub struct OrderNotifier {
+    pub pool: PostgresPool,
+}
+
+#[async_trait(?Send)]
+impl Subscriber for OrderNotifier {
+    fn name(&self) -> &'static str {
+        "order_feed"
+    }
+
+    async fn handle(&self, event: &Event) -> Result<()> {
+        let order_id = match event.data {
+            DomainEvent::OrderCreated { order_id }
+            | DomainEvent::OrderUpdated { order_id }
+            | DomainEvent::OrderDeleted { order_id }
+            | DomainEvent::OrderStatusChanged { order_id, .. }
+            | DomainEvent::ShipmentCreated { order_id, .. }
+            | DomainEvent::ShipmentUpdated { order_id, .. } => order_id,
+            _ => return Ok(()),
+        };
+        let body = json!({
+            "id": event.id,
+            "type": event.data.event_type(),
+            "order_id": order_id,
+            "created_at": event.created_at,
+            "data": event.data,
+        });
+
+        let mut tx = self.pool.begin().await?;
+        // a redelivered event keeps its row and is announced again
+        sqlx::query!(
+            r#"
+              INSERT INTO order_events (id, order_id, event_type, body) VALUES ($1, $2, $3, $4)
+              ON CONFLICT (id) DO NOTHING
+            "#,
+            event.id,
+            order_id,
+            event.data.event_type(),
+            body.to_string()
+        )
+        .execute(&mut tx)
+        .await?;
+        // listeners read an event right away, so an hour is plenty
+        sqlx::query!("DELETE FROM order_events WHERE created_at < NOW() - INTERVAL '1 hour'")
+            .execute(&mut tx)
+            .await?;
+        // the payload is limited to 8000 bytes, so only the ids go out; Postgres
+        // sends it on commit, when the row can be read
+        sqlx::query!(
+            "SELECT pg_notify($1, $2)",
+            CHANNEL,
+            json!({ "id": event.id, "order_id": order_id }).to_string()
+        )
+        .execute(&mut tx)
+        .await?;
+        tx.commit().await?;
+
+        Ok(())
+    }
+}
+
+/// Reads the event a notification points at, `None` once it was pruned.
+async fn load(id: i64, pool: &PostgresPool) -> Result<Option<FeedEvent>> {
+    let event = sqlx::query_as!(
+        FeedEvent,
+        r#"SELECT id, order_id, event_type, body AS "json" FROM order_events WHERE id = $1"#,
+        id
+    )
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(event)
+}
+
+/// Hands the event a notification points at to the feed's clients.
+async fn forward(payload: &str, pool: &PostgresPool, feed: &OrderFeed) {
+    let notification = match serde_json::from_str::<FeedNotification>(payload) {
+        Ok(notification) => notification,
+        Err(err) => {
+            log::warn!("ignoring malformed order event: {}", err);
+            return;
+        }
+    };
+    match load(notification.id, pool).await {
+        Ok(Some(event)) => feed.publish(event),
+        Ok(None) => log::warn!(
+            "order event {} of order {} is gone, skipping it",
+            notification.id,
+            notification.order_id
+        ),
+        Err(err) => log::error!("loading order event {} failed: {}", notification.id, err),
+    }
+}
+
+/// Supervises the task listening for order events and publishing them to `feed`.
+/// Notifications sent while its connection is down are lost; clients see the
+/// order's current state when they reconnect.
+pub fn start(supervisor: &mut Supervisor, pool: PostgresPool, feed: Arc<OrderFeed>) {
+    supervisor.spawn("order_feed", move |mut shutdown| {
+        let (pool, feed) = (pool.clone(), feed.clone());
+        async move {
+            let mut listener = PgListener::connect_with(&pool).await?;
+            listener.listen(CHANNEL).await?;
+
+            loop {
+                tokio::select! {
+                    notification = listener.recv() => {
+                        forward(notification?.payload(), &pool, &feed).await;
+                    }
+                    _ = shutdown.wait() => return Ok(()),
+                }
+            }
+        }
+    });
+}
@@ -0,0 +1,115 @@
+use crate::{
+    events::{Event, Subscriber},
+    models::outbox::{Outbox, OutboxEntry},
+    types::PostgresPool,
+    workers::supervisor::Supervisor,
+};
+use actix_web::rt;
+use anyhow::{anyhow, Result};
+use std::{rc::Rc, time::Duration};
+use tracing::Instrument;
+
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+const BATCH_SIZE: i64 = 100;
+/// How long claimed events stay hidden from other dispatchers; a batch taking
+/// longer may be delivered twice, which subscribers tolerate.
+const LEASE: Duration = Duration::from_secs(300);
+/// Attempts before an event is marked dead and stops holding back its aggregate.
+const MAX_ATTEMPTS: i32 = 10;
+
+/// Supervises the dispatcher handing committed domain events to `subscribers`,
+/// in order per aggregate. A batch in progress is finished on shutdown.
+pub fn start(
+    supervisor: &mut Supervisor,
+    pool: PostgresPool,
+    subscribers: Vec<Box<dyn Subscriber>>,
+) {
+    let subscribers = Rc::new(subscribers);
+
+    supervisor.spawn("outbox", move |mut shutdown| {
+        let (pool, subscribers) = (pool.clone(), subscribers.clone());
+        async move {
+            loop {
+                let count = match process(&pool, &subscribers).await {
+                    Ok(count) => count,
+                    Err(err) => {
+                        log::error!("dispatching domain events failed: {}", err);
+                        0
+                    }
+                };
+                if count > 0 {
+                    log::debug!("dispatched {} domain event(s)", count);
+                }
+
+                // a busy outbox is drained without pausing
+                if shutdown.is_triggered() {
+                    return Ok(());
+                }
+                if count == 0 {
+                    tokio::select! {
+                        _ = rt::time::sleep(POLL_INTERVAL) => {}
+                        _ = shutdown.wait() => return Ok(()),
+                    }
+                }
+            }
+        }
+    });
+}
+
+/// Delivers one batch of due events. Failed events stay in the outbox, holding
+/// back later events of their aggregate, and are retried with a backoff until
+/// they run out of attempts.
+async fn process(pool: &PostgresPool, subscribers: &[Box<dyn Subscriber>]) -> Result<usize> {
+    let entries = Outbox::claim(BATCH_SIZE, LEASE.as_secs_f64(), MAX_ATTEMPTS, pool).await?;
+    let count = entries.len();
+
+    for entry in entries {
+        match deliver(&entry, subscribers).await {
+            Ok(()) => Outbox::complete(entry.id, pool).await?,
+            Err(err) => {
+                match Outbox::retry(entry.id, &err.to_string(), MAX_ATTEMPTS, pool).await? {
+                    status if status == "dead" => log::error!(
+                        "delivering {} event {} failed for good after {} attempt(s): {}",
+                        entry.event_type,
+                        entry.id,
+                        entry.attempts,
+                        err
+                    ),
+                    _ => log::warn!(
+                        "delivering {} event {} failed (attempt {}): {}",
+                        entry.event_type,
+                        entry.id,
+                        entry.attempts,
+                        err
+                    ),
+                }
+            }
+        }
+    }
+
+    Ok(count)
+}
+
+async fn deliver(entry: &OutboxEntry, subscribers: &[Box<dyn Subscriber>]) -> Result<()> {
+    let event = Event {
+        id: entry.id,
+        data: serde_json::from_value(entry.payload.clone())?,
+        created_at: entry.created_at,
+    };
+
+    for subscriber in subscribers {
+        let span = tracing::info_span!(
+            "domain_event",
+            event_id = event.id,
+            event_type = %entry.event_type,
+            subscriber = subscriber.name(),
+        );
+        subscriber
+            .handle(&event)
+            .instrument(span)
+            .await
+            .map_err(|err| anyhow!("{}: {}", subscriber.name(), err))?;
+    }
+
+    Ok(())
+}
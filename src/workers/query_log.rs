@@ -0,0 +1,165 @@
+use crate::{
+    models::{search::SearchQueries, search_analytics::SearchEvent},
+    types::PostgresPool,
+    workers::supervisor::Supervisor,
+};
+use actix_web::rt;
+use std::{collections::HashMap, mem, sync::Arc, time::Duration};
+use tokio::sync::{mpsc, Mutex};
+
+const FLUSH_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Handle used by the search handler to log a search for analytics and
+/// "popular searches".
+#[derive(Clone)]
+pub struct QueryLog(mpsc::UnboundedSender<SearchEvent>);
+
+impl QueryLog {
+    pub fn record(&self, event: SearchEvent) {
+        if self.0.send(event).is_err() {
+            log::error!("query log worker is gone, search not recorded");
+        }
+    }
+}
+
+/// Supervises the worker that collects search events and writes them, and the
+/// query counts in `search_queries`, once per `FLUSH_INTERVAL` instead of on every
+/// search. Only first pages of searches that found something count as popular.
+/// Pending events are written before the worker stops.
+pub fn start(supervisor: &mut Supervisor, pool: PostgresPool) -> QueryLog {
+    let (sender, receiver) = mpsc::unbounded_channel::<SearchEvent>();
+
+    // kept outside the task so a restarted worker continues with the same channel
+    let receiver = Arc::new(Mutex::new(receiver));
+    supervisor.spawn("query_log", move |mut shutdown| {
+        let (pool, receiver) = (pool.clone(), receiver.clone());
+        async move {
+            let mut receiver = receiver.lock().await;
+            let mut events: Vec<SearchEvent> = Vec::new();
+            let mut interval = rt::time::interval(FLUSH_INTERVAL);
+            loop {
+                tokio::select! {
+                    event = receiver.recv() => match event {
+                        Some(event) => events.push(event),
+                        None => break,
+                    },
+                    _ = interval.tick() => {
+                        if events.is_empty() {
+                            continue;
+                        }
+                        flush(mem::take(&mut events), &pool).await;
+                    }
+                    _ = shutdown.wait() => {
+                        while let Ok(event) = receiver.try_recv() {
+                            events.push(event);
+                        }
+                        break;
+                    }
+                }
+            }
+
+            if !events.is_empty() {
+                flush(events, &pool).await;
+            }
+            Ok(())
+        }
+    });
+
+    QueryLog(sender)
+}
+
+async fn flush(events: Vec<SearchEvent>, pool: &PostgresPool) {
+    let events = collapse_prefixes(events);
+    let mut counts: HashMap<String, i64> = HashMap::new();
+    for event in &events {
+        let query = event.query.trim().to_lowercase();
+        if event.outcome == "ok" && event.page == 1 && event.result_count > 0 && !query.is_empty() {
+            *counts.entry(query).or_insert(0) += 1;
+        }
+    }
+
+    if let Err(err) = SearchEvent::insert_all(events, pool).await {
+        log::error!("recording search events failed: {}", err);
+    }
+    if !counts.is_empty() {
+        if let Err(err) = SearchQueries::record(counts.into_iter().collect(), pool).await {
+            log::error!("recording search queries failed: {}", err);
+        }
+    }
+}
+
+/// Drops the searches a search-as-you-type box fires while a word is typed: an
+/// event is left out when the same visitor's next search extends its query, so
+/// `t`, `te`, `tea` is written as `tea` only. Chains split by a flush stay apart.
+fn collapse_prefixes(events: Vec<SearchEvent>) -> Vec<SearchEvent> {
+    let normalized = |event: &SearchEvent| event.query.trim().to_lowercase();
+    let mut superseded = vec![false; events.len()];
+    let mut last_of_visitor: HashMap<&str, usize> = HashMap::new();
+    for (index, event) in events.iter().enumerate() {
+        let visitor = match event.session_id.as_deref() {
+            Some(visitor) => visitor,
+            None => continue,
+        };
+        if let Some(previous) = last_of_visitor.insert(visitor, index) {
+            let (before, after) = (normalized(&events[previous]), normalized(event));
+            superseded[previous] = after.len() > before.len() && after.starts_with(&before);
+        }
+    }
+
+    events
+        .into_iter()
+        .zip(superseded)
+        .filter_map(|(event, superseded)| (!superseded).then_some(event))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event(visitor: Option<&str>, query: &str) -> SearchEvent {
+        SearchEvent {
+            id: query.to_string(),
+            query: query.to_string(),
+            filters: Vec::new(),
+            sort: None,
+            page: 1,
+            result_count: 1,
+            latency_ms: 1,
+            session_id: visitor.map(str::to_string),
+            outcome: "ok".to_string(),
+        }
+    }
+
+    fn queries(events: Vec<SearchEvent>) -> Vec<String> {
+        collapse_prefixes(events)
+            .into_iter()
+            .map(|event| event.query)
+            .collect()
+    }
+
+    #[test]
+    fn keeps_the_last_query_of_a_typed_word() {
+        let events = vec![
+            event(Some("a"), "t"),
+            event(Some("b"), "g"),
+            event(Some("a"), "te"),
+            event(Some("b"), "ge"),
+            event(Some("a"), "Tea"),
+            event(Some("a"), "coffee"),
+        ];
+        assert_eq!(queries(events), vec!["ge", "Tea", "coffee"]);
+    }
+
+    #[test]
+    fn keeps_searches_that_do_not_extend_each_other() {
+        let events = vec![
+            event(Some("a"), "tea"),
+            event(Some("a"), "te"),
+            event(Some("a"), "te"),
+            event(None, "t"),
+            event(None, "te"),
+        ];
+        assert_eq!(queries(events), vec!["tea", "te", "te", "t", "te"]);
+    }
+}
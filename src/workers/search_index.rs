@@ -0,0 +1,91 @@
+use crate::{
+    events::{DomainEvent, Event, Subscriber},
+    models::search::ProductDocument,
+    search::SearchBackend,
+    types::PostgresPool,
+    workers::jobs::{Job, JobContext},
+};
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+/// Keeps the search index in step with product events. Only subscribed while
+/// search is enabled; changes made with search switched off need a reindex.
+pub struct SearchIndexer {
+    pub pool: PostgresPool,
+    pub backend: Arc<dyn SearchBackend>,
+}
+
+#[async_trait(?Send)]
+impl Subscriber for SearchIndexer {
+    fn name(&self) -> &'static str {
+        "search_index"
+    }
+
+    async fn handle(&self, event: &Event) -> Result<()> {
+        match event.data {
+            DomainEvent::ProductCreated { product_id }
+            | DomainEvent::ProductUpdated { product_id }
+            | DomainEvent::ProductDeleted { product_id }
+            // the search document carries the product's categories
+            | DomainEvent::ProductCategoriesChanged { product_id, .. } => {
+                index(product_id, &self.pool, self.backend.as_ref()).await
+            }
+            // and their slugs and names, including those of their ancestors
+            DomainEvent::CategoryUpdated {
+                ref product_ids, ..
+            }
+            | DomainEvent::CategoryMoved {
+                ref product_ids, ..
+            }
+            | DomainEvent::CategoryDeleted {
+                ref product_ids, ..
+            } => {
+                for &product_id in product_ids {
+                    index(product_id, &self.pool, self.backend.as_ref()).await?;
+                }
+                Ok(())
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Indexes every product again, e.g. after changing the index settings.
+#[derive(Serialize, Deserialize)]
+pub struct ReindexProducts {}
+
+#[async_trait(?Send)]
+impl Job for ReindexProducts {
+    const KIND: &'static str = "reindex_products";
+    const MAX_ATTEMPTS: i32 = 3;
+
+    fn unique_key(&self) -> Option<String> {
+        Some("all".to_string())
+    }
+
+    async fn run(self, context: &JobContext) -> Result<()> {
+        if !context.search.is_enabled() {
+            return Err(anyhow!("search is disabled"));
+        }
+
+        let ids = ProductDocument::find_ids(&context.pool).await?;
+        for &id in &ids {
+            index(id, &context.pool, context.search.as_ref()).await?;
+        }
+
+        log::info!("reindexed {} product(s)", ids.len());
+        Ok(())
+    }
+}
+
+/// Writes the product's current document, or removes it once the product is gone.
+async fn index(product_id: i32, pool: &PostgresPool, backend: &dyn SearchBackend) -> Result<()> {
+    match ProductDocument::find_by_id(product_id, pool).await? {
+        Some(document) => backend.index_product(document).await?,
+        None => backend.remove_product(product_id).await?,
+    }
+
+    Ok(())
+}
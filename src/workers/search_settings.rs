@@ -0,0 +1,166 @@
+use crate::{
+    models::search_settings::{SearchSettings, CHANNEL},
+    search::SearchBackend,
+    types::PostgresPool,
+    workers::supervisor::Supervisor,
+};
+use sqlx::postgres::PgListener;
+use std::sync::{Arc, RwLock};
+
+/// Makes `settings` the active version for this process's request handlers and its
+/// search backend, unless the same or a newer version already is. Returns whether
+/// it was activated.
+pub async fn activate(
+    settings: SearchSettings,
+    current: &RwLock<Arc<SearchSettings>>,
+    backend: &dyn SearchBackend,
+) -> bool {
+    let settings = Arc::new(settings);
+    {
+        let mut current = current.write().unwrap_or_else(|e| e.into_inner());
+        if current.version >= settings.version {
+            return false;
+        }
+        *current = settings.clone();
+    }
+
+    if let Err(err) = backend.apply_settings(&settings).await {
+        log::error!(
+            "could not apply search settings version {} to {}: {}",
+            settings.version,
+            backend.name(),
+            err
+        );
+    }
+    true
+}
+
+/// Activates a version announced on `CHANNEL`, which carries only its number.
+async fn forward(
+    payload: &str,
+    pool: &PostgresPool,
+    current: &RwLock<Arc<SearchSettings>>,
+    backend: &dyn SearchBackend,
+) {
+    let version = match payload.parse::<i32>() {
+        Ok(version) => version,
+        Err(err) => {
+            log::warn!("ignoring malformed search settings version: {}", err);
+            return;
+        }
+    };
+    if current.read().unwrap_or_else(|e| e.into_inner()).version >= version {
+        return;
+    }
+    match SearchSettings::find_by_version(version, pool).await {
+        Ok(settings) => {
+            activate(settings, current, backend).await;
+        }
+        Err(err) => log::error!(
+            "loading search settings version {} failed: {}",
+            version,
+            err
+        ),
+    }
+}
+
+/// Supervises the task that keeps `current` and `backend` on the newest settings
+/// version when another process, e.g. another server instance, activated it. The
+/// newest version is read again whenever the listener (re)connects, so versions
+/// announced while it was down are not missed.
+pub fn start(
+    supervisor: &mut Supervisor,
+    pool: PostgresPool,
+    current: Arc<RwLock<Arc<SearchSettings>>>,
+    backend: Arc<dyn SearchBackend>,
+) {
+    supervisor.spawn("search_settings", move |mut shutdown| {
+        let (pool, current, backend) = (pool.clone(), current.clone(), backend.clone());
+        async move {
+            let mut listener = PgListener::connect_with(&pool).await?;
+            listener.listen(CHANNEL).await?;
+            activate(
+                SearchSettings::current(&pool).await?,
+                &current,
+                backend.as_ref(),
+            )
+            .await;
+
+            loop {
+                tokio::select! {
+                    notification = listener.recv() => {
+                        forward(notification?.payload(), &pool, &current, backend.as_ref()).await;
+                    }
+                    _ = shutdown.wait() => return Ok(()),
+                }
+            }
+        }
+    });
+}
+
+/// Settings of a process that has not loaded any version yet.
+pub fn initial() -> Arc<RwLock<Arc<SearchSettings>>> {
+    Arc::new(RwLock::new(Arc::new(SearchSettings::default())))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        models::search_settings::SearchSettingsInput, search::PostgresSearch, test_support,
+    };
+    use actix_web::rt;
+    use std::time::Duration;
+
+    fn version(version: i32) -> SearchSettings {
+        SearchSettings {
+            version,
+            ..Default::default()
+        }
+    }
+
+    #[actix_web::test]
+    async fn only_activates_newer_versions() {
+        let backend = PostgresSearch::new(test_support::unused_pool());
+        let current = initial();
+
+        assert!(activate(version(2), &current, &backend).await);
+        assert!(!activate(version(1), &current, &backend).await);
+        assert!(!activate(version(2), &current, &backend).await);
+        assert_eq!(current.read().unwrap().version, 2);
+
+        assert!(activate(version(3), &current, &backend).await);
+        assert_eq!(current.read().unwrap().version, 3);
+    }
+
+    #[actix_web::test]
+    #[ignore = "needs Postgres at TEST_DATABASE_URL"]
+    async fn picks_up_versions_created_elsewhere() {
+        let pool = test_support::pool().await;
+        let backend: Arc<dyn SearchBackend> =
+            Arc::new(PostgresSearch::new(test_support::unused_pool()));
+        let current = initial();
+        let mut supervisor = Supervisor::new();
+        start(&mut supervisor, pool.clone(), current.clone(), backend);
+
+        // give the listener time to connect
+        rt::time::sleep(Duration::from_millis(500)).await;
+        let input = SearchSettingsInput {
+            synonyms: Vec::new(),
+            stop_words: vec!["listened".to_string()],
+            rules: Vec::new(),
+        };
+        let created = SearchSettings::create(input, None, &pool).await.unwrap();
+
+        let mut activated = false;
+        for _ in 0..50 {
+            if current.read().unwrap().version == created.version {
+                activated = true;
+                break;
+            }
+            rt::time::sleep(Duration::from_millis(100)).await;
+        }
+        supervisor.shutdown(Duration::from_secs(1)).await;
+        assert!(activated);
+    }
+}
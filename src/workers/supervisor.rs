@@ -0,0 +1,114 @@
+use actix_web::rt;
+use anyhow::Result;
+use futures::future::join_all;
+use std::{
+    future::Future,
+    time::{Duration, Instant},
+};
+use tokio::{sync::watch, task::JoinHandle};
+
+const INITIAL_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+/// A task that ran at least this long before failing restarts without delay
+/// escalation, as if it had never failed.
+const HEALTHY_RUN: Duration = Duration::from_secs(60);
+
+/// Tells a supervised task that the process is stopping. Tasks should finish
+/// (or persist) their current piece of work and return `Ok(())`.
+#[derive(Clone)]
+pub struct Shutdown(watch::Receiver<bool>);
+
+impl Shutdown {
+    pub fn is_triggered(&self) -> bool {
+        *self.0.borrow()
+    }
+
+    /// Resolves once shutdown has been requested.
+    pub async fn wait(&mut self) {
+        while !*self.0.borrow() {
+            if self.0.changed().await.is_err() {
+                return;
+            }
+        }
+    }
+}
+
+/// Runs the background workers. A task that returns an error or panics is
+/// started again after an exponential backoff; one that returns `Ok(())` is
+/// considered done.
+pub struct Supervisor {
+    shutdown: watch::Sender<bool>,
+    tasks: Vec<(&'static str, JoinHandle<()>)>,
+}
+
+impl Default for Supervisor {
+    fn default() -> Supervisor {
+        Supervisor::new()
+    }
+}
+
+impl Supervisor {
+    pub fn new() -> Supervisor {
+        let (shutdown, _) = watch::channel(false);
+        Supervisor {
+            shutdown,
+            tasks: Vec::new(),
+        }
+    }
+
+    /// Supervises the task built by `task`, which is called again for every restart.
+    pub fn spawn<F, Fut>(&mut self, name: &'static str, task: F)
+    where
+        F: Fn(Shutdown) -> Fut + 'static,
+        Fut: Future<Output = Result<()>> + 'static,
+    {
+        let shutdown = Shutdown(self.shutdown.subscribe());
+        let handle = rt::spawn(async move {
+            let mut backoff = INITIAL_BACKOFF;
+            loop {
+                let started = Instant::now();
+                let failure = match rt::spawn(task(shutdown.clone())).await {
+                    Ok(Ok(())) => return,
+                    Ok(Err(err)) => err.to_string(),
+                    Err(err) => format!("panicked: {}", err),
+                };
+                if shutdown.is_triggered() {
+                    log::warn!("{} worker failed while stopping: {}", name, failure);
+                    return;
+                }
+
+                if started.elapsed() >= HEALTHY_RUN {
+                    backoff = INITIAL_BACKOFF;
+                }
+                log::error!(
+                    "{} worker failed, restarting in {:?}: {}",
+                    name,
+                    backoff,
+                    failure
+                );
+                let mut stopping = shutdown.clone();
+                tokio::select! {
+                    _ = rt::time::sleep(backoff) => {}
+                    _ = stopping.wait() => return,
+                }
+                backoff = (backoff * 2).min(MAX_BACKOFF);
+            }
+        });
+        self.tasks.push((name, handle));
+    }
+
+    /// Asks every task to stop and waits up to `timeout` for them to return.
+    /// Tasks still running afterwards are dropped with the runtime.
+    pub async fn shutdown(self, timeout: Duration) {
+        let _ = self.shutdown.send(true);
+
+        let (names, handles): (Vec<_>, Vec<_>) = self.tasks.into_iter().unzip();
+        if rt::time::timeout(timeout, join_all(handles)).await.is_err() {
+            log::warn!(
+                "background workers ({}) did not stop within {:?}",
+                names.join(", "),
+                timeout
+            );
+        }
+    }
+}
@@ -0,0 +1,83 @@
+use crate::{
+    imaging,
+    models::images::{Image, ImageInput, ImageVariant},
+    storage::BlobStore,
+    types::PostgresPool,
+    workers::jobs::{self, Job, JobContext},
+};
+use actix_web::web;
+use anyhow::Result;
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+
+/// Builds the thumbnail/medium/large/WebP derivatives of an uploaded image.
+#[derive(Serialize, Deserialize)]
+pub struct GenerateVariants {
+    pub image_id: i32,
+}
+
+#[async_trait(?Send)]
+impl Job for GenerateVariants {
+    const KIND: &'static str = "generate_variants";
+    const MAX_ATTEMPTS: i32 = 5;
+
+    fn unique_key(&self) -> Option<String> {
+        Some(self.image_id.to_string())
+    }
+
+    async fn run(self, context: &JobContext) -> Result<()> {
+        generate(self.image_id, &context.pool, context.store.as_ref()).await
+    }
+}
+
+/// Records an uploaded image and queues its variants in one transaction, so no
+/// image is left without them.
+pub async fn create_image(input: ImageInput, pool: &PostgresPool) -> Result<Image> {
+    let mut tx = pool.begin().await?;
+    let image = Image::insert(input, &mut tx).await?;
+    jobs::enqueue_in(&GenerateVariants { image_id: image.id }, None, &mut tx).await?;
+    tx.commit().await?;
+
+    Ok(image)
+}
+
+async fn generate(image_id: i32, pool: &PostgresPool, store: &dyn BlobStore) -> Result<()> {
+    let image = match Image::find_by_id(image_id, pool).await {
+        Ok(image) => image,
+        Err(err) => match err.downcast::<sqlx::Error>() {
+            // deleted before a worker got to it
+            Ok(sqlx::Error::RowNotFound) => return Ok(()),
+            Ok(err) => return Err(err.into()),
+            Err(err) => return Err(err),
+        },
+    };
+    let (key, content_type) = match (image.storage_key, image.content_type) {
+        (Some(key), Some(content_type)) => (key, content_type),
+        _ => return Ok(()),
+    };
+
+    let original = store.get(&key).await?;
+    let rendered = web::block(move || imaging::render_variants(&original, &content_type)).await??;
+
+    let stem = key.rsplit_once('.').map(|(stem, _)| stem).unwrap_or(&key);
+    for variant in rendered {
+        let variant_key = format!("{}-{}.{}", stem, variant.name, variant.extension);
+        store
+            .put(&variant_key, variant.bytes, variant.content_type)
+            .await?;
+
+        let variant = ImageVariant {
+            name: variant.name.to_string(),
+            format: variant.format.to_string(),
+            width: variant.width as i32,
+            height: variant.height as i32,
+            path: store.url(&variant_key),
+            storage_key: variant_key,
+            content_type: variant.content_type.to_string(),
+        };
+        ImageVariant::upsert(image_id, variant, pool).await?;
+    }
+
+    log::info!("generated variants for image {}", image_id);
+    Ok(())
+}
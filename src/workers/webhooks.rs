@@ -0,0 +1,389 @@
+use crate::{
+    events::{Event, Subscriber},
+    models::webhooks::{AttemptOutcome, WebhookDelivery, WebhookSubscription},
+    signing,
+    types::PostgresPool,
+    workers::jobs::{self, Job, JobContext},
+};
+use actix_web::web;
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::{
+    io::Read,
+    time::{Duration, Instant},
+};
+
+const TIMEOUT: Duration = Duration::from_secs(10);
+/// Bytes of the response body kept in the delivery log.
+const LOGGED_BODY_BYTES: u64 = 1024;
+
+const EVENT_HEADER: &str = "X-Shopapi-Event";
+const DELIVERY_HEADER: &str = "X-Shopapi-Delivery";
+const TIMESTAMP_HEADER: &str = "X-Shopapi-Timestamp";
+const SIGNATURE_HEADER: &str = "X-Shopapi-Signature";
+
+/// Records a delivery for every active subscription interested in the event and
+/// queues a job sending it. Events that were dispatched before are skipped.
+pub struct WebhookDispatcher {
+    pub pool: PostgresPool,
+}
+
+#[async_trait(?Send)]
+impl Subscriber for WebhookDispatcher {
+    fn name(&self) -> &'static str {
+        "webhooks"
+    }
+
+    async fn handle(&self, event: &Event) -> Result<()> {
+        let event_type = event.data.event_type();
+        let body = json!({
+            "id": event.id,
+            "type": event_type,
+            "created_at": event.created_at,
+            "data": event.data,
+        });
+
+        let mut tx = self.pool.begin().await?;
+        for subscription in WebhookSubscription::find_for_event(event_type, &mut tx).await? {
+            let created =
+                WebhookDelivery::create(subscription.id, event.id, event_type, &body, &mut tx)
+                    .await?;
+            if let Some(delivery_id) = created {
+                jobs::enqueue_in(&DeliverWebhook { delivery_id }, None, &mut tx).await?;
+            }
+        }
+        tx.commit().await?;
+
+        Ok(())
+    }
+}
+
+/// Posts a delivery's body to its subscription. The request carries the event
+/// type, the delivery id, a unix timestamp and `sha256=<hex>`, the HMAC-SHA256 of
+/// `<timestamp>.<body>` keyed with the subscription's secret. Anything but a 2xx
+/// response is retried with the job backoff.
+#[derive(Serialize, Deserialize)]
+pub struct DeliverWebhook {
+    pub delivery_id: i64,
+}
+
+#[async_trait(?Send)]
+impl Job for DeliverWebhook {
+    const KIND: &'static str = "deliver_webhook";
+    const MAX_ATTEMPTS: i32 = 8;
+
+    fn unique_key(&self) -> Option<String> {
+        Some(self.delivery_id.to_string())
+    }
+
+    async fn run(self, context: &JobContext) -> Result<()> {
+        let delivery = match WebhookDelivery::find_by_id(self.delivery_id, &context.pool).await {
+            Ok(delivery) => delivery,
+            Err(err) => match err.downcast::<sqlx::Error>() {
+                // removed together with its subscription
+                Ok(sqlx::Error::RowNotFound) => return Ok(()),
+                Ok(err) => return Err(err.into()),
+                Err(err) => return Err(err),
+            },
+        };
+        let subscription =
+            WebhookSubscription::find_by_id(delivery.subscription_id, &context.pool).await?;
+        if !subscription.active {
+            log::info!(
+                "webhook {} is inactive, delivery {} not sent",
+                subscription.id,
+                delivery.id
+            );
+            return Ok(());
+        }
+
+        let body = delivery.body.to_string();
+        let timestamp = Utc::now().timestamp().to_string();
+        let signature = signing::sign(
+            subscription.secret.as_bytes(),
+            &format!("{}.{}", timestamp, body),
+        );
+        let request = ureq::AgentBuilder::new()
+            .timeout(TIMEOUT)
+            .build()
+            .post(&subscription.url)
+            .set("Content-Type", "application/json")
+            .set(EVENT_HEADER, &delivery.event_type)
+            .set(DELIVERY_HEADER, &delivery.id.to_string())
+            .set(TIMESTAMP_HEADER, &timestamp)
+            .set(SIGNATURE_HEADER, &format!("sha256={}", signature));
+
+        let outcome = web::block(move || send(request, &body)).await?;
+        let succeeded = matches!(outcome.response_status, Some(200..=299));
+        let failure = match (outcome.response_status, &outcome.error) {
+            (_, Some(error)) => error.clone(),
+            (status, None) => format!("webhook responded with {}", status.unwrap_or_default()),
+        };
+        WebhookDelivery::record_attempt(delivery.id, succeeded, outcome, &context.pool).await?;
+
+        if succeeded {
+            Ok(())
+        } else {
+            Err(anyhow!(failure))
+        }
+    }
+}
+
+/// Sends the request; runs on the blocking thread pool.
+fn send(request: ureq::Request, body: &str) -> AttemptOutcome {
+    let started = Instant::now();
+    let response = match request.send_string(body) {
+        Ok(response) | Err(ureq::Error::Status(_, response)) => Ok(response),
+        Err(err) => Err(err),
+    };
+    let duration_ms = started.elapsed().as_millis() as i32;
+
+    match response {
+        Ok(response) => {
+            let status = response.status() as i32;
+            let mut logged = Vec::new();
+            let response_body = match response
+                .into_reader()
+                .take(LOGGED_BODY_BYTES)
+                .read_to_end(&mut logged)
+            {
+                Ok(_) => Some(String::from_utf8_lossy(&logged).into_owned()),
+                Err(_) => None,
+            };
+            AttemptOutcome {
+                response_status: Some(status),
+                response_body,
+                error: None,
+                duration_ms,
+            }
+        }
+        Err(err) => AttemptOutcome {
+            response_status: None,
+            response_body: None,
+            error: Some(err.to_string()),
+            duration_ms,
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        events::DomainEvent,
+        models::webhooks::{DeliveryFilter, WebhookAttempt, WebhookSubscriptionInput},
+        search::postgres::PostgresSearch,
+        storage::LocalStore,
+        test_support,
+    };
+    use std::{
+        collections::HashMap,
+        io::{BufRead, BufReader, Write},
+        net::TcpListener,
+        sync::{Arc, Mutex},
+        thread,
+    };
+
+    const SECRET: &str = "test-webhook-secret";
+
+    struct Received {
+        headers: HashMap<String, String>,
+        body: String,
+    }
+
+    /// A local HTTP receiver answering requests with `statuses` in turn. Returns
+    /// its URL and the requests it got.
+    fn receiver(statuses: Vec<u16>) -> (String, Arc<Mutex<Vec<Received>>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/hooks", listener.local_addr().unwrap());
+        let received = Arc::new(Mutex::new(Vec::new()));
+
+        let log = received.clone();
+        thread::spawn(move || {
+            for (stream, status) in listener.incoming().zip(statuses) {
+                let mut stream = stream.unwrap();
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+
+                let mut headers = HashMap::new();
+                loop {
+                    line.clear();
+                    reader.read_line(&mut line).unwrap();
+                    let header = line.trim_end();
+                    if header.is_empty() {
+                        break;
+                    }
+                    if let Some((name, value)) = header.split_once(':') {
+                        headers.insert(name.trim().to_ascii_lowercase(), value.trim().to_string());
+                    }
+                }
+                let length = headers
+                    .get("content-length")
+                    .map_or(0, |length| length.parse().unwrap());
+                let mut body = vec![0; length];
+                reader.read_exact(&mut body).unwrap();
+
+                log.lock().unwrap().push(Received {
+                    headers,
+                    body: String::from_utf8(body).unwrap(),
+                });
+                write!(
+                    stream,
+                    "HTTP/1.1 {} Test\r\nContent-Length: 2\r\nConnection: close\r\n\r\nok",
+                    status
+                )
+                .unwrap();
+            }
+        });
+
+        (url, received)
+    }
+
+    fn context(pool: PostgresPool) -> JobContext {
+        let root = std::env::temp_dir().join("shopapi-webhook-tests");
+        JobContext {
+            store: Arc::new(LocalStore::new(root, "/static/uploads")),
+            search: Arc::new(PostgresSearch::new(pool.clone())),
+            pool,
+        }
+    }
+
+    async fn subscribe(url: String, pool: &PostgresPool) -> WebhookSubscription {
+        let input = WebhookSubscriptionInput {
+            url,
+            event_types: vec!["order.created".to_string()],
+            secret: Some(SECRET.to_string()),
+            active: None,
+            description: Some("test receiver".to_string()),
+        };
+        WebhookSubscription::create(input, pool).await.unwrap()
+    }
+
+    #[actix_web::test]
+    #[ignore = "needs Postgres at TEST_DATABASE_URL"]
+    async fn delivers_signed_requests_and_retries_failures() {
+        let pool = test_support::pool().await;
+        let (url, received) = receiver(vec![500, 200]);
+        let subscription = subscribe(url, &pool).await;
+
+        let body = json!({
+            "id": 1,
+            "type": "order.created",
+            "created_at": "2022-10-20T12:00:00",
+            "data": { "type": "order.created", "order_id": 1 },
+        });
+        let mut tx = pool.begin().await.unwrap();
+        let delivery_id =
+            WebhookDelivery::create(subscription.id, 1, "order.created", &body, &mut tx)
+                .await
+                .unwrap()
+                .unwrap();
+        tx.commit().await.unwrap();
+        let context = context(pool.clone());
+
+        // a 500 fails the job, which the queue runs again after its backoff
+        assert!(DeliverWebhook { delivery_id }.run(&context).await.is_err());
+        let delivery = WebhookDelivery::find_by_id(delivery_id, &pool)
+            .await
+            .unwrap();
+        assert_eq!(delivery.status, "failed");
+        assert_eq!(delivery.attempts, 1);
+        assert_eq!(delivery.response_status, Some(500));
+        assert!(delivery.delivered_at.is_none());
+
+        DeliverWebhook { delivery_id }.run(&context).await.unwrap();
+        let delivery = WebhookDelivery::find_by_id(delivery_id, &pool)
+            .await
+            .unwrap();
+        assert_eq!(delivery.status, "succeeded");
+        assert_eq!(delivery.attempts, 2);
+        assert_eq!(delivery.response_status, Some(200));
+        assert!(delivery.delivered_at.is_some());
+
+        let attempts = WebhookAttempt::find_by_delivery(delivery_id, &pool)
+            .await
+            .unwrap();
+        let statuses: Vec<_> = attempts
+            .iter()
+            .map(|attempt| attempt.response_status)
+            .collect();
+        assert_eq!(statuses, vec![Some(500), Some(200)]);
+        assert_eq!(attempts[0].response_body.as_deref(), Some("ok"));
+        assert!(attempts.iter().all(|attempt| attempt.error.is_none()));
+
+        {
+            let received = received.lock().unwrap();
+            assert_eq!(received.len(), 2);
+            for request in received.iter() {
+                assert_eq!(request.headers["x-shopapi-event"], "order.created");
+                assert_eq!(
+                    request.headers["x-shopapi-delivery"],
+                    delivery_id.to_string()
+                );
+                let timestamp: i64 = request.headers["x-shopapi-timestamp"].parse().unwrap();
+                assert!((Utc::now().timestamp() - timestamp).abs() < 60);
+
+                let signature = request.headers["x-shopapi-signature"]
+                    .strip_prefix("sha256=")
+                    .unwrap();
+                let signed = format!("{}.{}", timestamp, request.body);
+                assert!(signing::verify(SECRET.as_bytes(), &signed, signature));
+                assert_eq!(
+                    serde_json::from_str::<serde_json::Value>(&request.body).unwrap(),
+                    body
+                );
+            }
+        }
+
+        WebhookSubscription::delete(subscription.id, &pool)
+            .await
+            .unwrap();
+    }
+
+    #[actix_web::test]
+    #[ignore = "needs Postgres at TEST_DATABASE_URL"]
+    async fn dispatches_each_event_once() {
+        let pool = test_support::pool().await;
+        let subscription = subscribe("http://127.0.0.1:9/hooks".to_string(), &pool).await;
+        let dispatcher = WebhookDispatcher { pool: pool.clone() };
+        let event = Event {
+            id: Utc::now().timestamp_nanos(),
+            data: DomainEvent::OrderCreated { order_id: 7 },
+            created_at: Utc::now().naive_utc(),
+        };
+
+        // at least once delivery from the outbox may hand the event over twice
+        dispatcher.handle(&event).await.unwrap();
+        dispatcher.handle(&event).await.unwrap();
+
+        let filter = DeliveryFilter {
+            status: None,
+            limit: None,
+            offset: None,
+        };
+        let deliveries = WebhookDelivery::find_by_subscription(subscription.id, &filter, &pool)
+            .await
+            .unwrap();
+        assert_eq!(deliveries.len(), 1);
+        assert_eq!(deliveries[0].event_id, event.id);
+        assert_eq!(deliveries[0].status, "pending");
+        assert_eq!(deliveries[0].body["data"]["order_id"], 7);
+
+        let queued: i64 = sqlx::query_scalar(
+            "SELECT COUNT(*) FROM jobs WHERE kind = 'deliver_webhook' AND unique_key = $1",
+        )
+        .bind(deliveries[0].id.to_string())
+        .fetch_one(&pool)
+        .await
+        .unwrap();
+        assert_eq!(queued, 1);
+
+        WebhookSubscription::delete(subscription.id, &pool)
+            .await
+            .unwrap();
+    }
+}